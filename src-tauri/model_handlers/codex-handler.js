@@ -9,6 +9,8 @@ import { execSync } from 'child_process'
 loadEnvFiles()
 process.env.NO_COLOR = process.env.NO_COLOR || '1'
 
+const PROTOCOL_VERSION = 1
+
 const rl = readline.createInterface({ input: process.stdin, crlfDelay: Infinity })
 
 function locateCodexBinary() {
@@ -47,14 +49,14 @@ if (apiKey) {
     apiKey,
     baseUrl: process.env.CODEX_BASE_URL,
   })
-  console.log('[Codex SDK Handler] Using API key authentication via bundled SDK')
+  console.error('[Codex SDK Handler] Using API key authentication via bundled SDK')
 } else {
   const localBinary = locateCodexBinary()
   if (!localBinary) {
     console.error('[Codex SDK Handler] No CODEX_API_KEY configured and no local codex binary found. Codex requests will fail until credentials are provided.')
     process.exit(1)
   }
-  console.log('[Codex SDK Handler] Using system codex binary at', localBinary)
+  console.error('[Codex SDK Handler] Using system codex binary at', localBinary)
   codex = new Codex({ codexPathOverride: localBinary })
 }
 
@@ -259,6 +261,7 @@ class CodexSession {
       reasoning: new Map(),
       commands: new Map(),
       mcp: new Map(),
+      fileChanges: new Set(),
       cancelled: false,
       structured: Boolean(outputSchema),
       structuredResult: null,
@@ -360,46 +363,74 @@ rl.on('line', (line) => {
 
   const type = command.type
   const sessionId = typeof command.sessionId === 'string' ? command.sessionId : undefined
+  const commandId = typeof command.commandId === 'string' ? command.commandId : undefined
+  const ack = (extra) => {
+    if (commandId) emit(sessionId, { type: 'command:ack', commandId, ...extra })
+  }
+  const reject = (message) => {
+    if (commandId) emit(sessionId, { type: 'command:error', commandId, message })
+  }
+
+  if (type === 'hello') {
+    if (command.protocolVersion !== PROTOCOL_VERSION) {
+      console.warn('[Codex SDK Handler] Backend protocol version mismatch:', command.protocolVersion)
+    }
+    emit(undefined, { type: 'hello', protocolVersion: PROTOCOL_VERSION, handler: 'codex' })
+    return
+  }
+
+  if (type !== 'permission' && !sessionId) {
+    reject('sessionId is required')
+    return
+  }
 
   if (type === 'interrupt') {
-    if (!sessionId) return
     interruptSession(sessionId).catch(() => {})
+    ack()
     return
   }
 
   if (type === 'restart') {
-    if (!sessionId) return
     interruptSession(sessionId)
       .catch(() => {})
       .finally(() => {
         resetThread(sessionId)
       })
+    ack()
     return
   }
 
   if (type === 'permission') {
-    // Permissions are auto-approved; nothing to do
+    // The Codex SDK has no approval hook, so this handler never raises
+    // permission:request and there is nothing to answer.
+    ack()
     return
   }
 
   if (type === 'register') {
-    if (!sessionId) return
-    const session = getSession(sessionId)
-    session.register({
-      threadId: typeof command.threadId === 'string' ? command.threadId : undefined,
-      options: command.options,
-    })
+    try {
+      const session = getSession(sessionId)
+      session.register({
+        threadId: typeof command.threadId === 'string' ? command.threadId : undefined,
+        options: command.options,
+      })
+      ack()
+    } catch (err) {
+      reject(err?.message || String(err))
+    }
     return
   }
 
   if (type === 'run') {
-    if (!sessionId) return
     const session = getSession(sessionId)
+    const queued = session.running || session.queue.length > 0
     session.enqueue(command)
+    ack({ queued })
     return
   }
 
   console.warn('[Codex SDK Handler] Unknown command type:', type)
+  reject(`Unknown command type: ${type}`)
 })
 
 process.on('SIGTERM', () => {
@@ -505,9 +536,7 @@ function handleItemEvent(phase, item, context) {
       handleMcpToolCall(phase, item, context)
       break
     case 'file_change':
-      if (phase === 'completed') {
-        handleFileChange(item, context)
-      }
+      handleFileChange(phase, item, context)
       break
     case 'todo_list':
       if (phase !== 'started') {
@@ -674,23 +703,45 @@ function handleMcpToolCall(phase, item, context) {
   }
 }
 
-function handleFileChange(item, context) {
+function handleFileChange(phase, item, context) {
   const id = item.id || `file-${Date.now()}`
   const changes = Array.isArray(item.changes) ? item.changes : []
+
+  if (phase !== 'completed') {
+    // Announce the change before it lands so the backend can snapshot the
+    // affected files first
+    if (phase === 'started') {
+      context.fileChanges.add(id)
+      emit(context.sessionId, {
+        type: 'tool:start',
+        id,
+        tool: 'file-change',
+        args: {
+          status: 'in_progress',
+          changes,
+        },
+        ts: Date.now(),
+      })
+    }
+    return
+  }
   const summary = changes
     .map((change) => formatFileChange(change))
     .join('\n') || 'Files changed'
 
-  emit(context.sessionId, {
-    type: 'tool:start',
-    id,
-    tool: 'file-change',
-    args: {
-      status: item.status,
-      changes,
-    },
-    ts: Date.now(),
-  })
+  // Changes reported only once they landed still need their start
+  if (!context.fileChanges.delete(id)) {
+    emit(context.sessionId, {
+      type: 'tool:start',
+      id,
+      tool: 'file-change',
+      args: {
+        status: item.status,
+        changes,
+      },
+      ts: Date.now(),
+    })
+  }
 
   emit(context.sessionId, {
     type: 'tool:output',
@@ -0,0 +1,409 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::checkpoint::{
+    new_checkpoint_id, project_root_for, store_checkpoint, FileSnapshot, FileState,
+};
+use crate::checkpoint_path::CheckpointPath;
+use crate::{execute_session_command, write_to_session_terminal};
+
+// Tool output beyond this is cut before it goes back to the model.
+const MAX_TOOL_OUTPUT_CHARS: usize = 16_000;
+
+/// A tool call requested by the model.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// Raw JSON arguments as streamed by the model.
+    pub arguments: String,
+}
+
+pub(crate) struct ToolOutcome {
+    pub output: String,
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct ShellArgs {
+    command: String,
+}
+
+#[derive(Deserialize)]
+struct ReadFileArgs {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct WriteFileArgs {
+    path: String,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct TerminalArgs {
+    data: String,
+}
+
+/// Tools offered to OpenAI-compatible models, in the `tools` request format.
+pub(crate) fn tool_definitions() -> serde_json::Value {
+    serde_json::json!([
+        {
+            "type": "function",
+            "function": {
+                "name": "shell",
+                "description": "Run a shell command in the project directory and return its output.",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "command": { "type": "string" } },
+                    "required": ["command"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "read_file",
+                "description": "Read a text file, relative to the project directory.",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "path": { "type": "string" } },
+                    "required": ["path"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "write_file",
+                "description": "Create or overwrite a file, relative to the project directory.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "content": { "type": "string" }
+                    },
+                    "required": ["path", "content"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "terminal_write",
+                "description": "Send input to the session's open terminal.",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "data": { "type": "string" } },
+                    "required": ["data"]
+                }
+            }
+        }
+    ])
+}
+
+/// The tool name and args shown in `tool:start`, matching what the codex
+/// handler reports for the same kind of action.
+pub(crate) fn display(session_id: &str, call: &ToolCall) -> (String, serde_json::Value) {
+    let args: serde_json::Value =
+        serde_json::from_str(&call.arguments).unwrap_or(serde_json::Value::Null);
+    match call.name.as_str() {
+        "shell" => (
+            "bash".to_string(),
+            serde_json::json!({ "command": args.get("command") }),
+        ),
+        "write_file" => {
+            let path = args.get("path").and_then(|p| p.as_str()).unwrap_or("");
+            let exists = resolve_path(session_id, path)
+                .map(|p| p.absolute().exists())
+                .unwrap_or(false);
+            (
+                "file-change".to_string(),
+                serde_json::json!({
+                    "status": "in_progress",
+                    "changes": [{ "path": path, "kind": if exists { "update" } else { "add" } }],
+                }),
+            )
+        }
+        "read_file" => (
+            "read".to_string(),
+            serde_json::json!({ "path": args.get("path") }),
+        ),
+        "terminal_write" => ("terminal".to_string(), args),
+        other => (other.to_string(), args),
+    }
+}
+
+/// The permission tool name and subject (command or project-relative path)
+/// that a call is checked against.
+pub(crate) fn permission_target(session_id: &str, call: &ToolCall) -> (String, String) {
+    let args: serde_json::Value =
+        serde_json::from_str(&call.arguments).unwrap_or(serde_json::Value::Null);
+    let field = |key: &str| {
+        args.get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    // The key the tool itself resolves to; a path it would refuse is checked
+    // as given.
+    let relative = |path: String| {
+        resolve_path(session_id, &path)
+            .map(|resolved| resolved.key())
+            .unwrap_or(path)
+    };
+    match call.name.as_str() {
+        "shell" => ("bash".to_string(), field("command")),
+        "terminal_write" => ("bash".to_string(), field("data")),
+        "read_file" => ("read".to_string(), relative(field("path"))),
+        "write_file" => ("write".to_string(), relative(field("path"))),
+        other => (other.to_string(), String::new()),
+    }
+}
+
+pub(crate) async fn run_tool(session_id: &str, call: &ToolCall) -> ToolOutcome {
+    let result = match call.name.as_str() {
+        "shell" => shell(session_id, &call.arguments).await,
+        "read_file" => read_file(session_id, &call.arguments),
+        "write_file" => write_file(session_id, &call.arguments),
+        "terminal_write" => terminal_write(session_id, &call.arguments),
+        other => Err(format!("Unknown tool: {}", other)),
+    };
+    match result {
+        Ok(mut outcome) => {
+            outcome.output = truncate(outcome.output);
+            outcome
+        }
+        Err(e) => ToolOutcome {
+            output: format!("Error: {}", e),
+            exit_code: Some(1),
+        },
+    }
+}
+
+fn parse_args<'a, T: Deserialize<'a>>(arguments: &'a str) -> Result<T, String> {
+    serde_json::from_str(arguments).map_err(|e| format!("Invalid tool arguments: {}", e))
+}
+
+/// Resolves a path from the model, refusing anything outside the project.
+fn resolve_path(session_id: &str, path: &str) -> Result<CheckpointPath, String> {
+    CheckpointPath::resolve(&project_root_for(session_id)?, path)
+}
+
+async fn shell(session_id: &str, arguments: &str) -> Result<ToolOutcome, String> {
+    let args: ShellArgs = parse_args(arguments)?;
+    let result = execute_session_command(session_id, &args.command).await?;
+    Ok(ToolOutcome {
+        output: result.output,
+        exit_code: Some(result.exit_code),
+    })
+}
+
+fn read_file(session_id: &str, arguments: &str) -> Result<ToolOutcome, String> {
+    read_in(&project_root_for(session_id)?, arguments)
+}
+
+fn read_in(base: &Path, arguments: &str) -> Result<ToolOutcome, String> {
+    let args: ReadFileArgs = parse_args(arguments)?;
+    let target = CheckpointPath::resolve(base, &args.path)?;
+    let content = fs::read_to_string(target.absolute())
+        .map_err(|e| format!("Failed to read {}: {}", args.path, e))?;
+    Ok(ToolOutcome {
+        output: content,
+        exit_code: None,
+    })
+}
+
+/// Checkpoints the file's current contents, then writes the new contents.
+fn write_file(session_id: &str, arguments: &str) -> Result<ToolOutcome, String> {
+    write_in(
+        &project_root_for(session_id)?,
+        arguments,
+        |snapshot, trigger| {
+            store_checkpoint(
+                session_id,
+                &new_checkpoint_id(),
+                &[snapshot],
+                Some(trigger),
+                None,
+                "auto",
+                Vec::new(),
+            )
+            .map(|_| ())
+        },
+    )
+}
+
+/// Writes the file under `base`, handing the change to `checkpoint` (with
+/// its trigger) first; nothing is written if that fails.
+fn write_in(
+    base: &Path,
+    arguments: &str,
+    checkpoint: impl FnOnce(FileSnapshot, String) -> Result<(), String>,
+) -> Result<ToolOutcome, String> {
+    let args: WriteFileArgs = parse_args(arguments)?;
+    let resolved = CheckpointPath::resolve(base, &args.path)?;
+    let target = resolved.absolute();
+    let original = FileState::read(target)?;
+    let existed = original.exists;
+    let current = FileState {
+        exists: true,
+        bytes: args.content.clone().into_bytes(),
+        mode: original.mode,
+    };
+
+    checkpoint(
+        FileSnapshot::from_states(resolved.key(), original, current),
+        format!("write:{}", args.path),
+    )?;
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create parent directory: {}", e))?;
+    }
+    fs::write(target, &args.content)
+        .map_err(|e| format!("Failed to write {}: {}", args.path, e))?;
+
+    Ok(ToolOutcome {
+        output: format!("{} {}", if existed { "~" } else { "+" }, args.path),
+        exit_code: None,
+    })
+}
+
+fn terminal_write(session_id: &str, arguments: &str) -> Result<ToolOutcome, String> {
+    let args: TerminalArgs = parse_args(arguments)?;
+    write_to_session_terminal(session_id, &args.data)?;
+    Ok(ToolOutcome {
+        output: "Sent to terminal".to_string(),
+        exit_code: None,
+    })
+}
+
+fn truncate(mut output: String) -> String {
+    if output.len() > MAX_TOOL_OUTPUT_CHARS {
+        let mut cut = MAX_TOOL_OUTPUT_CHARS;
+        while !output.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        output.truncate(cut);
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(value: serde_json::Value) -> String {
+        value.to_string()
+    }
+
+    /// Refuses every write, recording that it was asked.
+    fn no_checkpoint(
+        asked: &mut bool,
+    ) -> impl FnOnce(FileSnapshot, String) -> Result<(), String> + '_ {
+        move |_, _| {
+            *asked = true;
+            Err("checkpoint refused".to_string())
+        }
+    }
+
+    #[test]
+    fn reads_files_inside_the_project() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "pub fn f() {}").unwrap();
+
+        let outcome = read_in(
+            dir.path(),
+            &args(serde_json::json!({ "path": "src/lib.rs" })),
+        )
+        .unwrap();
+
+        assert_eq!(outcome.output, "pub fn f() {}");
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_project() {
+        let outer = tempfile::tempdir().unwrap();
+        let project = outer.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(outer.path().join("secret.txt"), "secret").unwrap();
+        let outside = outer.path().join("secret.txt");
+
+        for path in [
+            "../secret.txt",
+            "src/../../secret.txt",
+            outside.to_str().unwrap(),
+        ] {
+            let arguments = args(serde_json::json!({ "path": path, "content": "x" }));
+            assert!(read_in(&project, &arguments).is_err(), "read {}", path);
+            let mut asked = false;
+            assert!(
+                write_in(&project, &arguments, no_checkpoint(&mut asked)).is_err(),
+                "wrote {}",
+                path
+            );
+            assert!(!asked);
+        }
+        assert_eq!(fs::read_to_string(&outside).unwrap(), "secret");
+    }
+
+    #[test]
+    fn checkpoints_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("notes.txt");
+        fs::write(&target, "before").unwrap();
+        let mut saved = None;
+
+        let outcome = write_in(
+            dir.path(),
+            &args(serde_json::json!({ "path": "notes.txt", "content": "after" })),
+            |snapshot, trigger| {
+                assert_eq!(fs::read_to_string(&target).unwrap(), "before");
+                saved = Some((snapshot, trigger));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let (snapshot, trigger) = saved.unwrap();
+        assert_eq!(trigger, "write:notes.txt");
+        assert_eq!(snapshot.path, "notes.txt");
+        assert_eq!(snapshot.original_data(), Some(&b"before"[..]));
+        assert_eq!(snapshot.current_data(), Some(&b"after"[..]));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "after");
+        assert_eq!(outcome.output, "~ notes.txt");
+    }
+
+    #[test]
+    fn writes_nothing_when_the_checkpoint_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut asked = false;
+
+        let result = write_in(
+            dir.path(),
+            &args(serde_json::json!({ "path": "new/file.txt", "content": "x" })),
+            no_checkpoint(&mut asked),
+        );
+
+        assert!(result.is_err());
+        assert!(asked);
+        assert!(!dir.path().join("new").exists());
+    }
+
+    #[test]
+    fn truncates_long_output_on_a_char_boundary() {
+        let short = "é".repeat(100);
+        assert_eq!(truncate(short.clone()), short);
+
+        // One ASCII byte puts every two-byte char off the even cut point.
+        let long = format!("a{}", "é".repeat(MAX_TOOL_OUTPUT_CHARS));
+        let truncated = truncate(long.clone());
+        let kept = truncated.strip_suffix("\n[output truncated]").unwrap();
+        assert_eq!(kept.len(), MAX_TOOL_OUTPUT_CHARS - 1);
+        assert!(long.starts_with(kept));
+    }
+}
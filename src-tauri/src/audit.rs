@@ -0,0 +1,563 @@
+//! Append-only audit log of agent actions.
+//!
+//! Entries are written as JSON lines to
+//! `~/.claude-code/audit/<project hash>/<session_id>.jsonl`, outside the
+//! project so the log never ends up in its working tree. Shell commands,
+//! file changes and MCP calls are recorded when they finish, together with the
+//! checkpoints taken around them; permission decisions and checkpoints are
+//! recorded as they happen.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::command;
+
+use crate::checkpoint::project_root_for;
+use crate::checkpoint_location::project_key;
+use crate::protocol::{HandlerEvent, HandlerMessage};
+
+// Output kept per entry; the full output stays in the transcript.
+const MAX_OUTPUT_CHARS: usize = 4_000;
+// Checkpoint ids remembered per session for linking.
+const MAX_TRACKED_CHECKPOINTS: usize = 50;
+
+static STATE: Lazy<Mutex<AuditState>> = Lazy::new(|| Mutex::new(AuditState::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub ts: String,
+    pub session_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// `command`, `file_change`, `mcp`, `tool`, `permission` or `checkpoint`.
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_id: Option<String>,
+    pub cwd: String,
+    #[serde(default)]
+    pub detail: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checkpoint_ids: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditQuery {
+    /// Include every session of the project, not just the given one.
+    #[serde(default)]
+    pub all_sessions: bool,
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    /// Only entries at or after `since` and before `until`.
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+/// When an entry was written; `None` for a timestamp that does not parse.
+fn entry_time(entry: &AuditEntry) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&entry.ts)
+        .ok()
+        .map(|ts| ts.with_timezone(&Utc))
+}
+
+impl AuditQuery {
+    fn in_range(&self, entry: &AuditEntry) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        entry_time(entry).is_some_and(|ts| {
+            self.since.map_or(true, |since| ts >= since)
+                && self.until.map_or(true, |until| ts < until)
+        })
+    }
+}
+
+struct OpenAction {
+    model: String,
+    kind: String,
+    detail: serde_json::Value,
+    started_at: String,
+    output: String,
+    // Checkpoints the session had when the action started.
+    checkpoint_mark: usize,
+}
+
+#[derive(Default)]
+struct AuditState {
+    // Keyed by (session id, tool call id).
+    open: HashMap<(String, String), OpenAction>,
+    checkpoints: HashMap<String, Vec<String>>,
+}
+
+impl AuditState {
+    /// Tracks an action from its `tool:start` until the `tool:output` marked
+    /// done, and returns the finished entry then. `cwd` is left for the caller.
+    fn observe(&mut self, model: &str, message: &HandlerMessage) -> Option<AuditEntry> {
+        let session_id = message.session_id.as_deref()?;
+        match &message.event {
+            HandlerEvent::ToolStart { id, tool, args } => {
+                let key = (session_id.to_string(), id.clone());
+                // A repeated start for an open action keeps the first one.
+                if self.open.contains_key(&key) {
+                    return None;
+                }
+                let mark = self.checkpoints.get(session_id).map_or(0, Vec::len);
+                let mut detail = args.clone();
+                if let Some(obj) = detail.as_object_mut() {
+                    obj.insert("tool".to_string(), serde_json::json!(tool));
+                }
+                self.open.insert(
+                    key,
+                    OpenAction {
+                        model: model.to_string(),
+                        kind: action_kind(tool).to_string(),
+                        detail,
+                        started_at: now(),
+                        output: String::new(),
+                        checkpoint_mark: mark,
+                    },
+                );
+                None
+            }
+            HandlerEvent::ToolOutput {
+                id,
+                chunk,
+                done,
+                exit_code,
+                ..
+            } => {
+                let key = (session_id.to_string(), id.clone());
+                let action = self.open.get_mut(&key)?;
+                let room = MAX_OUTPUT_CHARS.saturating_sub(action.output.chars().count());
+                action.output.extend(chunk.chars().take(room));
+                if !*done {
+                    return None;
+                }
+                let action = self.open.remove(&key)?;
+                Some(AuditEntry {
+                    ts: now(),
+                    session_id: session_id.to_string(),
+                    model: Some(action.model),
+                    kind: action.kind,
+                    action_id: Some(id.clone()),
+                    cwd: String::new(),
+                    detail: action.detail,
+                    started_at: Some(action.started_at),
+                    exit_code: *exit_code,
+                    output: Some(action.output).filter(|o| !o.is_empty()),
+                    checkpoint_ids: self.linked_checkpoints(session_id, action.checkpoint_mark),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn track_checkpoint(&mut self, session_id: &str, checkpoint_id: &str) {
+        let ids = self.checkpoints.entry(session_id.to_string()).or_default();
+        ids.push(checkpoint_id.to_string());
+        if ids.len() > MAX_TRACKED_CHECKPOINTS {
+            let excess = ids.len() - MAX_TRACKED_CHECKPOINTS;
+            ids.drain(..excess);
+            // Keep marks of in-flight actions pointing at the same checkpoints.
+            for ((session, _), action) in self.open.iter_mut() {
+                if session == session_id {
+                    action.checkpoint_mark = action.checkpoint_mark.saturating_sub(excess);
+                }
+            }
+        }
+    }
+
+    /// The checkpoint taken last before the action, plus any taken during it.
+    fn linked_checkpoints(&self, session_id: &str, mark: usize) -> Vec<String> {
+        let Some(ids) = self.checkpoints.get(session_id) else {
+            return Vec::new();
+        };
+        let mark = mark.min(ids.len());
+        ids[mark.saturating_sub(1)..].to_vec()
+    }
+}
+
+fn audit_dir(session_id: &str) -> Result<PathBuf, String> {
+    let home = dirs::home_dir().ok_or("Failed to get home directory")?;
+    Ok(home
+        .join(".claude-code")
+        .join("audit")
+        .join(project_key(&project_root_for(session_id)?)))
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+fn cwd_for(session_id: &str) -> String {
+    project_root_for(session_id)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn append(entry: &AuditEntry) {
+    let result = audit_dir(&entry.session_id).and_then(|dir| {
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create audit directory: {}", e))?;
+        let line = serde_json::to_string(entry)
+            .map_err(|e| format!("Failed to serialize audit entry: {}", e))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{}.jsonl", entry.session_id)))
+            .map_err(|e| format!("Failed to open audit log: {}", e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write audit log: {}", e))
+    });
+    if let Err(e) = result {
+        eprintln!("[RUST] Audit entry dropped: {}", e);
+    }
+}
+
+fn action_kind(tool: &str) -> &'static str {
+    match tool {
+        "bash" => "command",
+        "file-change" => "file_change",
+        "mcp" => "mcp",
+        _ => "tool",
+    }
+}
+
+/// Records the handler events that describe agent actions. Called for every
+/// message a handler emits, before it is forwarded to the webview.
+pub(crate) fn observe(model: &str, message: &HandlerMessage) {
+    let finished = STATE.lock().unwrap().observe(model, message);
+    if let Some(mut entry) = finished {
+        entry.cwd = cwd_for(&entry.session_id);
+        append(&entry);
+    }
+}
+
+pub(crate) fn record_permission(
+    session_id: &str,
+    request_id: &str,
+    tool: &str,
+    subject: &str,
+    allow: bool,
+    scope: &str,
+    source: &str,
+) {
+    append(&AuditEntry {
+        ts: now(),
+        session_id: session_id.to_string(),
+        model: None,
+        kind: "permission".to_string(),
+        action_id: Some(request_id.to_string()),
+        cwd: cwd_for(session_id),
+        detail: serde_json::json!({
+            "tool": tool,
+            "subject": subject,
+            "allow": allow,
+            "scope": scope,
+            "source": source,
+        }),
+        started_at: None,
+        exit_code: None,
+        output: None,
+        checkpoint_ids: Vec::new(),
+    });
+}
+
+pub(crate) fn record_checkpoint(
+    session_id: &str,
+    checkpoint_id: &str,
+    trigger: Option<&str>,
+    paths: Vec<String>,
+) {
+    STATE
+        .lock()
+        .unwrap()
+        .track_checkpoint(session_id, checkpoint_id);
+    append(&AuditEntry {
+        ts: now(),
+        session_id: session_id.to_string(),
+        model: None,
+        kind: "checkpoint".to_string(),
+        action_id: None,
+        cwd: cwd_for(session_id),
+        detail: serde_json::json!({ "trigger": trigger, "files": paths }),
+        started_at: None,
+        exit_code: None,
+        output: None,
+        checkpoint_ids: vec![checkpoint_id.to_string()],
+    });
+}
+
+fn read_entries(session_id: &str, query: &AuditQuery) -> Result<Vec<AuditEntry>, String> {
+    read_log(&audit_dir(session_id)?, session_id, query)
+}
+
+/// The entries of `session_id` (or every session, see [`AuditQuery`]) in
+/// `dir` that match `query`, oldest first.
+fn read_log(dir: &Path, session_id: &str, query: &AuditQuery) -> Result<Vec<AuditEntry>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let logs: Vec<PathBuf> = if query.all_sessions {
+        fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read audit directory: {}", e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect()
+    } else {
+        vec![dir.join(format!("{}.jsonl", session_id))]
+    };
+
+    let mut entries = Vec::new();
+    for log in logs.into_iter().filter(|path| path.exists()) {
+        let contents =
+            fs::read_to_string(&log).map_err(|e| format!("Failed to read audit log: {}", e))?;
+        entries.extend(
+            contents
+                .lines()
+                .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+                .filter(|entry| query.kinds.is_empty() || query.kinds.contains(&entry.kind))
+                .filter(|entry| query.in_range(entry)),
+        );
+    }
+    entries.sort_by_key(entry_time);
+    if let Some(limit) = query.limit {
+        let skip = entries.len().saturating_sub(limit);
+        entries.drain(..skip);
+    }
+    Ok(entries)
+}
+
+#[command]
+pub fn query_audit_log(
+    session_id: String,
+    query: Option<AuditQuery>,
+) -> Result<Vec<AuditEntry>, String> {
+    read_entries(&session_id, &query.unwrap_or_default())
+}
+
+/// Writes the matching entries to `destination` as JSON lines and returns how
+/// many were exported.
+#[command]
+pub fn export_audit_log(
+    session_id: String,
+    destination: String,
+    query: Option<AuditQuery>,
+) -> Result<usize, String> {
+    let entries = read_entries(&session_id, &query.unwrap_or_default())?;
+    write_entries(Path::new(&destination), &entries)?;
+    Ok(entries.len())
+}
+
+fn write_entries(destination: &Path, entries: &[AuditEntry]) -> Result<(), String> {
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(
+            &serde_json::to_string(entry)
+                .map_err(|e| format!("Failed to serialize audit entry: {}", e))?,
+        );
+        out.push('\n');
+    }
+    fs::write(destination, out).map_err(|e| format!("Failed to export audit log: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(event: serde_json::Value) -> HandlerMessage {
+        HandlerMessage::parse(&event.to_string(), "s1")
+    }
+
+    fn start(id: &str, command: &str) -> HandlerMessage {
+        message(json!({ "type": "tool:start", "id": id, "tool": "bash",
+            "args": { "command": command } }))
+    }
+
+    fn output(id: &str, chunk: &str, done: bool) -> HandlerMessage {
+        message(json!({ "type": "tool:output", "id": id, "chunk": chunk,
+            "done": done, "exitCode": 0 }))
+    }
+
+    fn entry(session_id: &str, kind: &str, ts: &str) -> AuditEntry {
+        AuditEntry {
+            ts: ts.to_string(),
+            session_id: session_id.to_string(),
+            model: None,
+            kind: kind.to_string(),
+            action_id: None,
+            cwd: String::new(),
+            detail: serde_json::Value::Null,
+            started_at: None,
+            exit_code: None,
+            output: None,
+            checkpoint_ids: Vec::new(),
+        }
+    }
+
+    fn write_log(dir: &Path, session_id: &str, entries: &[AuditEntry]) {
+        fs::create_dir_all(dir).unwrap();
+        write_entries(&dir.join(format!("{}.jsonl", session_id)), entries).unwrap();
+    }
+
+    fn ts(raw: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(raw)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn pairs_start_with_completion() {
+        let mut state = AuditState::default();
+        state.track_checkpoint("s1", "cp1");
+        state.track_checkpoint("s1", "cp2");
+        assert!(state
+            .observe("codex", &start("call", "cargo test"))
+            .is_none());
+        state.track_checkpoint("s1", "cp3");
+        assert!(state
+            .observe("codex", &output("call", "ok ", false))
+            .is_none());
+
+        let entry = state
+            .observe("codex", &output("call", "done", true))
+            .unwrap();
+        assert_eq!(entry.kind, "command");
+        assert_eq!(entry.model.as_deref(), Some("codex"));
+        assert_eq!(entry.action_id.as_deref(), Some("call"));
+        assert_eq!(entry.detail["command"], "cargo test");
+        assert_eq!(entry.detail["tool"], "bash");
+        assert_eq!(entry.output.as_deref(), Some("ok done"));
+        assert_eq!(entry.exit_code, Some(0));
+        assert!(entry.started_at.is_some());
+        // The checkpoint taken last before the action and the one during it.
+        assert_eq!(entry.checkpoint_ids, vec!["cp2", "cp3"]);
+        assert!(state.open.is_empty());
+
+        // Output without an open action is not recorded.
+        assert!(state
+            .observe("codex", &output("call", "late", true))
+            .is_none());
+    }
+
+    #[test]
+    fn ignores_a_repeated_start() {
+        let mut state = AuditState::default();
+        state.observe("codex", &start("call", "first"));
+        state.observe("codex", &output("call", "partial", false));
+        state.observe("codex", &start("call", "second"));
+
+        let entry = state.observe("codex", &output("call", "", true)).unwrap();
+        assert_eq!(entry.detail["command"], "first");
+        assert_eq!(entry.output.as_deref(), Some("partial"));
+    }
+
+    #[test]
+    fn caps_recorded_output() {
+        let mut state = AuditState::default();
+        state.observe("codex", &start("call", "yes"));
+        let chunk = "é".repeat(MAX_OUTPUT_CHARS);
+        state.observe("codex", &output("call", &chunk, false));
+        let entry = state
+            .observe("codex", &output("call", "more", true))
+            .unwrap();
+        assert_eq!(entry.output.unwrap().chars().count(), MAX_OUTPUT_CHARS);
+    }
+
+    #[test]
+    fn filters_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        write_log(
+            dir.path(),
+            "s1",
+            &[
+                entry("s1", "command", "2026-03-01T10:00:00Z"),
+                entry("s1", "permission", "2026-03-01T09:00:00Z"),
+                entry("s1", "command", "not a timestamp"),
+                entry("s1", "file_change", "2026-03-01T12:00:00Z"),
+            ],
+        );
+        write_log(
+            dir.path(),
+            "s2",
+            &[entry("s2", "command", "2026-03-01T11:00:00Z")],
+        );
+
+        let all = read_log(dir.path(), "s1", &AuditQuery::default()).unwrap();
+        assert_eq!(all.len(), 4);
+
+        let query = AuditQuery {
+            kinds: vec!["command".to_string()],
+            ..Default::default()
+        };
+        let commands = read_log(dir.path(), "s1", &query).unwrap();
+        assert!(commands.iter().all(|e| e.kind == "command"));
+        assert_eq!(commands.len(), 2);
+
+        // Bounds drop unparsable timestamps; `until` is exclusive.
+        let query = AuditQuery {
+            since: Some(ts("2026-03-01T09:00:00Z")),
+            until: Some(ts("2026-03-01T12:00:00Z")),
+            ..Default::default()
+        };
+        let kinds: Vec<String> = read_log(dir.path(), "s1", &query)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        assert_eq!(kinds, vec!["permission", "command"]);
+
+        // Every session, oldest first, keeping the newest `limit`.
+        let query = AuditQuery {
+            all_sessions: true,
+            since: Some(ts("2026-01-01T00:00:00Z")),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let newest: Vec<String> = read_log(dir.path(), "s1", &query)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.ts)
+            .collect();
+        assert_eq!(newest, vec!["2026-03-01T11:00:00Z", "2026-03-01T12:00:00Z"]);
+
+        let missing = dir.path().join("missing");
+        assert!(read_log(&missing, "s1", &AuditQuery::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn exports_entries_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut recorded = entry("s1", "command", "2026-03-01T10:00:00Z");
+        recorded.checkpoint_ids = vec!["cp1".to_string()];
+        recorded.exit_code = Some(2);
+        let entries = vec![recorded, entry("s1", "checkpoint", "2026-03-01T11:00:00Z")];
+
+        let destination = dir.path().join("export.jsonl");
+        write_entries(&destination, &entries).unwrap();
+        let contents = fs::read_to_string(&destination).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+
+        let exported: Vec<AuditEntry> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(exported[0].checkpoint_ids, vec!["cp1"]);
+        assert_eq!(exported[0].exit_code, Some(2));
+        assert_eq!(exported[1].kind, "checkpoint");
+    }
+}
@@ -0,0 +1,348 @@
+//! Checkpoints taken by the backend from handler `file-change` events, so they
+//! exist even when the webview is closed or slow to react.
+//!
+//! When a handler reports a change as in progress, the affected files are
+//! read before the change lands; when it reports the change as completed, the
+//! new contents are read and both sides are saved as one checkpoint. Changes
+//! only reported once they landed have no known prior contents, so only the
+//! files they add are checkpointed. Checkpoints are written on a worker
+//! thread so the handler's events keep flowing meanwhile.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::checkpoint::{
+    new_checkpoint_id, project_root_for, store_checkpoint, FileSnapshot, FileState,
+};
+use crate::checkpoint_path::CheckpointPath;
+use crate::protocol::{HandlerEvent, HandlerMessage};
+
+// (session id, item id) of a reported change.
+type ChangeKey = (String, String);
+
+// Originals captured for in-progress changes.
+static PENDING: Lazy<Mutex<HashMap<ChangeKey, Vec<PendingFile>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Checkpoints waiting to be written, in the order their changes completed.
+static SAVES: Lazy<Mutex<Sender<Save>>> = Lazy::new(|| {
+    let (tx, rx) = mpsc::channel::<Save>();
+    thread::spawn(move || {
+        for save in rx {
+            save.run();
+        }
+    });
+    Mutex::new(tx)
+});
+
+struct PendingFile {
+    path: String,
+    original: FileState,
+}
+
+struct Change {
+    path: String,
+    kind: String,
+}
+
+struct Save {
+    session_id: String,
+    item_id: String,
+    snapshots: Vec<FileSnapshot>,
+}
+
+impl Save {
+    fn run(self) {
+        let checkpoint_id = new_checkpoint_id();
+        let result = store_checkpoint(
+            &self.session_id,
+            &checkpoint_id,
+            &self.snapshots,
+            Some(format!("file_change:{}", self.item_id)),
+            None,
+            "auto",
+            Vec::new(),
+        );
+        match result {
+            Ok(_) => eprintln!(
+                "[RUST] Auto-checkpoint {} saved for session {}",
+                checkpoint_id, self.session_id
+            ),
+            Err(e) => eprintln!("[RUST] Auto-checkpoint failed: {}", e),
+        }
+    }
+}
+
+fn changes(args: &serde_json::Value) -> Vec<Change> {
+    args.get("changes")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|change| {
+            let path = change
+                .get("path")
+                .or_else(|| change.get("file"))
+                .and_then(|p| p.as_str())?;
+            Some(Change {
+                path: path.to_string(),
+                kind: change
+                    .get("kind")
+                    .and_then(|k| k.as_str())
+                    .unwrap_or("")
+                    .to_lowercase(),
+            })
+        })
+        .collect()
+}
+
+/// The reported path as a checkpoint location, or `None` (logged) for files
+/// outside the project.
+fn checkpoint_path(base: &Path, path: &str) -> Option<CheckpointPath> {
+    CheckpointPath::resolve(base, path)
+        .inspect_err(|e| eprintln!("[RUST] Auto-checkpoint skipped {}: {}", path, e))
+        .ok()
+}
+
+/// Reads the files a change is about to touch.
+fn capture(base: &Path, args: &serde_json::Value) -> Vec<PendingFile> {
+    changes(args)
+        .into_iter()
+        .filter_map(|change| {
+            let path = checkpoint_path(base, &change.path)?;
+            match FileState::read(path.absolute()) {
+                Ok(original) => Some(PendingFile {
+                    path: path.key(),
+                    original,
+                }),
+                Err(e) => {
+                    eprintln!("[RUST] Auto-checkpoint skipped {}: {}", path.key(), e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Originals for a change nobody captured: only added files are known to
+/// have been absent, anything else is skipped rather than guessed.
+fn uncaptured(base: &Path, args: &serde_json::Value) -> Vec<PendingFile> {
+    changes(args)
+        .into_iter()
+        .filter_map(|change| {
+            if change.kind != "add" {
+                eprintln!(
+                    "[RUST] Auto-checkpoint skipped {}: contents before the change were not captured",
+                    change.path
+                );
+                return None;
+            }
+            Some(PendingFile {
+                path: checkpoint_path(base, &change.path)?.key(),
+                original: FileState::default(),
+            })
+        })
+        .collect()
+}
+
+/// Pairs the captured originals with the files as they are now.
+fn snapshots(base: &Path, files: Vec<PendingFile>) -> Vec<FileSnapshot> {
+    files
+        .into_iter()
+        .map(|file| {
+            let current = CheckpointPath::resolve(base, &file.path)
+                .and_then(|path| FileState::read(path.absolute()))
+                .unwrap_or_default();
+            FileSnapshot::from_states(file.path, file.original, current)
+        })
+        .collect()
+}
+
+/// Tracks one event of a session in `base`, returning the snapshots of a
+/// change that completed.
+fn track(base: &Path, session_id: &str, event: &HandlerEvent) -> Option<Vec<FileSnapshot>> {
+    let key = |id: &str| (session_id.to_string(), id.to_string());
+    let files = match event {
+        HandlerEvent::ToolStart { id, tool, args } if tool == "file-change" => {
+            match args.get("status").and_then(|s| s.as_str()).unwrap_or("") {
+                "in_progress" | "pending" => {
+                    PENDING.lock().unwrap().insert(key(id), capture(base, args));
+                    return None;
+                }
+                "failed" | "declined" => {
+                    PENDING.lock().unwrap().remove(&key(id));
+                    return None;
+                }
+                _ => {
+                    let captured = PENDING.lock().unwrap().remove(&key(id));
+                    captured.unwrap_or_else(|| uncaptured(base, args))
+                }
+            }
+        }
+        HandlerEvent::ToolOutput { id, done: true, .. } => {
+            PENDING.lock().unwrap().remove(&key(id))?
+        }
+        _ => return None,
+    };
+    Some(snapshots(base, files)).filter(|snapshots| !snapshots.is_empty())
+}
+
+/// Called for every message read from a handler process.
+pub(crate) fn observe(message: &HandlerMessage) {
+    let Some(session_id) = message.session_id.as_deref() else {
+        return;
+    };
+    let (item_id, relevant) = match &message.event {
+        HandlerEvent::ToolStart { id, tool, .. } => (id, tool == "file-change"),
+        HandlerEvent::ToolOutput { id, done, .. } => (id, *done),
+        _ => return,
+    };
+    if !relevant {
+        return;
+    }
+    let Ok(base) = project_root_for(session_id) else {
+        return;
+    };
+    let Some(snapshots) = track(&base, session_id, &message.event) else {
+        return;
+    };
+    let save = Save {
+        session_id: session_id.to_string(),
+        item_id: item_id.clone(),
+        snapshots,
+    };
+    if SAVES.lock().unwrap().send(save).is_err() {
+        eprintln!("[RUST] Auto-checkpoint failed: checkpoint writer stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    fn file_change(id: &str, status: &str, changes: serde_json::Value) -> HandlerEvent {
+        HandlerEvent::ToolStart {
+            id: id.to_string(),
+            tool: "file-change".to_string(),
+            args: json!({ "status": status, "changes": changes }),
+        }
+    }
+
+    fn done(id: &str) -> HandlerEvent {
+        HandlerEvent::ToolOutput {
+            id: id.to_string(),
+            chunk: String::new(),
+            stream: None,
+            done: true,
+            exit_code: None,
+        }
+    }
+
+    #[test]
+    fn pairs_captured_contents_with_the_completed_change() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "old").unwrap();
+        let changes = json!([{ "path": "a.txt", "kind": "update" }]);
+
+        assert!(track(
+            dir.path(),
+            "pairs",
+            &file_change("c1", "in_progress", changes.clone())
+        )
+        .is_none());
+        fs::write(dir.path().join("a.txt"), "new").unwrap();
+        let snapshots = track(
+            dir.path(),
+            "pairs",
+            &file_change("c1", "completed", changes),
+        )
+        .unwrap();
+
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].path, "a.txt");
+        assert_eq!(snapshots[0].original_data(), Some(&b"old"[..]));
+        assert_eq!(snapshots[0].current_data(), Some(&b"new"[..]));
+    }
+
+    #[test]
+    fn finishes_a_captured_change_when_its_output_is_done() {
+        let dir = tempfile::tempdir().unwrap();
+        let changes = json!([{ "path": "b.txt", "kind": "add" }]);
+
+        track(
+            dir.path(),
+            "output",
+            &file_change("c1", "in_progress", changes),
+        );
+        fs::write(dir.path().join("b.txt"), "added").unwrap();
+        let snapshots = track(dir.path(), "output", &done("c1")).unwrap();
+
+        assert_eq!(snapshots[0].original_data(), None);
+        assert_eq!(snapshots[0].current_data(), Some(&b"added"[..]));
+        assert!(track(dir.path(), "output", &done("c1")).is_none());
+    }
+
+    #[test]
+    fn keeps_captures_apart_per_session() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "old").unwrap();
+        let changes = json!([{ "path": "a.txt", "kind": "update" }]);
+
+        track(
+            dir.path(),
+            "first",
+            &file_change("c1", "in_progress", changes.clone()),
+        );
+        assert!(track(dir.path(), "second", &done("c1")).is_none());
+        assert!(track(dir.path(), "first", &done("c1")).is_some());
+    }
+
+    #[test]
+    fn checkpoints_only_added_files_of_uncaptured_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "edited").unwrap();
+        fs::write(dir.path().join("b.txt"), "added").unwrap();
+        let changes = json!([
+            { "path": "a.txt", "kind": "update" },
+            { "path": "b.txt", "kind": "add" },
+            { "path": "../c.txt", "kind": "add" },
+        ]);
+
+        let snapshots =
+            track(dir.path(), "late", &file_change("c1", "completed", changes)).unwrap();
+
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].path, "b.txt");
+        assert!(!snapshots[0].original_exists);
+    }
+
+    #[test]
+    fn forgets_failed_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "old").unwrap();
+        let changes = json!([{ "path": "a.txt", "kind": "update" }]);
+
+        track(
+            dir.path(),
+            "failed",
+            &file_change("c1", "in_progress", changes.clone()),
+        );
+        assert!(track(
+            dir.path(),
+            "failed",
+            &file_change("c1", "failed", changes.clone())
+        )
+        .is_none());
+        assert!(track(
+            dir.path(),
+            "failed",
+            &file_change("c1", "completed", changes)
+        )
+        .is_none());
+    }
+}
@@ -5,17 +5,174 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use tauri::command;
 
+use crate::checkpoint_git::GitCheckpoints;
+use crate::checkpoint_index;
+use crate::checkpoint_location;
+use crate::checkpoint_path::CheckpointPath;
+use crate::checkpoint_store::{self, Manifest, ManifestEntry};
 use crate::get_session_project_dir;
 
+/// One file in a checkpoint: `original_*` is the file before the change,
+/// `current_*` after it. The webview sends the text fields; the byte fields,
+/// when present, take precedence and carry non-UTF-8 content.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileSnapshot {
     pub path: String,
+    #[serde(default)]
     pub original_content: String,
+    #[serde(default)]
     pub current_content: String,
     pub checksum: Option<String>,
+    #[serde(
+        default,
+        with = "base64_bytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub original_bytes: Option<Vec<u8>>,
+    #[serde(
+        default,
+        with = "base64_bytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub current_bytes: Option<Vec<u8>>,
+    /// False when the file did not exist on that side of the change.
+    #[serde(default = "default_exists")]
+    pub original_exists: bool,
+    #[serde(default = "default_exists")]
+    pub current_exists: bool,
+    /// Unix permission bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_mode: Option<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn default_exists() -> bool {
+    true
+}
+
+mod base64_bytes {
+    use base64::{engine::general_purpose, Engine as _};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => s.serialize_some(&general_purpose::STANDARD.encode(bytes)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|encoded| {
+                general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(D::Error::custom)
+            })
+            .transpose()
+    }
+}
+
+/// A file as found on disk, or its absence.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FileState {
+    pub exists: bool,
+    pub bytes: Vec<u8>,
+    pub mode: Option<u32>,
+}
+
+impl FileState {
+    pub(crate) fn read(path: &Path) -> Result<Self, String> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(FileState::default()),
+            Err(e) => return Err(format!("Failed to stat {}: {}", path.display(), e)),
+        };
+        let bytes =
+            fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(FileState {
+            exists: true,
+            bytes,
+            mode: file_mode(&metadata),
+        })
+    }
+}
+
+impl FileSnapshot {
+    pub(crate) fn from_states(path: String, original: FileState, current: FileState) -> Self {
+        FileSnapshot {
+            path,
+            original_content: String::from_utf8_lossy(&original.bytes).into_owned(),
+            current_content: String::from_utf8_lossy(&current.bytes).into_owned(),
+            checksum: None,
+            original_bytes: Some(original.bytes),
+            current_bytes: Some(current.bytes),
+            original_exists: original.exists,
+            current_exists: current.exists,
+            original_mode: original.mode,
+            current_mode: current.mode,
+        }
+    }
+
+    /// The file's bytes before the change, or `None` if it did not exist.
+    pub(crate) fn original_data(&self) -> Option<&[u8]> {
+        self.original_exists.then(|| {
+            self.original_bytes
+                .as_deref()
+                .unwrap_or(self.original_content.as_bytes())
+        })
+    }
+
+    /// The file's bytes after the change, or `None` if it was deleted.
+    pub(crate) fn current_data(&self) -> Option<&[u8]> {
+        self.current_exists.then(|| {
+            self.current_bytes
+                .as_deref()
+                .unwrap_or(self.current_content.as_bytes())
+        })
+    }
+
+    pub(crate) fn original_state(&self) -> FileState {
+        FileState {
+            exists: self.original_exists,
+            bytes: self.original_data().unwrap_or_default().to_vec(),
+            mode: self.original_mode,
+        }
+    }
+
+    pub(crate) fn current_state(&self) -> FileState {
+        FileState {
+            exists: self.current_exists,
+            bytes: self.current_data().unwrap_or_default().to_vec(),
+            mode: self.current_mode,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &Path, mode: u32) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Failed to set permissions on {}: {}", path.display(), e))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_path: &Path, _mode: u32) -> Result<(), String> {
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CheckpointMetadata {
     pub id: String,
     pub timestamp: DateTime<Utc>,
@@ -25,9 +182,46 @@ pub struct CheckpointMetadata {
     pub file_count: usize,
     pub git_branch: Option<String>,
     pub git_commit: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The checkpoint the session was at when this one was taken, so the
+    /// checkpoints form a tree that branches where a restore diverged.
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Set on checkpoints unpacked from an archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imported: Option<ImportOrigin>,
+}
+
+/// Where an imported checkpoint came from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportOrigin {
+    /// File name of the archive.
+    pub archive: String,
+    /// The checkpoint's id in the archive.
+    pub original_id: String,
+    /// The project the archive was exported from.
+    pub project_dir: String,
+    pub imported_at: DateTime<Utc>,
 }
 
-fn project_root_for(session_id: &str) -> Result<PathBuf, String> {
+/// A checkpoint with the ids of the checkpoints taken on top of it.
+#[derive(Debug, Serialize)]
+pub struct CheckpointNode {
+    #[serde(flatten)]
+    pub metadata: CheckpointMetadata,
+    pub children: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckpointTree {
+    /// The checkpoint the session's files currently correspond to.
+    pub head: Option<String>,
+    /// Newest first.
+    pub checkpoints: Vec<CheckpointNode>,
+}
+
+pub(crate) fn project_root_for(session_id: &str) -> Result<PathBuf, String> {
     match get_session_project_dir(session_id) {
         Some(dir) => {
             let trimmed = dir.trim();
@@ -43,12 +237,38 @@ fn project_root_for(session_id: &str) -> Result<PathBuf, String> {
     }
 }
 
-fn checkpoints_dir(session_id: &str) -> Result<PathBuf, String> {
-    let base = project_root_for(session_id)?;
-    Ok(base
-        .join(".conductor")
-        .join("hartford")
-        .join(".checkpoints"))
+/// A key of the `checkpoints` settings section, from `projects.<dir>` if set
+/// there, else from the top level.
+pub(crate) fn checkpoint_setting(project_dir: &Path, key: &str) -> Option<serde_json::Value> {
+    let settings = crate::load_settings().ok()?;
+    let lookup = |section: Option<&serde_json::Value>| {
+        section
+            .and_then(|s| s.get("checkpoints"))
+            .and_then(|c| c.get(key))
+            .cloned()
+    };
+    let project = settings
+        .get("projects")
+        .and_then(|p| p.get(project_dir.to_string_lossy().as_ref()));
+    lookup(project).or_else(|| lookup(Some(&settings)))
+}
+
+/// The directory the session's new checkpoints are written to.
+pub(crate) fn checkpoints_dir(session_id: &str) -> Result<PathBuf, String> {
+    checkpoint_location::active_root(&project_root_for(session_id)?)
+}
+
+/// The store root and directory of a checkpoint kept outside git, looking in
+/// every layout the project has checkpoints in.
+fn stored_checkpoint(session_id: &str, checkpoint_id: &str) -> Option<(PathBuf, PathBuf)> {
+    let project_dir = project_root_for(session_id).ok()?;
+    checkpoint_location::roots(&project_dir)
+        .into_iter()
+        .map(|root| {
+            let dir = root.join(checkpoint_id);
+            (root, dir)
+        })
+        .find(|(_, dir)| dir.is_dir())
 }
 
 fn ensure_checkpoints_dir(session_id: &str) -> Result<PathBuf, String> {
@@ -64,21 +284,93 @@ fn checkpoint_dir(session_id: &str, checkpoint_id: &str) -> Result<PathBuf, Stri
     Ok(ensure_checkpoints_dir(session_id)?.join(checkpoint_id))
 }
 
-fn resolve_target_path(base: &Path, file_path: &str) -> PathBuf {
-    let rel = Path::new(file_path);
-    if rel.is_absolute() {
-        rel.strip_prefix(base)
-            .map(|p| base.join(p))
-            .unwrap_or_else(|_| {
-                rel.file_name()
-                    .map(|name| base.join(name))
-                    .unwrap_or_else(|| base.to_path_buf())
-            })
-    } else {
-        base.join(rel)
+// Each session's head checkpoint, keyed by session id.
+pub(crate) const HEADS_FILE: &str = "heads.json";
+
+pub(crate) fn read_heads(dir: &Path) -> HashMap<String, String> {
+    fs::read_to_string(dir.join(HEADS_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn write_heads(dir: &Path, heads: &HashMap<String, String>) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(heads)
+        .map_err(|e| format!("Failed to serialize checkpoint heads: {}", e))?;
+    fs::write(dir.join(HEADS_FILE), json)
+        .map_err(|e| format!("Failed to write checkpoint heads: {}", e))
+}
+
+fn checkpoint_head(session_id: &str) -> Option<String> {
+    let project_dir = project_root_for(session_id).ok()?;
+    checkpoint_location::roots(&project_dir)
+        .iter()
+        .find_map(|root| read_heads(root).remove(session_id))
+}
+
+fn set_checkpoint_head(session_id: &str, checkpoint_id: Option<&str>) {
+    let result = ensure_checkpoints_dir(session_id).and_then(|dir| {
+        let mut heads = read_heads(&dir);
+        match checkpoint_id {
+            Some(id) => heads.insert(session_id.to_string(), id.to_string()),
+            None => heads.remove(session_id),
+        };
+        write_heads(&dir, &heads)
+    });
+    if let Err(e) = result {
+        eprintln!("[RUST] Failed to move checkpoint head: {}", e);
     }
 }
 
+/// The git store of the session's project, for reading checkpoints kept there.
+fn git_checkpoints(session_id: &str) -> Option<GitCheckpoints> {
+    GitCheckpoints::open(&project_root_for(session_id).ok()?)
+}
+
+pub(crate) fn read_metadata(
+    session_id: &str,
+    checkpoint_id: &str,
+) -> Result<CheckpointMetadata, String> {
+    let Some((_, checkpoint_dir)) = stored_checkpoint(session_id, checkpoint_id) else {
+        return git_checkpoints(session_id)
+            .map(|git| git.metadata(checkpoint_id))
+            .transpose()?
+            .flatten()
+            .ok_or_else(|| format!("Checkpoint {} not found", checkpoint_id));
+    };
+    let metadata_path = checkpoint_dir.join("metadata.json");
+    let metadata_json = fs::read_to_string(&metadata_path)
+        .map_err(|e| format!("Failed to read metadata: {}", e))?;
+    serde_json::from_str(&metadata_json).map_err(|e| format!("Failed to parse metadata: {}", e))
+}
+
+fn write_metadata(session_id: &str, metadata: &CheckpointMetadata) -> Result<(), String> {
+    let Some((_, checkpoint_dir)) = stored_checkpoint(session_id, &metadata.id) else {
+        if let Some(git) = git_checkpoints(session_id) {
+            if git.update_metadata(metadata)? {
+                checkpoint_index::record_updated(session_id, metadata);
+                return Ok(());
+            }
+        }
+        return Err(format!("Checkpoint {} not found", metadata.id));
+    };
+    let metadata_path = checkpoint_dir.join("metadata.json");
+    let metadata_json = serde_json::to_string_pretty(metadata)
+        .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+    fs::write(metadata_path, metadata_json)
+        .map_err(|e| format!("Failed to write metadata: {}", e))?;
+    checkpoint_index::record_updated(session_id, metadata);
+    Ok(())
+}
+
+pub(crate) fn new_checkpoint_id() -> String {
+    format!(
+        "cp_{}_{}",
+        Utc::now().timestamp_millis(),
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    )
+}
+
 #[command]
 pub async fn save_checkpoint_files(
     session_id: String,
@@ -86,70 +378,310 @@ pub async fn save_checkpoint_files(
     files: Vec<FileSnapshot>,
     trigger: Option<String>,
 ) -> Result<(), String> {
-    let checkpoint_dir = checkpoint_dir(&session_id, &checkpoint_id)?;
-
-    fs::create_dir_all(&checkpoint_dir)
-        .map_err(|e| format!("Failed to create checkpoint directory: {}", e))?;
+    store_checkpoint(
+        &session_id,
+        &checkpoint_id,
+        &files,
+        trigger,
+        None,
+        "auto",
+        Vec::new(),
+    )
+    .map(|_| ())
+}
 
-    let git_base = project_root_for(&session_id).ok();
+/// Writes a checkpoint on top of the session's head and makes it the new head.
+pub(crate) fn store_checkpoint(
+    session_id: &str,
+    checkpoint_id: &str,
+    files: &[FileSnapshot],
+    trigger: Option<String>,
+    name: Option<String>,
+    checkpoint_type: &str,
+    tags: Vec<String>,
+) -> Result<CheckpointMetadata, String> {
+    let git_base = project_root_for(session_id).ok();
 
     let metadata = CheckpointMetadata {
-        id: checkpoint_id.clone(),
+        id: checkpoint_id.to_string(),
         timestamp: Utc::now(),
-        name: None,
-        checkpoint_type: "auto".to_string(),
+        name,
+        checkpoint_type: checkpoint_type.to_string(),
         trigger,
         file_count: files.len(),
         git_branch: git_base.as_ref().and_then(|base| get_git_branch(base).ok()),
         git_commit: git_base.as_ref().and_then(|base| get_git_commit(base).ok()),
+        tags,
+        parent_id: checkpoint_head(session_id),
+        imported: None,
     };
 
-    let metadata_path = checkpoint_dir.join("metadata.json");
-    let metadata_json = serde_json::to_string_pretty(&metadata)
-        .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
-    fs::write(metadata_path, metadata_json)
-        .map_err(|e| format!("Failed to write metadata: {}", e))?;
+    write_checkpoint(session_id, &metadata, files)?;
+    set_checkpoint_head(session_id, Some(checkpoint_id));
 
-    let files_dir = checkpoint_dir.join("files");
-    fs::create_dir_all(&files_dir)
-        .map_err(|e| format!("Failed to create files directory: {}", e))?;
+    crate::audit::record_checkpoint(
+        session_id,
+        checkpoint_id,
+        metadata.trigger.as_deref(),
+        files.iter().map(|f| f.path.clone()).collect(),
+    );
 
-    for (index, file) in files.iter().enumerate() {
-        let safe_name = format!("file_{}.json", index);
-        let file_path = files_dir.join(safe_name);
+    Ok(metadata)
+}
 
-        let file_json = serde_json::to_string_pretty(&file)
-            .map_err(|e| format!("Failed to serialize file snapshot: {}", e))?;
-        fs::write(file_path, file_json)
-            .map_err(|e| format!("Failed to write file snapshot: {}", e))?;
+/// Stores a checkpoint under its metadata as given, in the project's git store
+/// when that is enabled and in the checkpoints directory otherwise.
+pub(crate) fn write_checkpoint(
+    session_id: &str,
+    metadata: &CheckpointMetadata,
+    files: &[FileSnapshot],
+) -> Result<(), String> {
+    let base = project_root_for(session_id)?;
+    match GitCheckpoints::for_writing(&base) {
+        Some(git) => git.save(session_id, metadata, files)?,
+        None => store_checkpoint_files(session_id, metadata, files)?,
+    }
+    checkpoint_index::record_saved(
+        session_id,
+        metadata,
+        files.iter().map(|f| f.path.clone()).collect(),
+    );
+    Ok(())
+}
 
-        let content_name = format!("content_{}.txt", index);
-        let content_path = files_dir.join(content_name);
-        fs::write(content_path, &file.current_content)
-            .map_err(|e| format!("Failed to write file content: {}", e))?;
+fn store_checkpoint_files(
+    session_id: &str,
+    metadata: &CheckpointMetadata,
+    files: &[FileSnapshot],
+) -> Result<(), String> {
+    let checkpoint_dir = checkpoint_dir(session_id, &metadata.id)?;
+    fs::create_dir_all(&checkpoint_dir)
+        .map_err(|e| format!("Failed to create checkpoint directory: {}", e))?;
+
+    // Blobs first, so a manifest never points at content that is not stored.
+    let store_root = checkpoints_dir(session_id)?;
+    let mut entries = Vec::with_capacity(files.len());
+    for file in files {
+        let blob = |data: Option<&[u8]>| {
+            data.map(|bytes| checkpoint_store::write_blob(&store_root, bytes))
+                .transpose()
+        };
+        entries.push(ManifestEntry {
+            path: file.path.clone(),
+            original: blob(file.original_data())?,
+            current: blob(file.current_data())?,
+            original_mode: file.original_mode,
+            current_mode: file.current_mode,
+        });
     }
+    checkpoint_store::write_manifest(&checkpoint_dir, &Manifest::new(entries))?;
+    write_metadata(session_id, metadata)
+}
 
-    let mapping: HashMap<String, usize> = files
-        .iter()
-        .enumerate()
-        .map(|(i, f)| (f.path.clone(), i))
-        .collect();
+/// Saves the given files (by default every file the project's checkpoints
+/// track) as they are now, under a name.
+#[command]
+pub async fn create_checkpoint(
+    session_id: String,
+    name: String,
+    files: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+) -> Result<CheckpointMetadata, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Checkpoint name cannot be empty".to_string());
+    }
+    let paths = match files {
+        Some(files) => files,
+        None => {
+            let mut paths: Vec<String> = read_checkpoint_list(&session_id)
+                .iter()
+                .filter_map(|metadata| load_snapshots(&session_id, &metadata.id).ok())
+                .flatten()
+                .map(|snapshot| snapshot.path)
+                .collect();
+            paths.sort();
+            paths.dedup();
+            paths
+        }
+    };
 
-    let mapping_path = checkpoint_dir.join("file_mapping.json");
-    let mapping_json = serde_json::to_string_pretty(&mapping)
-        .map_err(|e| format!("Failed to serialize file mapping: {}", e))?;
-    fs::write(mapping_path, mapping_json)
-        .map_err(|e| format!("Failed to write file mapping: {}", e))?;
+    let base = project_root_for(&session_id)?;
+    let mut snapshots = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = CheckpointPath::resolve(&base, &path)?;
+        let state = FileState::read(path.absolute())?;
+        snapshots.push(FileSnapshot::from_states(path.key(), state.clone(), state));
+    }
+    store_checkpoint(
+        &session_id,
+        &new_checkpoint_id(),
+        &snapshots,
+        None,
+        Some(name.to_string()),
+        "manual",
+        tags.unwrap_or_default(),
+    )
+}
 
-    Ok(())
+/// Sets or, with an empty name, clears a checkpoint's name.
+#[command]
+pub async fn rename_checkpoint(
+    session_id: String,
+    checkpoint_id: String,
+    name: Option<String>,
+) -> Result<CheckpointMetadata, String> {
+    let mut metadata = read_metadata(&session_id, &checkpoint_id)?;
+    metadata.name = name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    write_metadata(&session_id, &metadata)?;
+    Ok(metadata)
 }
 
 #[command]
-pub async fn restore_checkpoint(session_id: String, checkpoint_id: String) -> Result<(), String> {
-    let checkpoint_dir = checkpoint_dir(&session_id, &checkpoint_id)?;
+pub async fn tag_checkpoint(
+    session_id: String,
+    checkpoint_id: String,
+    tag: String,
+) -> Result<CheckpointMetadata, String> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Err("Tag cannot be empty".to_string());
+    }
+    let mut metadata = read_metadata(&session_id, &checkpoint_id)?;
+    if !metadata.tags.iter().any(|t| t == tag) {
+        metadata.tags.push(tag.to_string());
+        write_metadata(&session_id, &metadata)?;
+    }
+    Ok(metadata)
+}
+
+#[command]
+pub async fn untag_checkpoint(
+    session_id: String,
+    checkpoint_id: String,
+    tag: String,
+) -> Result<CheckpointMetadata, String> {
+    let mut metadata = read_metadata(&session_id, &checkpoint_id)?;
+    metadata.tags.retain(|t| t != &tag);
+    write_metadata(&session_id, &metadata)?;
+    Ok(metadata)
+}
 
-    if !checkpoint_dir.exists() {
-        return Err(format!("Checkpoint {} not found", checkpoint_id));
+/// Every checkpoint with its children, and the session's head.
+#[command]
+pub async fn get_checkpoint_tree(session_id: String) -> Result<CheckpointTree, String> {
+    let checkpoints = read_checkpoint_list(&session_id);
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    // Oldest first, so children are listed in the order they were taken.
+    for metadata in checkpoints.iter().rev() {
+        if let Some(parent) = &metadata.parent_id {
+            children
+                .entry(parent.clone())
+                .or_default()
+                .push(metadata.id.clone());
+        }
+    }
+    Ok(CheckpointTree {
+        head: checkpoint_head(&session_id),
+        checkpoints: checkpoints
+            .into_iter()
+            .map(|metadata| CheckpointNode {
+                children: children.remove(&metadata.id).unwrap_or_default(),
+                metadata,
+            })
+            .collect(),
+    })
+}
+
+/// Moves the head after restoring a whole checkpoint: to the checkpoint itself
+/// for its current side, to its parent for its original side.
+fn move_head_after_restore(session_id: &str, checkpoint_id: &str, mode: &str) {
+    if mode == "current" {
+        set_checkpoint_head(session_id, Some(checkpoint_id));
+    } else if let Ok(metadata) = read_metadata(session_id, checkpoint_id) {
+        set_checkpoint_head(session_id, metadata.parent_id.as_deref());
+    }
+}
+
+/// Points the children and heads of a checkpoint about to be deleted at its
+/// parent, so the tree stays connected.
+fn unlink_checkpoint(session_id: &str, checkpoint_id: &str) {
+    let parent = read_metadata(session_id, checkpoint_id)
+        .ok()
+        .and_then(|m| m.parent_id);
+    for mut metadata in read_checkpoint_list(session_id) {
+        if metadata.parent_id.as_deref() == Some(checkpoint_id) {
+            metadata.parent_id = parent.clone();
+            if let Err(e) = write_metadata(session_id, &metadata) {
+                eprintln!(
+                    "[RUST] Failed to reparent checkpoint {}: {}",
+                    metadata.id, e
+                );
+            }
+        }
+    }
+    let roots = project_root_for(session_id)
+        .map(|project_dir| checkpoint_location::roots(&project_dir))
+        .unwrap_or_default();
+    for dir in roots {
+        let heads = read_heads(&dir);
+        if !heads.values().any(|head| head == checkpoint_id) {
+            continue;
+        }
+        let heads: HashMap<String, String> = heads
+            .into_iter()
+            .filter_map(|(session, head)| {
+                if head == checkpoint_id {
+                    parent.clone().map(|parent| (session, parent))
+                } else {
+                    Some((session, head))
+                }
+            })
+            .collect();
+        if let Err(e) = write_heads(&dir, &heads) {
+            eprintln!("[RUST] Failed to update checkpoint heads: {}", e);
+        }
+    }
+}
+
+/// Loads every file of a checkpoint, from its manifest, from the project's git
+/// store or, for checkpoints saved before the blob store, from the per-file
+/// JSON snapshots.
+pub(crate) fn load_snapshots(
+    session_id: &str,
+    checkpoint_id: &str,
+) -> Result<Vec<FileSnapshot>, String> {
+    let Some((store_root, checkpoint_dir)) = stored_checkpoint(session_id, checkpoint_id) else {
+        return git_checkpoints(session_id)
+            .map(|git| git.snapshots(checkpoint_id))
+            .transpose()?
+            .flatten()
+            .ok_or_else(|| format!("Checkpoint {} not found", checkpoint_id));
+    };
+
+    if let Some(manifest) = checkpoint_store::read_manifest(&checkpoint_dir)? {
+        return manifest
+            .files
+            .into_iter()
+            .map(|entry| {
+                let side =
+                    |hash: &Option<String>, mode: Option<u32>| -> Result<FileState, String> {
+                        Ok(match hash {
+                            Some(hash) => FileState {
+                                exists: true,
+                                bytes: checkpoint_store::read_blob(&store_root, hash)?,
+                                mode,
+                            },
+                            None => FileState::default(),
+                        })
+                    };
+                let original = side(&entry.original, entry.original_mode)?;
+                let current = side(&entry.current, entry.current_mode)?;
+                let mut snapshot = FileSnapshot::from_states(entry.path, original, current);
+                snapshot.checksum = entry.current;
+                Ok(snapshot)
+            })
+            .collect();
     }
 
     let mapping_path = checkpoint_dir.join("file_mapping.json");
@@ -157,28 +689,398 @@ pub async fn restore_checkpoint(session_id: String, checkpoint_id: String) -> Re
         .map_err(|e| format!("Failed to read file mapping: {}", e))?;
     let mapping: HashMap<String, usize> = serde_json::from_str(&mapping_json)
         .map_err(|e| format!("Failed to parse file mapping: {}", e))?;
+    let mut indexes: Vec<usize> = mapping.values().copied().collect();
+    indexes.sort_unstable();
 
     let files_dir = checkpoint_dir.join("files");
-    let project_base = project_root_for(&session_id)?;
-    for (file_path, index) in mapping.iter() {
-        let snapshot_path = files_dir.join(format!("file_{}.json", index));
-        let snapshot_json = fs::read_to_string(&snapshot_path)
-            .map_err(|e| format!("Failed to read file snapshot: {}", e))?;
-        let snapshot: FileSnapshot = serde_json::from_str(&snapshot_json)
-            .map_err(|e| format!("Failed to parse file snapshot: {}", e))?;
-
-        let target_path = resolve_target_path(&project_base, file_path);
-
-        if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create parent directory: {}", e))?;
+    indexes
+        .into_iter()
+        .map(|index| {
+            let snapshot_path = files_dir.join(format!("file_{}.json", index));
+            let snapshot_json = fs::read_to_string(&snapshot_path)
+                .map_err(|e| format!("Failed to read file snapshot: {}", e))?;
+            serde_json::from_str(&snapshot_json)
+                .map_err(|e| format!("Failed to parse file snapshot: {}", e))
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RestoreReport {
+    /// Files written or removed; files already in the restored state are not
+    /// touched.
+    pub restored: Vec<String>,
+    /// Files edited since the checkpoint, whatever was done with them.
+    pub conflicts: Vec<String>,
+    /// Conflicting files left as they are (`on_conflict == "skip"`).
+    pub skipped: Vec<String>,
+    /// `.orig` copies written before overwriting (`on_conflict == "backup"`).
+    pub backups: Vec<String>,
+    /// Checkpoint of the restored files as they were, taken before any of them
+    /// was replaced. Absent when nothing needed restoring.
+    pub pre_restore_checkpoint: Option<String>,
+}
+
+/// Whether the file on disk was changed since the checkpoint by something
+/// other than the agent: it matches neither side the checkpoint recorded nor
+/// the state the newest checkpoint of that file left it in.
+fn modified_since(session_id: &str, base: &Path, snapshot: &FileSnapshot) -> Result<bool, String> {
+    let disk = FileState::read(CheckpointPath::resolve(base, &snapshot.path)?.absolute())?;
+    Ok(changed_outside(&disk, snapshot, || {
+        latest_snapshot_of(session_id, &snapshot.path)
+    }))
+}
+
+/// `modified_since` for the file as found on `disk`. `latest` looks up the
+/// newest checkpointed snapshot of the file, only when the sides differ.
+fn changed_outside(
+    disk: &FileState,
+    snapshot: &FileSnapshot,
+    latest: impl FnOnce() -> Option<FileSnapshot>,
+) -> bool {
+    let disk_hash = disk.exists.then(|| checkpoint_store::hash(&disk.bytes));
+    let recorded = match &snapshot.checksum {
+        Some(checksum) if snapshot.current_exists => Some(checksum.clone()),
+        _ => snapshot.current_data().map(checkpoint_store::hash),
+    };
+    if disk_hash == recorded || disk_hash == snapshot.original_data().map(checkpoint_store::hash) {
+        return false;
+    }
+    latest().map_or(true, |latest| {
+        disk_hash != latest.current_data().map(checkpoint_store::hash)
+    })
+}
+
+fn find_conflicts(session_id: &str, snapshots: &[FileSnapshot]) -> Result<Vec<String>, String> {
+    let base = project_root_for(session_id)?;
+    let mut conflicts = Vec::new();
+    for snapshot in snapshots {
+        if modified_since(session_id, &base, snapshot)? {
+            conflicts.push(snapshot.path.clone());
+        }
+    }
+    Ok(conflicts)
+}
+
+/// One file a restore replaces: its state before and after, and where the new
+/// contents are staged until they are renamed into place.
+struct StagedWrite {
+    path: String,
+    target: PathBuf,
+    before: FileState,
+    after: FileState,
+    staged: Option<PathBuf>,
+}
+
+/// The writes of one restore. Everything is staged first and only then renamed
+/// into place, so a failure at any point can put every file back.
+#[derive(Default)]
+pub(crate) struct RestoreTransaction {
+    writes: Vec<StagedWrite>,
+    /// How many of `writes` are in place.
+    committed: usize,
+    /// Directories created for restored files, deepest first.
+    created_dirs: Vec<PathBuf>,
+    backups: Vec<PathBuf>,
+}
+
+/// A temp file next to `target`, so the rename stays on one filesystem.
+fn staging_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(
+        ".{}.{}.restore",
+        name,
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    ))
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+impl RestoreTransaction {
+    fn create_parent(&mut self, target: &Path) -> Result<(), String> {
+        let Some(parent) = target.parent() else {
+            return Ok(());
+        };
+        let missing: Vec<PathBuf> = parent
+            .ancestors()
+            .take_while(|dir| !dir.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create parent directory: {}", e))?;
+        self.created_dirs.extend(missing);
+        Ok(())
+    }
+
+    fn backup(&mut self, target: &Path) -> Result<(), String> {
+        let mut backup = target.to_path_buf().into_os_string();
+        backup.push(".orig");
+        let backup = PathBuf::from(backup);
+        fs::copy(target, &backup)
+            .map_err(|e| format!("Failed to back up {}: {}", target.display(), e))?;
+        self.backups.push(backup);
+        Ok(())
+    }
+
+    pub(crate) fn stage(
+        &mut self,
+        path: String,
+        target: PathBuf,
+        after: FileState,
+    ) -> Result<(), String> {
+        let before = FileState::read(&target)?;
+        let mut write = StagedWrite {
+            path,
+            target,
+            before,
+            after,
+            staged: None,
+        };
+        if !write.after.exists {
+            self.writes.push(write);
+            return Ok(());
         }
+        self.create_parent(&write.target)?;
+        let staged = staging_path(&write.target);
+        write.staged = Some(staged.clone());
+        let path = write.path.clone();
+        let mode = write.after.mode.or(write.before.mode);
+        let data = write.after.bytes.clone();
+        // Recorded before writing, so a partly written temp file is cleaned up.
+        self.writes.push(write);
+        fs::write(&staged, data).map_err(|e| format!("Failed to stage {}: {}", path, e))?;
+        if let Some(mode) = mode {
+            set_file_mode(&staged, mode)?;
+        }
+        Ok(())
+    }
 
-        fs::write(&target_path, &snapshot.original_content)
-            .map_err(|e| format!("Failed to restore file {}: {}", file_path, e))?;
+    fn commit(&mut self) -> Result<(), String> {
+        while let Some(write) = self.writes.get(self.committed) {
+            match &write.staged {
+                Some(staged) => fs::rename(staged, &write.target)
+                    .map_err(|e| format!("Failed to restore file {}: {}", write.path, e))?,
+                None => remove_if_exists(&write.target)
+                    .map_err(|e| format!("Failed to remove file {}: {}", write.path, e))?,
+            }
+            self.committed += 1;
+        }
+        Ok(())
     }
 
-    Ok(())
+    /// Puts back every file already replaced and removes what the restore
+    /// created. Returns the files that could not be put back.
+    fn rollback(&mut self) -> Vec<String> {
+        let mut failed = Vec::new();
+        for write in self.writes[..self.committed].iter().rev() {
+            let result = if write.before.exists {
+                fs::write(&write.target, &write.before.bytes)
+                    .map_err(|e| e.to_string())
+                    .and_then(|()| match write.before.mode {
+                        Some(mode) => set_file_mode(&write.target, mode),
+                        None => Ok(()),
+                    })
+            } else {
+                remove_if_exists(&write.target).map_err(|e| e.to_string())
+            };
+            if let Err(e) = result {
+                eprintln!("[RUST] Failed to roll back {}: {}", write.path, e);
+                failed.push(write.path.clone());
+            }
+        }
+        for write in &self.writes[self.committed..] {
+            if let Some(staged) = &write.staged {
+                let _ = remove_if_exists(staged);
+            }
+        }
+        for backup in &self.backups {
+            let _ = remove_if_exists(backup);
+        }
+        for dir in &self.created_dirs {
+            // Only empties; anything else put there since is left alone.
+            let _ = fs::remove_dir(dir);
+        }
+        failed
+    }
+
+    pub(crate) fn touched(&self) -> Vec<String> {
+        self.writes[..self.committed]
+            .iter()
+            .map(|w| w.path.clone())
+            .collect()
+    }
+}
+
+/// Puts the original (or, with `mode == "current"`, the current) side of
+/// `snapshots` back into the project. Files that did not exist on that side
+/// are removed.
+///
+/// Files edited since the checkpoint are handled by `on_conflict`: `"skip"`
+/// leaves them alone, `"force"` overwrites them, `"backup"` copies them to
+/// `<file>.orig` first, and anything else fails the restore before any file
+/// is written.
+///
+/// The restore is all or nothing: the new contents are staged, a
+/// `restore:<id>` checkpoint of the files as they are is taken, and only then
+/// is everything renamed into place. On failure every file touched is put
+/// back and the pre-restore checkpoint is dropped again.
+fn write_snapshots(
+    session_id: &str,
+    checkpoint_id: &str,
+    snapshots: &[FileSnapshot],
+    mode: &str,
+    on_conflict: Option<&str>,
+) -> Result<RestoreReport, String> {
+    let project_base = project_root_for(session_id)?;
+    let conflicts = find_conflicts(session_id, snapshots)?;
+    let on_conflict = on_conflict.unwrap_or("abort");
+    if !conflicts.is_empty() && !matches!(on_conflict, "skip" | "force" | "backup") {
+        return Err(format!(
+            "Restore would overwrite changes made since the checkpoint: {}",
+            conflicts.join(", ")
+        ));
+    }
+
+    let mut report = RestoreReport::default();
+    let mut restore = RestoreTransaction::default();
+    let mut stage_all = || -> Result<(), String> {
+        for snapshot in snapshots {
+            let target_path = CheckpointPath::resolve(&project_base, &snapshot.path)?
+                .absolute()
+                .to_path_buf();
+            if conflicts.contains(&snapshot.path) {
+                if on_conflict == "skip" {
+                    report.skipped.push(snapshot.path.clone());
+                    continue;
+                }
+                if on_conflict == "backup" && target_path.exists() {
+                    restore.backup(&target_path)?;
+                    report.backups.push(format!("{}.orig", snapshot.path));
+                }
+            }
+            let after = if mode == "current" {
+                snapshot.current_state()
+            } else {
+                snapshot.original_state()
+            };
+            let before = FileState::read(&target_path)?;
+            let unchanged = before.exists == after.exists
+                && before.bytes == after.bytes
+                && after.mode.map_or(true, |m| before.mode == Some(m));
+            if !unchanged {
+                restore.stage(snapshot.path.clone(), target_path, after)?;
+            }
+        }
+        Ok(())
+    };
+    let staged = stage_all();
+    report.pre_restore_checkpoint =
+        finish_restore(session_id, checkpoint_id, &mut restore, staged)?;
+    report.restored = restore.touched();
+    report.conflicts = conflicts;
+    Ok(report)
+}
+
+/// Completes a restore whose writes were staged in `restore` (`staged` being
+/// how that went): takes a `restore:<id>` checkpoint of the files as they are,
+/// then renames everything into place. On failure every file touched is put
+/// back and the pre-restore checkpoint is dropped again. Returns the
+/// pre-restore checkpoint, if anything needed writing.
+pub(crate) fn finish_restore(
+    session_id: &str,
+    checkpoint_id: &str,
+    restore: &mut RestoreTransaction,
+    staged: Result<(), String>,
+) -> Result<Option<String>, String> {
+    let mut result = staged;
+    let mut pre_restore = None;
+    if result.is_ok() && !restore.writes.is_empty() {
+        let files: Vec<FileSnapshot> = restore
+            .writes
+            .iter()
+            .map(|w| FileSnapshot::from_states(w.path.clone(), w.before.clone(), w.after.clone()))
+            .collect();
+        result = store_checkpoint(
+            session_id,
+            &new_checkpoint_id(),
+            &files,
+            Some(format!("restore:{}", checkpoint_id)),
+            None,
+            "auto",
+            vec![],
+        )
+        .map(|metadata| pre_restore = Some(metadata.id));
+    }
+    if result.is_ok() {
+        result = restore.commit();
+    }
+
+    if let Err(e) = result {
+        let touched = restore.touched();
+        let failed = restore.rollback();
+        if let Some(id) = pre_restore {
+            if let Err(e) = remove_checkpoint(session_id, &id) {
+                eprintln!(
+                    "[RUST] Failed to remove pre-restore checkpoint {}: {}",
+                    id, e
+                );
+            }
+        }
+        let mut message = format!("Restore failed, nothing was changed: {}", e);
+        if !touched.is_empty() {
+            message = format!(
+                "Restore failed and was rolled back: {} (files put back: {})",
+                e,
+                touched.join(", ")
+            );
+        }
+        if !failed.is_empty() {
+            message.push_str(&format!("; could not put back: {}", failed.join(", ")));
+        }
+        return Err(message);
+    }
+    Ok(pre_restore)
+}
+
+#[command]
+pub async fn restore_checkpoint(
+    session_id: String,
+    checkpoint_id: String,
+    on_conflict: Option<String>,
+) -> Result<RestoreReport, String> {
+    let snapshots = load_snapshots(&session_id, &checkpoint_id)?;
+    let report = write_snapshots(
+        &session_id,
+        &checkpoint_id,
+        &snapshots,
+        "original",
+        on_conflict.as_deref(),
+    )?;
+    move_head_after_restore(&session_id, &checkpoint_id, "original");
+    Ok(report)
+}
+
+/// Files of the checkpoint (or just `files`) edited since it was taken, which
+/// a restore would overwrite.
+#[command]
+pub async fn check_checkpoint_conflicts(
+    session_id: String,
+    checkpoint_id: String,
+    files: Option<Vec<String>>,
+) -> Result<Vec<String>, String> {
+    let mut snapshots = load_snapshots(&session_id, &checkpoint_id)?;
+    if let Some(files) = files {
+        snapshots.retain(|s| files.contains(&s.path));
+    }
+    find_conflicts(&session_id, &snapshots)
 }
 
 #[derive(Serialize)]
@@ -186,6 +1088,10 @@ pub struct CheckpointFileData {
     pub path: String,
     pub original_content: String,
     pub current_content: String,
+    pub original_exists: bool,
+    pub current_exists: bool,
+    /// True when either side is not valid UTF-8; the text fields are then lossy.
+    pub binary: bool,
 }
 
 #[tauri::command]
@@ -194,32 +1100,22 @@ pub async fn get_checkpoint_file(
     checkpoint_id: String,
     file_path: String,
 ) -> Result<CheckpointFileData, String> {
-    let checkpoint_dir = checkpoint_dir(&session_id, &checkpoint_id)?;
-    if !checkpoint_dir.exists() {
-        return Err(format!("Checkpoint {} not found", checkpoint_id));
-    }
-
-    let mapping_path = checkpoint_dir.join("file_mapping.json");
-    let mapping_json = fs::read_to_string(&mapping_path)
-        .map_err(|e| format!("Failed to read file mapping: {}", e))?;
-    let mapping: HashMap<String, usize> = serde_json::from_str(&mapping_json)
-        .map_err(|e| format!("Failed to parse file mapping: {}", e))?;
-
-    let index = mapping
-        .get(&file_path)
+    let snapshot = load_snapshots(&session_id, &checkpoint_id)?
+        .into_iter()
+        .find(|s| s.path == file_path)
         .ok_or_else(|| format!("File not found in checkpoint: {}", file_path))?;
 
-    let files_dir = checkpoint_dir.join("files");
-    let snapshot_path = files_dir.join(format!("file_{}.json", index));
-    let snapshot_json = fs::read_to_string(&snapshot_path)
-        .map_err(|e| format!("Failed to read file snapshot: {}", e))?;
-    let snapshot: FileSnapshot = serde_json::from_str(&snapshot_json)
-        .map_err(|e| format!("Failed to parse file snapshot: {}", e))?;
-
+    let binary = [snapshot.original_data(), snapshot.current_data()]
+        .into_iter()
+        .flatten()
+        .any(|data| std::str::from_utf8(data).is_err());
     Ok(CheckpointFileData {
         path: snapshot.path,
         original_content: snapshot.original_content,
         current_content: snapshot.current_content,
+        original_exists: snapshot.original_exists,
+        current_exists: snapshot.current_exists,
+        binary,
     })
 }
 
@@ -228,43 +1124,18 @@ pub async fn restore_checkpoint_with_mode(
     session_id: String,
     checkpoint_id: String,
     mode: String,
-) -> Result<(), String> {
-    let checkpoint_dir = checkpoint_dir(&session_id, &checkpoint_id)?;
-    if !checkpoint_dir.exists() {
-        return Err(format!("Checkpoint {} not found", checkpoint_id));
-    }
-
-    let mapping_path = checkpoint_dir.join("file_mapping.json");
-    let mapping_json = fs::read_to_string(&mapping_path)
-        .map_err(|e| format!("Failed to read file mapping: {}", e))?;
-    let mapping: HashMap<String, usize> = serde_json::from_str(&mapping_json)
-        .map_err(|e| format!("Failed to parse file mapping: {}", e))?;
-
-    let project_base = project_root_for(&session_id)?;
-    let files_dir = checkpoint_dir.join("files");
-    for (file_path, index) in mapping.iter() {
-        let snapshot_path = files_dir.join(format!("file_{}.json", index));
-        let snapshot_json = fs::read_to_string(&snapshot_path)
-            .map_err(|e| format!("Failed to read file snapshot: {}", e))?;
-        let snapshot: FileSnapshot = serde_json::from_str(&snapshot_json)
-            .map_err(|e| format!("Failed to parse file snapshot: {}", e))?;
-
-        let target_path = resolve_target_path(&project_base, file_path);
-
-        if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create parent directory: {}", e))?;
-        }
-
-        let content = if mode == "current" {
-            &snapshot.current_content
-        } else {
-            &snapshot.original_content
-        };
-        fs::write(&target_path, content)
-            .map_err(|e| format!("Failed to restore file {}: {}", file_path, e))?;
-    }
-    Ok(())
+    on_conflict: Option<String>,
+) -> Result<RestoreReport, String> {
+    let snapshots = load_snapshots(&session_id, &checkpoint_id)?;
+    let report = write_snapshots(
+        &session_id,
+        &checkpoint_id,
+        &snapshots,
+        &mode,
+        on_conflict.as_deref(),
+    )?;
+    move_head_after_restore(&session_id, &checkpoint_id, &mode);
+    Ok(report)
 }
 
 #[tauri::command]
@@ -273,81 +1144,70 @@ pub async fn restore_checkpoint_files(
     checkpoint_id: String,
     files: Vec<String>,
     mode: String,
-) -> Result<(), String> {
-    let checkpoint_dir = checkpoint_dir(&session_id, &checkpoint_id)?;
-    if !checkpoint_dir.exists() {
-        return Err(format!("Checkpoint {} not found", checkpoint_id));
-    }
-
-    let mapping_path = checkpoint_dir.join("file_mapping.json");
-    let mapping_json = fs::read_to_string(&mapping_path)
-        .map_err(|e| format!("Failed to read file mapping: {}", e))?;
-    let mapping: HashMap<String, usize> = serde_json::from_str(&mapping_json)
-        .map_err(|e| format!("Failed to parse file mapping: {}", e))?;
-
-    let project_base = project_root_for(&session_id)?;
-    let files_dir = checkpoint_dir.join("files");
-    for file_path in files.iter() {
-        if let Some(index) = mapping.get(file_path) {
-            let snapshot_path = files_dir.join(format!("file_{}.json", index));
-            let snapshot_json = fs::read_to_string(&snapshot_path)
-                .map_err(|e| format!("Failed to read file snapshot: {}", e))?;
-            let snapshot: FileSnapshot = serde_json::from_str(&snapshot_json)
-                .map_err(|e| format!("Failed to parse file snapshot: {}", e))?;
-
-            let target_path = resolve_target_path(&project_base, file_path);
-
-            if let Some(parent) = target_path.parent() {
-                fs::create_dir_all(parent)
-                    .map_err(|e| format!("Failed to create parent directory: {}", e))?;
-            }
-
-            let content = if mode == "current" {
-                &snapshot.current_content
-            } else {
-                &snapshot.original_content
-            };
-            fs::write(&target_path, content)
-                .map_err(|e| format!("Failed to restore file {}: {}", file_path, e))?;
-        } else {
-            return Err(format!("File not found in checkpoint: {}", file_path));
-        }
+    on_conflict: Option<String>,
+) -> Result<RestoreReport, String> {
+    let mut snapshots = load_snapshots(&session_id, &checkpoint_id)?;
+    if let Some(missing) = files
+        .iter()
+        .find(|f| !snapshots.iter().any(|s| &s.path == *f))
+    {
+        return Err(format!("File not found in checkpoint: {}", missing));
     }
-    Ok(())
+    snapshots.retain(|s| files.contains(&s.path));
+    write_snapshots(
+        &session_id,
+        &checkpoint_id,
+        &snapshots,
+        &mode,
+        on_conflict.as_deref(),
+    )
 }
 
 #[command]
 pub async fn delete_checkpoint(session_id: String, checkpoint_id: String) -> Result<(), String> {
-    let dir = checkpoints_dir(&session_id)?;
-    let checkpoint_dir = dir.join(&checkpoint_id);
+    remove_checkpoint(&session_id, &checkpoint_id)?;
+    collect_blobs(&session_id);
 
-    if checkpoint_dir.exists() {
+    Ok(())
+}
+
+/// Deletes a checkpoint from whichever store holds it.
+pub(crate) fn remove_checkpoint(session_id: &str, checkpoint_id: &str) -> Result<(), String> {
+    unlink_checkpoint(session_id, checkpoint_id);
+    if let Some((_, checkpoint_dir)) = stored_checkpoint(session_id, checkpoint_id) {
         fs::remove_dir_all(&checkpoint_dir)
             .map_err(|e| format!("Failed to delete checkpoint: {}", e))?;
+    } else if let Some(git) = git_checkpoints(session_id) {
+        git.delete(checkpoint_id)?;
     }
-
+    checkpoint_index::record_deleted(session_id, checkpoint_id);
     Ok(())
 }
 
+/// Deletes unreferenced blobs in every location holding the project's
+/// checkpoints.
+pub(crate) fn collect_blobs(session_id: &str) {
+    let Ok(project_dir) = project_root_for(session_id) else {
+        return;
+    };
+    for root in checkpoint_location::roots(&project_dir) {
+        match checkpoint_store::collect_garbage(&root) {
+            Ok(0) => {}
+            Ok(removed) => eprintln!("[RUST] Removed {} unreferenced checkpoint blobs", removed),
+            Err(e) => eprintln!("[RUST] Checkpoint blob collection failed: {}", e),
+        }
+    }
+}
+
 #[command]
 pub async fn list_checkpoint_files(
     session_id: String,
     checkpoint_id: String,
 ) -> Result<Vec<String>, String> {
-    let dir = checkpoints_dir(&session_id)?;
-    let checkpoint_dir = dir.join(&checkpoint_id);
-
-    if !checkpoint_dir.exists() {
-        return Err(format!("Checkpoint {} not found", checkpoint_id));
-    }
-
-    let mapping_path = checkpoint_dir.join("file_mapping.json");
-    let mapping_json = fs::read_to_string(mapping_path)
-        .map_err(|e| format!("Failed to read file mapping: {}", e))?;
-    let mapping: HashMap<String, usize> = serde_json::from_str(&mapping_json)
-        .map_err(|e| format!("Failed to parse file mapping: {}", e))?;
-
-    Ok(mapping.keys().cloned().collect())
+    Ok(load_snapshots(&session_id, &checkpoint_id)?
+        .into_iter()
+        .map(|s| s.path)
+        .collect())
 }
 
 #[command]
@@ -355,17 +1215,7 @@ pub async fn get_checkpoint_metadata(
     session_id: String,
     checkpoint_id: String,
 ) -> Result<CheckpointMetadata, String> {
-    let dir = checkpoints_dir(&session_id)?;
-    let checkpoint_dir = dir.join(&checkpoint_id);
-
-    let metadata_path = checkpoint_dir.join("metadata.json");
-
-    let metadata_json = fs::read_to_string(&metadata_path)
-        .map_err(|e| format!("Failed to read metadata: {}", e))?;
-    let metadata: CheckpointMetadata = serde_json::from_str(&metadata_json)
-        .map_err(|e| format!("Failed to parse metadata: {}", e))?;
-
-    Ok(metadata)
+    read_metadata(&session_id, &checkpoint_id)
 }
 
 fn get_git_branch(base: &Path) -> Result<String, String> {
@@ -428,69 +1278,248 @@ pub async fn get_git_info(session_id: String) -> Result<HashMap<String, String>,
 
 #[command]
 pub async fn clean_old_checkpoints(session_id: String, keep_count: usize) -> Result<(), String> {
-    let dir = match checkpoints_dir(&session_id) {
-        Ok(path) => path,
-        Err(_) => return Ok(()),
-    };
+    for metadata in read_checkpoint_list(&session_id).iter().skip(keep_count) {
+        remove_checkpoint(&session_id, &metadata.id)
+            .map_err(|e| format!("Failed to delete old checkpoint: {}", e))?;
+    }
+    collect_blobs(&session_id);
 
-    if !dir.exists() {
-        return Ok(());
+    Ok(())
+}
+
+/// The newest snapshot of `path` among the session's recent checkpoints.
+pub(crate) fn latest_snapshot_of(session_id: &str, path: &str) -> Option<FileSnapshot> {
+    // Older checkpoints are unlikely to matter and each costs a manifest read.
+    const SEARCH_DEPTH: usize = 20;
+    read_checkpoint_list(session_id)
+        .into_iter()
+        .take(SEARCH_DEPTH)
+        .find_map(|metadata| {
+            load_snapshots(session_id, &metadata.id)
+                .ok()?
+                .into_iter()
+                .find(|s| s.path == path)
+        })
+}
+
+#[command]
+pub async fn list_checkpoints(session_id: String) -> Result<Vec<CheckpointMetadata>, String> {
+    Ok(read_checkpoint_list(&session_id))
+}
+
+/// Metadata of every checkpoint of the session, newest first.
+pub(crate) fn read_checkpoint_list(session_id: &str) -> Vec<CheckpointMetadata> {
+    match checkpoint_index::entries(session_id) {
+        Ok(entries) => entries.into_iter().map(|entry| entry.metadata).collect(),
+        Err(e) => {
+            eprintln!("[RUST] Failed to list checkpoints: {}", e);
+            Vec::new()
+        }
     }
+}
 
-    let mut checkpoints: Vec<(PathBuf, DateTime<Utc>)> = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(contents: &str) -> FileState {
+        FileState {
+            exists: true,
+            bytes: contents.as_bytes().to_vec(),
+            mode: None,
+        }
+    }
 
-    if let Ok(entries) = fs::read_dir(&dir) {
-        for entry in entries.flatten() {
+    fn snapshot(original: FileState, current: FileState) -> FileSnapshot {
+        FileSnapshot::from_states("a.txt".to_string(), original, current)
+    }
+
+    fn read(path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    /// Files in `dir` left over from staging.
+    fn leftovers(dir: &Path) -> Vec<String> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(dir).unwrap().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
             if entry.path().is_dir() {
-                let metadata_path = entry.path().join("metadata.json");
-                if metadata_path.exists() {
-                    if let Ok(metadata_json) = fs::read_to_string(&metadata_path) {
-                        if let Ok(metadata) =
-                            serde_json::from_str::<CheckpointMetadata>(&metadata_json)
-                        {
-                            checkpoints.push((entry.path(), metadata.timestamp));
-                        }
-                    }
-                }
+                found.extend(leftovers(&entry.path()));
+            } else if name.ends_with(".restore") {
+                found.push(name);
             }
         }
+        found
     }
 
-    checkpoints.sort_by(|a, b| b.1.cmp(&a.1));
+    /// A project holding `kept.txt` and `gone.txt`, and a transaction that
+    /// rewrites the first, deletes the second and creates `new/dir/made.txt`.
+    fn staged_restore() -> (tempfile::TempDir, RestoreTransaction) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("kept.txt"), "kept before").unwrap();
+        fs::write(root.join("gone.txt"), "gone before").unwrap();
+        let mut restore = RestoreTransaction::default();
+        restore
+            .stage(
+                "kept.txt".into(),
+                root.join("kept.txt"),
+                state("kept after"),
+            )
+            .unwrap();
+        restore
+            .stage(
+                "gone.txt".into(),
+                root.join("gone.txt"),
+                FileState::default(),
+            )
+            .unwrap();
+        restore
+            .stage(
+                "new/dir/made.txt".into(),
+                root.join("new/dir/made.txt"),
+                state("made"),
+            )
+            .unwrap();
+        (dir, restore)
+    }
 
-    for (path, _) in checkpoints.iter().skip(keep_count) {
-        fs::remove_dir_all(path).map_err(|e| format!("Failed to delete old checkpoint: {}", e))?;
+    #[test]
+    fn unchanged_since_either_side() {
+        let snapshot = snapshot(state("before"), state("after"));
+        let no_lookup = || -> Option<FileSnapshot> { panic!("looked up the newest snapshot") };
+        assert!(!changed_outside(&state("after"), &snapshot, no_lookup));
+        assert!(!changed_outside(&state("before"), &snapshot, no_lookup));
     }
 
-    Ok(())
-}
+    #[test]
+    fn missing_file_matches_a_missing_side() {
+        let created = snapshot(FileState::default(), state("new"));
+        assert!(!changed_outside(&FileState::default(), &created, || None));
+        let deleted = snapshot(state("old"), FileState::default());
+        assert!(!changed_outside(&FileState::default(), &deleted, || None));
+        let kept = snapshot(state("old"), state("new"));
+        assert!(changed_outside(&FileState::default(), &kept, || None));
+    }
 
-#[command]
-pub async fn list_checkpoints(session_id: String) -> Result<Vec<CheckpointMetadata>, String> {
-    let dir = match checkpoints_dir(&session_id) {
-        Ok(path) => path,
-        Err(_) => return Ok(Vec::new()),
-    };
+    #[test]
+    fn edited_outside_the_agent() {
+        let snapshot = snapshot(state("before"), state("after"));
+        assert!(changed_outside(&state("edited"), &snapshot, || None));
+    }
 
-    if !dir.exists() {
-        return Ok(Vec::new());
+    #[test]
+    fn later_agent_changes_are_not_conflicts() {
+        let snapshot = snapshot(state("before"), state("after"));
+        let latest = || Some(self::snapshot(state("after"), state("later")));
+        assert!(!changed_outside(&state("later"), &snapshot, latest));
+        let latest = || Some(self::snapshot(state("after"), state("later")));
+        assert!(changed_outside(&state("edited"), &snapshot, latest));
     }
 
-    let mut out: Vec<CheckpointMetadata> = Vec::new();
-    if let Ok(entries) = fs::read_dir(&dir) {
-        for entry in entries.flatten() {
-            if entry.path().is_dir() {
-                let meta_path = entry.path().join("metadata.json");
-                if meta_path.exists() {
-                    if let Ok(json) = fs::read_to_string(&meta_path) {
-                        if let Ok(md) = serde_json::from_str::<CheckpointMetadata>(&json) {
-                            out.push(md);
-                        }
-                    }
-                }
-            }
-        }
+    #[test]
+    fn recorded_checksum_stands_for_the_current_side() {
+        let mut legacy = snapshot(state("before"), state("stale"));
+        legacy.checksum = Some(checkpoint_store::hash(b"after"));
+        assert!(!changed_outside(&state("after"), &legacy, || None));
+    }
+
+    #[test]
+    fn staging_leaves_targets_alone() {
+        let (dir, _restore) = staged_restore();
+        let root = dir.path();
+        assert_eq!(read(&root.join("kept.txt")).as_deref(), Some("kept before"));
+        assert_eq!(read(&root.join("gone.txt")).as_deref(), Some("gone before"));
+        assert!(!root.join("new/dir/made.txt").exists());
+    }
+
+    #[test]
+    fn commit_puts_every_file_in_place() {
+        let (dir, mut restore) = staged_restore();
+        let root = dir.path();
+        restore.commit().unwrap();
+        assert_eq!(read(&root.join("kept.txt")).as_deref(), Some("kept after"));
+        assert!(!root.join("gone.txt").exists());
+        assert_eq!(
+            read(&root.join("new/dir/made.txt")).as_deref(),
+            Some("made")
+        );
+        assert_eq!(restore.touched().len(), 3);
+        assert!(leftovers(root).is_empty());
+    }
+
+    #[test]
+    fn rollback_after_commit_restores_the_project() {
+        let (dir, mut restore) = staged_restore();
+        let root = dir.path();
+        restore.commit().unwrap();
+        assert!(restore.rollback().is_empty());
+        assert_eq!(read(&root.join("kept.txt")).as_deref(), Some("kept before"));
+        assert_eq!(read(&root.join("gone.txt")).as_deref(), Some("gone before"));
+        assert!(!root.join("new/dir/made.txt").exists());
+        assert!(!root.join("new").exists());
+    }
+
+    #[test]
+    fn rollback_before_commit_drops_staged_files() {
+        let (dir, mut restore) = staged_restore();
+        let root = dir.path();
+        assert!(restore.rollback().is_empty());
+        assert!(restore.touched().is_empty());
+        assert!(leftovers(root).is_empty());
+        assert!(!root.join("new").exists());
+        assert_eq!(read(&root.join("kept.txt")).as_deref(), Some("kept before"));
+    }
+
+    #[test]
+    fn failed_commit_rolls_back_the_files_already_replaced() {
+        let (dir, mut restore) = staged_restore();
+        let root = dir.path();
+        // Lose the staged contents of the last write so renaming it fails.
+        let staged = restore.writes[2].staged.clone().unwrap();
+        fs::remove_file(staged).unwrap();
+
+        assert!(restore.commit().is_err());
+        assert_eq!(restore.touched(), vec!["kept.txt", "gone.txt"]);
+        assert!(restore.rollback().is_empty());
+        assert_eq!(read(&root.join("kept.txt")).as_deref(), Some("kept before"));
+        assert_eq!(read(&root.join("gone.txt")).as_deref(), Some("gone before"));
+        assert!(!root.join("new").exists());
+    }
+
+    #[test]
+    fn rollback_removes_backups() {
+        let (dir, mut restore) = staged_restore();
+        let root = dir.path();
+        restore.backup(&root.join("kept.txt")).unwrap();
+        assert!(root.join("kept.txt.orig").exists());
+        restore.rollback();
+        assert!(!root.join("kept.txt.orig").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_restores_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("run.sh");
+        fs::write(&script, "echo before").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut restore = RestoreTransaction::default();
+        let after = FileState {
+            mode: Some(0o644),
+            ..state("echo after")
+        };
+        restore
+            .stage("run.sh".into(), script.clone(), after)
+            .unwrap();
+        restore.commit().unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&script), 0o644);
+
+        restore.rollback();
+        assert_eq!(mode(&script), 0o755);
+        assert_eq!(read(&script).as_deref(), Some("echo before"));
     }
-    out.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-    Ok(out)
 }
@@ -0,0 +1,634 @@
+//! Portable checkpoint archives, for sharing checkpoints with someone else or
+//! attaching them to a bug report.
+//!
+//! An archive is a gzipped tar holding `archive.json`, then for each
+//! checkpoint `checkpoints/<id>/metadata.json` (name, trigger, git branch and
+//! commit, ...) and `checkpoints/<id>/manifest.json` (its files by content
+//! hash), and every distinct file content once under `blobs/<sha256>`.
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tauri::command;
+
+use crate::checkpoint::{
+    load_snapshots, new_checkpoint_id, project_root_for, read_metadata, write_checkpoint,
+    CheckpointMetadata, FileSnapshot, FileState, ImportOrigin,
+};
+use crate::checkpoint_path::CheckpointPath;
+use crate::checkpoint_store::{self, Manifest, ManifestEntry};
+
+const FORMAT: &str = "checkpoint-archive";
+const VERSION: u32 = 1;
+const INDEX_FILE: &str = "archive.json";
+// Cap on the unpacked size of an imported archive.
+const MAX_IMPORT_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+// Cap on one index, metadata or manifest entry, which is read into memory.
+const MAX_JSON_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchiveIndex {
+    format: String,
+    version: u32,
+    exported_at: DateTime<Utc>,
+    project_dir: String,
+    checkpoints: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportedCheckpoint {
+    pub original_id: String,
+    /// Differs from `original_id` when that id was already taken.
+    pub id: String,
+}
+
+type ArchiveBuilder = tar::Builder<GzEncoder<File>>;
+
+fn append(builder: &mut ArchiveBuilder, path: &str, data: &[u8]) -> Result<(), String> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, path, data)
+        .map_err(|e| format!("Failed to write archive entry {}: {}", path, e))
+}
+
+fn append_json<T: Serialize>(
+    builder: &mut ArchiveBuilder,
+    path: &str,
+    value: &T,
+) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(value)
+        .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+    append(builder, path, &json)
+}
+
+/// Adds a blob unless the archive already holds it, and returns its hash.
+fn append_blob(
+    builder: &mut ArchiveBuilder,
+    written: &mut HashSet<String>,
+    data: Option<&[u8]>,
+) -> Result<Option<String>, String> {
+    let Some(data) = data else {
+        return Ok(None);
+    };
+    let hash = checkpoint_store::hash(data);
+    if written.insert(hash.clone()) {
+        append(builder, &format!("blobs/{}", hash), data)?;
+    }
+    Ok(Some(hash))
+}
+
+fn valid_checkpoint_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+}
+
+/// Whether `path` is an entry an archive can hold; anything else is skipped
+/// on import without being read.
+fn expected_entry(path: &str) -> bool {
+    if path == INDEX_FILE {
+        return true;
+    }
+    if let Some(hash) = path.strip_prefix("blobs/") {
+        return hash.len() == 64
+            && hash
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+    }
+    path.strip_prefix("checkpoints/")
+        .and_then(|rest| rest.split_once('/'))
+        .is_some_and(|(id, file)| {
+            valid_checkpoint_id(id) && matches!(file, "metadata.json" | "manifest.json")
+        })
+}
+
+/// Writes the checkpoints `ids` to a new archive at `destination`, loading
+/// each one with `load` as it is packed.
+fn write_archive(
+    destination: &Path,
+    project_dir: &Path,
+    ids: &[String],
+    mut load: impl FnMut(&str) -> Result<(CheckpointMetadata, Vec<FileSnapshot>), String>,
+) -> Result<(), String> {
+    let file = File::create(destination).map_err(|e| format!("Failed to create archive: {}", e))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mut written = HashSet::new();
+
+    for id in ids {
+        let (metadata, snapshots) = load(id)?;
+        let mut entries = Vec::new();
+        for snapshot in snapshots {
+            entries.push(ManifestEntry {
+                original: append_blob(&mut builder, &mut written, snapshot.original_data())?,
+                current: append_blob(&mut builder, &mut written, snapshot.current_data())?,
+                original_mode: snapshot.original_mode,
+                current_mode: snapshot.current_mode,
+                path: snapshot.path,
+            });
+        }
+        append_json(
+            &mut builder,
+            &format!("checkpoints/{}/metadata.json", id),
+            &metadata,
+        )?;
+        append_json(
+            &mut builder,
+            &format!("checkpoints/{}/manifest.json", id),
+            &Manifest::new(entries),
+        )?;
+    }
+    append_json(
+        &mut builder,
+        INDEX_FILE,
+        &ArchiveIndex {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            exported_at: Utc::now(),
+            project_dir: project_dir.to_string_lossy().to_string(),
+            checkpoints: ids.to_vec(),
+        },
+    )?;
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(|e| format!("Failed to finish archive: {}", e))?;
+    Ok(())
+}
+
+/// Packs checkpoints into an archive at `destination` and returns how many
+/// were exported.
+#[command]
+pub async fn export_checkpoints(
+    session_id: String,
+    checkpoint_ids: Vec<String>,
+    destination: String,
+) -> Result<usize, String> {
+    let mut seen = HashSet::new();
+    let ids: Vec<String> = checkpoint_ids
+        .into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
+    if ids.is_empty() {
+        return Err("No checkpoints to export".to_string());
+    }
+    let project_dir = project_root_for(&session_id)?;
+    write_archive(Path::new(&destination), &project_dir, &ids, |id| {
+        Ok((
+            read_metadata(&session_id, id)?,
+            load_snapshots(&session_id, id)?,
+        ))
+    })?;
+    Ok(ids.len())
+}
+
+/// An archive's index and JSON entries. Its blobs are in a staging store.
+struct UnpackedArchive {
+    index: ArchiveIndex,
+    json: HashMap<String, Vec<u8>>,
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(
+    json: &HashMap<String, Vec<u8>>,
+    path: &str,
+) -> Result<T, String> {
+    let data = json
+        .get(path)
+        .ok_or_else(|| format!("Archive is missing {}", path))?;
+    serde_json::from_slice(data).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+/// Reads the archive at `source`, streaming its blobs into the blob store at
+/// `staging`, where each is checked against its hash.
+fn read_archive(source: &Path, staging: &Path) -> Result<UnpackedArchive, String> {
+    let file = File::open(source).map_err(|e| format!("Failed to open archive: {}", e))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut json: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut total: u64 = 0;
+    for entry in archive
+        .entries()
+        .map_err(|e| format!("Failed to read archive: {}", e))?
+    {
+        let mut entry = entry.map_err(|e| format!("Failed to read archive: {}", e))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(|e| format!("Failed to read archive: {}", e))?
+            .to_string_lossy()
+            .to_string();
+        if !expected_entry(&path) {
+            continue;
+        }
+        total = total.saturating_add(entry.size());
+        if total > MAX_IMPORT_BYTES {
+            return Err(format!(
+                "Archive is larger than {} bytes unpacked",
+                MAX_IMPORT_BYTES
+            ));
+        }
+        if let Some(hash) = path.strip_prefix("blobs/") {
+            checkpoint_store::write_blob_from(staging, hash, &mut entry)
+                .map_err(|e| format!("Archive blob is unusable: {}", e))?;
+            continue;
+        }
+        if entry.size() > MAX_JSON_BYTES {
+            return Err(format!("Archive entry {} is too large", path));
+        }
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .map_err(|e| format!("Failed to read archive: {}", e))?;
+        json.insert(path, data);
+    }
+
+    let mut index: ArchiveIndex = parse(&json, INDEX_FILE)?;
+    if index.format != FORMAT || index.version > VERSION {
+        return Err(format!(
+            "Unsupported checkpoint archive: {} v{}",
+            index.format, index.version
+        ));
+    }
+    if let Some(bad) = index.checkpoints.iter().find(|id| !valid_checkpoint_id(id)) {
+        return Err(format!("Invalid checkpoint id in archive: {}", bad));
+    }
+    let mut seen = HashSet::new();
+    index.checkpoints.retain(|id| seen.insert(id.clone()));
+    Ok(UnpackedArchive { index, json })
+}
+
+/// Turns the archive's checkpoints into checkpoints of `project_dir` and hands
+/// them to `save`, parents first. `exists` tells whether the project already
+/// has a checkpoint id: a taken id is replaced, and a parent outside the
+/// archive is kept only if the project has it.
+fn unpack(
+    archive: &UnpackedArchive,
+    staging: &Path,
+    project_dir: &Path,
+    origin: &str,
+    exists: impl Fn(&str) -> bool,
+    mut save: impl FnMut(&CheckpointMetadata, &[FileSnapshot]) -> Result<(), String>,
+) -> Result<Vec<ImportedCheckpoint>, String> {
+    let ids: HashMap<String, String> = archive
+        .index
+        .checkpoints
+        .iter()
+        .map(|id| {
+            let new_id = if exists(id) {
+                new_checkpoint_id()
+            } else {
+                id.clone()
+            };
+            (id.clone(), new_id)
+        })
+        .collect();
+
+    let mut checkpoints: Vec<(String, CheckpointMetadata)> = archive
+        .index
+        .checkpoints
+        .iter()
+        .map(|id| {
+            parse(&archive.json, &format!("checkpoints/{}/metadata.json", id))
+                .map(|metadata| (id.clone(), metadata))
+        })
+        .collect::<Result<_, String>>()?;
+    // Parents first, so git-stored children can point at their commits.
+    checkpoints.sort_by_key(|(_, metadata)| metadata.timestamp);
+
+    let side = |hash: &Option<String>, mode: Option<u32>| -> Result<FileState, String> {
+        let Some(hash) = hash else {
+            return Ok(FileState::default());
+        };
+        let bytes = checkpoint_store::read_blob(staging, hash)
+            .map_err(|_| format!("Archive is missing blob {}", hash))?;
+        Ok(FileState {
+            exists: true,
+            bytes,
+            mode,
+        })
+    };
+
+    let mut imported = Vec::with_capacity(checkpoints.len());
+    for (original_id, mut metadata) in checkpoints {
+        let manifest: Manifest = parse(
+            &archive.json,
+            &format!("checkpoints/{}/manifest.json", original_id),
+        )?;
+        let mut snapshots = Vec::with_capacity(manifest.files.len());
+        for entry in manifest.files {
+            // Archives come from elsewhere; keep their files inside the project.
+            let path = CheckpointPath::resolve(project_dir, &entry.path)
+                .map_err(|e| format!("Unsafe path in archive: {}", e))?;
+            let original = side(&entry.original, entry.original_mode)?;
+            let current = side(&entry.current, entry.current_mode)?;
+            snapshots.push(FileSnapshot::from_states(path.key(), original, current));
+        }
+
+        metadata.id = ids[&original_id].clone();
+        metadata.parent_id = metadata
+            .parent_id
+            .and_then(|parent| match ids.get(&parent) {
+                Some(new_id) => Some(new_id.clone()),
+                None => exists(&parent).then_some(parent),
+            });
+        metadata.file_count = snapshots.len();
+        metadata.imported = Some(ImportOrigin {
+            archive: origin.to_string(),
+            original_id: original_id.clone(),
+            project_dir: archive.index.project_dir.clone(),
+            imported_at: Utc::now(),
+        });
+        save(&metadata, &snapshots)?;
+        imported.push(ImportedCheckpoint {
+            original_id,
+            id: metadata.id,
+        });
+    }
+    Ok(imported)
+}
+
+/// Unpacks an archive into the session's checkpoints. Checkpoints whose id is
+/// already taken get a new one; parent links between imported checkpoints
+/// follow the new ids, and links to other checkpoints are kept when the
+/// session has them.
+#[command]
+pub async fn import_checkpoints(
+    session_id: String,
+    source: String,
+) -> Result<Vec<ImportedCheckpoint>, String> {
+    // Dropped, with every staged blob, once the import is done or failed.
+    let staging =
+        tempfile::tempdir().map_err(|e| format!("Failed to create staging directory: {}", e))?;
+    let source_path = Path::new(&source);
+    let archive = read_archive(source_path, staging.path())?;
+    let project_dir = project_root_for(&session_id)?;
+    let origin = source_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| source.clone());
+    let imported = unpack(
+        &archive,
+        staging.path(),
+        &project_dir,
+        &origin,
+        |id| read_metadata(&session_id, id).is_ok(),
+        |metadata, snapshots| write_checkpoint(&session_id, metadata, snapshots),
+    )?;
+    eprintln!(
+        "[RUST] Imported {} checkpoints from {}",
+        imported.len(),
+        source
+    );
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+    use std::path::PathBuf;
+
+    type Checkpoint = (CheckpointMetadata, Vec<FileSnapshot>);
+
+    fn state(contents: &str, mode: Option<u32>) -> FileState {
+        FileState {
+            exists: true,
+            bytes: contents.as_bytes().to_vec(),
+            mode,
+        }
+    }
+
+    fn checkpoint(
+        id: &str,
+        minute: u32,
+        parent: Option<&str>,
+        files: Vec<FileSnapshot>,
+    ) -> Checkpoint {
+        let metadata = CheckpointMetadata {
+            id: id.to_string(),
+            timestamp: Utc.with_ymd_and_hms(2026, 3, 1, 10, minute, 0).unwrap(),
+            name: None,
+            checkpoint_type: "auto".to_string(),
+            trigger: None,
+            file_count: files.len(),
+            git_branch: None,
+            git_commit: None,
+            tags: Vec::new(),
+            parent_id: parent.map(str::to_string),
+            imported: None,
+        };
+        (metadata, files)
+    }
+
+    fn project() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let project = fs::canonicalize(dir.path()).unwrap().join("project");
+        fs::create_dir(&project).unwrap();
+        (dir, project)
+    }
+
+    fn export(dir: &Path, checkpoints: &[Checkpoint], ids: &[&str]) -> PathBuf {
+        let destination = dir.join("export.tar.gz");
+        let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        write_archive(&destination, Path::new("/elsewhere"), &ids, |id| {
+            Ok(checkpoints
+                .iter()
+                .find(|(metadata, _)| metadata.id == id)
+                .cloned()
+                .unwrap())
+        })
+        .unwrap();
+        destination
+    }
+
+    fn import(
+        source: &Path,
+        project: &Path,
+        existing: &[&str],
+    ) -> Result<(Vec<ImportedCheckpoint>, Vec<Checkpoint>), String> {
+        let staging = tempfile::tempdir().unwrap();
+        let archive = read_archive(source, staging.path())?;
+        let mut saved = Vec::new();
+        let imported = unpack(
+            &archive,
+            staging.path(),
+            project,
+            "export.tar.gz",
+            |id| existing.contains(&id),
+            |metadata, snapshots| {
+                saved.push((metadata.clone(), snapshots.to_vec()));
+                Ok(())
+            },
+        )?;
+        Ok((imported, saved))
+    }
+
+    #[test]
+    fn round_trips_checkpoints() {
+        let (dir, project) = project();
+        let first = checkpoint(
+            "cp_a",
+            0,
+            None,
+            vec![
+                FileSnapshot::from_states(
+                    "src/lib.rs".to_string(),
+                    state("old", Some(0o644)),
+                    state("new", Some(0o755)),
+                ),
+                FileSnapshot::from_states(
+                    "added.txt".to_string(),
+                    FileState::default(),
+                    state("hi", None),
+                ),
+            ],
+        );
+        let (mut metadata, files) = checkpoint(
+            "cp_b",
+            5,
+            Some("cp_a"),
+            vec![FileSnapshot::from_states(
+                "src/lib.rs".to_string(),
+                state("new", Some(0o755)),
+                FileState::default(),
+            )],
+        );
+        metadata.name = Some("Before refactor".to_string());
+        metadata.tags = vec!["keep".to_string()];
+        let checkpoints = vec![first, (metadata, files)];
+
+        // Children listed first still come back after their parents.
+        let source = export(dir.path(), &checkpoints, &["cp_b", "cp_a"]);
+        let (imported, saved) = import(&source, &project, &[]).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert!(imported.iter().all(|c| c.id == c.original_id));
+
+        let ids: Vec<&str> = saved.iter().map(|(m, _)| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["cp_a", "cp_b"]);
+        let (child, _) = &saved[1];
+        assert_eq!(child.parent_id.as_deref(), Some("cp_a"));
+        assert_eq!(child.name.as_deref(), Some("Before refactor"));
+        // Provenance is kept apart from the tags, which retention honours.
+        assert_eq!(child.tags, vec!["keep"]);
+        let origin = child.imported.as_ref().unwrap();
+        assert_eq!(origin.archive, "export.tar.gz");
+        assert_eq!(origin.original_id, "cp_b");
+        assert_eq!(origin.project_dir, "/elsewhere");
+
+        for ((_, expected), (metadata, actual)) in checkpoints.iter().zip(&saved) {
+            assert_eq!(metadata.file_count, expected.len());
+            for (expected, actual) in expected.iter().zip(actual) {
+                assert_eq!(actual.path, expected.path);
+                assert_eq!(actual.original_data(), expected.original_data());
+                assert_eq!(actual.current_data(), expected.current_data());
+                assert_eq!(actual.original_mode, expected.original_mode);
+                assert_eq!(actual.current_mode, expected.current_mode);
+            }
+        }
+    }
+
+    #[test]
+    fn renames_taken_ids_and_keeps_known_parents() {
+        let (dir, project) = project();
+        let checkpoints = vec![
+            checkpoint("cp_a", 0, None, Vec::new()),
+            checkpoint("cp_b", 1, Some("cp_a"), Vec::new()),
+            checkpoint("cp_c", 2, Some("cp_local"), Vec::new()),
+            checkpoint("cp_d", 3, Some("cp_gone"), Vec::new()),
+        ];
+        // A repeated id is imported once.
+        let source = export(
+            dir.path(),
+            &checkpoints,
+            &["cp_a", "cp_b", "cp_c", "cp_d", "cp_a"],
+        );
+        let (imported, saved) = import(&source, &project, &["cp_a", "cp_local"]).unwrap();
+        assert_eq!(imported.len(), 4);
+
+        let renamed = &imported[0];
+        assert_eq!(renamed.original_id, "cp_a");
+        assert_ne!(renamed.id, "cp_a");
+        let parent = |id: &str| {
+            saved
+                .iter()
+                .find(|(m, _)| m.imported.as_ref().unwrap().original_id == id)
+                .and_then(|(m, _)| m.parent_id.clone())
+        };
+        assert_eq!(parent("cp_b"), Some(renamed.id.clone()));
+        assert_eq!(parent("cp_c").as_deref(), Some("cp_local"));
+        assert_eq!(parent("cp_d"), None);
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_project() {
+        let (dir, project) = project();
+        let checkpoints = vec![checkpoint(
+            "cp_a",
+            0,
+            None,
+            vec![FileSnapshot::from_states(
+                "../outside.txt".to_string(),
+                FileState::default(),
+                state("x", None),
+            )],
+        )];
+        let source = export(dir.path(), &checkpoints, &["cp_a"]);
+        let err = import(&source, &project, &[]).err().unwrap();
+        assert!(err.contains("Unsafe path"), "{}", err);
+    }
+
+    #[test]
+    fn refuses_blobs_that_do_not_match_their_hash() {
+        let (dir, project) = project();
+        let source = dir.path().join("forged.tar.gz");
+        let file = File::create(&source).unwrap();
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        let hash = checkpoint_store::hash(b"expected");
+        append(&mut builder, &format!("blobs/{}", hash), b"forged").unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let err = import(&source, &project, &[]).err().unwrap();
+        assert!(err.contains("does not match"), "{}", err);
+    }
+
+    #[test]
+    fn accepts_only_expected_entries() {
+        let hash = checkpoint_store::hash(b"x");
+        for path in [
+            INDEX_FILE.to_string(),
+            format!("blobs/{}", hash),
+            "checkpoints/cp_1-a/metadata.json".to_string(),
+            "checkpoints/cp_1-a/manifest.json".to_string(),
+        ] {
+            assert!(expected_entry(&path), "{}", path);
+        }
+        for path in [
+            "../archive.json".to_string(),
+            "/archive.json".to_string(),
+            "./archive.json".to_string(),
+            format!("blobs/{}", hash.to_uppercase()),
+            format!("blobs/{}", &hash[..63]),
+            format!("blobs/../{}", &hash[..61]),
+            "blobs/".to_string(),
+            "checkpoints/../metadata.json".to_string(),
+            "checkpoints/cp/../../metadata.json".to_string(),
+            "checkpoints/cp/nested/metadata.json".to_string(),
+            "checkpoints/cp/other.json".to_string(),
+            "/checkpoints/cp/metadata.json".to_string(),
+        ] {
+            assert!(!expected_entry(&path), "{}", path);
+        }
+    }
+}
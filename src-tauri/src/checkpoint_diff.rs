@@ -0,0 +1,511 @@
+//! Line diffs between checkpoint snapshots and the working tree.
+//!
+//! Diffs are computed here so the webview receives per-file line counts, and
+//! hunks or unified text only when it asks for them, instead of every file's
+//! full contents on both sides.
+
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, DiffTag, TextDiff};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tauri::command;
+
+use crate::checkpoint::{load_snapshots, project_root_for, read_metadata, FileState};
+use crate::checkpoint_path::CheckpointPath;
+
+pub(crate) const DEFAULT_CONTEXT: usize = 3;
+// Past this the diff is approximated rather than stalling the command.
+const DIFF_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DiffOptions {
+    /// Only diff these paths, as stored in the checkpoint.
+    pub paths: Option<Vec<String>>,
+    /// Include structured hunks for each file.
+    #[serde(default)]
+    pub hunks: bool,
+    /// Include unified diff text for each file.
+    #[serde(default)]
+    pub unified: bool,
+    /// Context lines around each hunk; defaults to 3.
+    pub context: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffLine {
+    /// `context`, `add` or `remove`.
+    pub kind: String,
+    /// 1-based line numbers on each side, absent for lines not on that side.
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    /// The line without its line ending.
+    pub text: String,
+}
+
+/// A hunk in unified diff terms: starts are 1-based, and a side with no lines
+/// starts at the line before the hunk.
+#[derive(Debug, Serialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileDiff {
+    pub path: String,
+    /// `added`, `deleted` or `modified`.
+    pub status: String,
+    /// True when either side is not valid UTF-8; no lines are counted then.
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hunks: Option<Vec<DiffHunk>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unified: Option<String>,
+}
+
+/// Changed files only, sorted by path, with totals across them.
+#[derive(Debug, Serialize)]
+pub struct CheckpointDiff {
+    pub files: Vec<FileDiff>,
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+/// Line diff used for every checkpoint comparison, so hunk indexes agree
+/// between the diff and restore commands.
+pub(crate) fn line_diff<'a>(old: &'a str, new: &'a str) -> TextDiff<'a, 'a, 'a, str> {
+    TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(old, new)
+}
+
+fn hunk_start(start: usize, len: usize) -> usize {
+    if len == 0 {
+        start
+    } else {
+        start + 1
+    }
+}
+
+/// Diffs one file between two states. `None` when nothing changed.
+fn diff_file(
+    path: &str,
+    old: &FileState,
+    new: &FileState,
+    options: &DiffOptions,
+) -> Option<FileDiff> {
+    if old.exists == new.exists && old.bytes == new.bytes && old.mode == new.mode {
+        return None;
+    }
+    let status = match (old.exists, new.exists) {
+        (false, true) => "added",
+        (true, false) => "deleted",
+        _ => "modified",
+    };
+    let mut file = FileDiff {
+        path: path.to_string(),
+        status: status.to_string(),
+        binary: false,
+        additions: 0,
+        deletions: 0,
+        old_mode: old.mode,
+        new_mode: new.mode,
+        hunks: None,
+        unified: None,
+    };
+
+    let (Ok(old_text), Ok(new_text)) = (
+        std::str::from_utf8(&old.bytes),
+        std::str::from_utf8(&new.bytes),
+    ) else {
+        file.binary = true;
+        return Some(file);
+    };
+
+    let diff = line_diff(old_text, new_text);
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        match tag {
+            DiffTag::Insert => file.additions += new_range.len(),
+            DiffTag::Delete => file.deletions += old_range.len(),
+            DiffTag::Replace => {
+                file.additions += new_range.len();
+                file.deletions += old_range.len();
+            }
+            DiffTag::Equal => {}
+        }
+    }
+
+    let context = options.context.unwrap_or(DEFAULT_CONTEXT);
+    if options.hunks {
+        let hunks = diff
+            .grouped_ops(context)
+            .into_iter()
+            .filter_map(|group| {
+                let first = group.first()?;
+                let last = group.last()?;
+                let old_range = first.old_range().start..last.old_range().end;
+                let new_range = first.new_range().start..last.new_range().end;
+                let lines = group
+                    .iter()
+                    .flat_map(|op| diff.iter_changes(op))
+                    .map(|change| DiffLine {
+                        kind: match change.tag() {
+                            ChangeTag::Equal => "context",
+                            ChangeTag::Insert => "add",
+                            ChangeTag::Delete => "remove",
+                        }
+                        .to_string(),
+                        old_line: change.old_index().map(|i| i + 1),
+                        new_line: change.new_index().map(|i| i + 1),
+                        text: change
+                            .value()
+                            .trim_end_matches('\n')
+                            .trim_end_matches('\r')
+                            .to_string(),
+                    })
+                    .collect();
+                Some(DiffHunk {
+                    old_start: hunk_start(old_range.start, old_range.len()),
+                    old_lines: old_range.len(),
+                    new_start: hunk_start(new_range.start, new_range.len()),
+                    new_lines: new_range.len(),
+                    lines,
+                })
+            })
+            .collect();
+        file.hunks = Some(hunks);
+    }
+    if options.unified {
+        let old_name = if old.exists {
+            format!("a/{}", path)
+        } else {
+            "/dev/null".to_string()
+        };
+        let new_name = if new.exists {
+            format!("b/{}", path)
+        } else {
+            "/dev/null".to_string()
+        };
+        file.unified = Some(
+            diff.unified_diff()
+                .context_radius(context)
+                .header(&old_name, &new_name)
+                .to_string(),
+        );
+    }
+    Some(file)
+}
+
+fn collect(
+    pairs: BTreeMap<String, (FileState, FileState)>,
+    options: &DiffOptions,
+) -> CheckpointDiff {
+    let files: Vec<FileDiff> = pairs
+        .iter()
+        .filter(|(path, _)| {
+            options
+                .paths
+                .as_ref()
+                .map_or(true, |paths| paths.contains(path))
+        })
+        .filter_map(|(path, (old, new))| diff_file(path, old, new, options))
+        .collect();
+    CheckpointDiff {
+        additions: files.iter().map(|f| f.additions).sum(),
+        deletions: files.iter().map(|f| f.deletions).sum(),
+        files,
+    }
+}
+
+/// What a checkpoint's change did: each file's original against its current
+/// side.
+#[command]
+pub async fn diff_checkpoint(
+    session_id: String,
+    checkpoint_id: String,
+    options: Option<DiffOptions>,
+) -> Result<CheckpointDiff, String> {
+    let pairs = load_snapshots(&session_id, &checkpoint_id)?
+        .into_iter()
+        .map(|s| {
+            let pair = (s.original_state(), s.current_state());
+            (s.path, pair)
+        })
+        .collect();
+    Ok(collect(pairs, &options.unwrap_or_default()))
+}
+
+/// A checkpoint's files against the working tree. `mode` picks the side of the
+/// checkpoint to compare, as in `restore_checkpoint_with_mode`: the original
+/// side unless it is `"current"`.
+#[command]
+pub async fn diff_checkpoint_to_working_tree(
+    session_id: String,
+    checkpoint_id: String,
+    mode: Option<String>,
+    options: Option<DiffOptions>,
+) -> Result<CheckpointDiff, String> {
+    let base = project_root_for(&session_id)?;
+    let use_current = mode.as_deref() == Some("current");
+    let options = options.unwrap_or_default();
+    let mut pairs = BTreeMap::new();
+    for snapshot in load_snapshots(&session_id, &checkpoint_id)? {
+        let wanted = options
+            .paths
+            .as_ref()
+            .map_or(true, |paths| paths.contains(&snapshot.path));
+        if !wanted {
+            continue;
+        }
+        let saved = if use_current {
+            snapshot.current_state()
+        } else {
+            snapshot.original_state()
+        };
+        let working = FileState::read(CheckpointPath::resolve(&base, &snapshot.path)?.absolute())?;
+        pairs.insert(snapshot.path, (saved, working));
+    }
+    Ok(collect(pairs, &options))
+}
+
+/// The checkpoints after `from` up to and including `to`, oldest first, if
+/// `from` is an ancestor of `to`.
+fn descendants_until(session_id: &str, from: &str, to: &str) -> Option<Vec<String>> {
+    let mut chain = Vec::new();
+    let mut next = Some(to.to_string());
+    while let Some(id) = next {
+        if id == from {
+            chain.reverse();
+            return Some(chain);
+        }
+        if chain.contains(&id) {
+            return None;
+        }
+        next = read_metadata(session_id, &id).ok()?.parent_id;
+        chain.push(id);
+    }
+    None
+}
+
+/// How the files changed between two checkpoints, comparing the state each
+/// left them in. When `from` is an ancestor of `to` the checkpoints in between
+/// are replayed, so files changed only along the way are included; otherwise
+/// only the files `to` holds are compared. A file first touched after `from`
+/// is compared from its original side in the first checkpoint touching it; a
+/// file not touched after `from` is left out.
+#[command]
+pub async fn diff_checkpoints(
+    session_id: String,
+    from_checkpoint_id: String,
+    to_checkpoint_id: String,
+    options: Option<DiffOptions>,
+) -> Result<CheckpointDiff, String> {
+    let from: BTreeMap<String, FileState> = load_snapshots(&session_id, &from_checkpoint_id)?
+        .into_iter()
+        .map(|s| {
+            let state = s.current_state();
+            (s.path, state)
+        })
+        .collect();
+    let chain = descendants_until(&session_id, &from_checkpoint_id, &to_checkpoint_id)
+        .unwrap_or_else(|| vec![to_checkpoint_id.clone()]);
+    let mut pairs: BTreeMap<String, (FileState, FileState)> = BTreeMap::new();
+    for id in &chain {
+        for snapshot in load_snapshots(&session_id, id)? {
+            let new = snapshot.current_state();
+            if let Some(pair) = pairs.get_mut(&snapshot.path) {
+                pair.1 = new;
+                continue;
+            }
+            let old = from
+                .get(&snapshot.path)
+                .cloned()
+                .unwrap_or_else(|| snapshot.original_state());
+            pairs.insert(snapshot.path, (old, new));
+        }
+    }
+    Ok(collect(pairs, &options.unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(contents: &str) -> FileState {
+        FileState {
+            exists: true,
+            bytes: contents.as_bytes().to_vec(),
+            mode: Some(0o644),
+        }
+    }
+
+    fn with_hunks() -> DiffOptions {
+        DiffOptions {
+            hunks: true,
+            ..Default::default()
+        }
+    }
+
+    fn numbered(count: usize) -> String {
+        (1..=count).map(|n| format!("line {}\n", n)).collect()
+    }
+
+    #[test]
+    fn unchanged_files_are_skipped() {
+        let file = state("same\n");
+        assert!(diff_file("a.txt", &file, &file, &with_hunks()).is_none());
+    }
+
+    #[test]
+    fn mode_changes_count_as_modified() {
+        let old = state("same\n");
+        let new = FileState {
+            mode: Some(0o755),
+            ..old.clone()
+        };
+        let diff = diff_file("run.sh", &old, &new, &with_hunks()).unwrap();
+        assert_eq!(diff.status, "modified");
+        assert_eq!((diff.additions, diff.deletions), (0, 0));
+        assert_eq!(diff.new_mode, Some(0o755));
+        assert!(diff.hunks.unwrap().is_empty());
+    }
+
+    #[test]
+    fn counts_lines_and_status() {
+        let diff = diff_file(
+            "a.txt",
+            &FileState::default(),
+            &state("a\nb\n"),
+            &with_hunks(),
+        )
+        .unwrap();
+        assert_eq!(diff.status, "added");
+        assert_eq!((diff.additions, diff.deletions), (2, 0));
+
+        let diff = diff_file(
+            "a.txt",
+            &state("a\nb\n"),
+            &FileState::default(),
+            &with_hunks(),
+        )
+        .unwrap();
+        assert_eq!(diff.status, "deleted");
+        assert_eq!((diff.additions, diff.deletions), (0, 2));
+
+        let diff = diff_file(
+            "a.txt",
+            &state("a\nb\nc\n"),
+            &state("a\nB\nc\n"),
+            &with_hunks(),
+        )
+        .unwrap();
+        assert_eq!(diff.status, "modified");
+        assert_eq!((diff.additions, diff.deletions), (1, 1));
+    }
+
+    #[test]
+    fn hunks_are_numbered_from_one() {
+        let old = numbered(20);
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 18\n", "");
+        let diff = diff_file("a.txt", &state(&old), &state(&new), &with_hunks()).unwrap();
+        let hunks = diff.hunks.unwrap();
+        assert_eq!(hunks.len(), 2);
+
+        let first = &hunks[0];
+        assert_eq!((first.old_start, first.old_lines), (1, 5));
+        assert_eq!((first.new_start, first.new_lines), (1, 5));
+        let removed = first.lines.iter().find(|l| l.kind == "remove").unwrap();
+        assert_eq!((removed.old_line, removed.new_line), (Some(2), None));
+        assert_eq!(removed.text, "line 2");
+        let added = first.lines.iter().find(|l| l.kind == "add").unwrap();
+        assert_eq!((added.old_line, added.new_line), (None, Some(2)));
+
+        let second = &hunks[1];
+        assert_eq!((second.old_start, second.old_lines), (15, 6));
+        assert_eq!((second.new_start, second.new_lines), (15, 5));
+    }
+
+    #[test]
+    fn empty_side_starts_before_the_hunk() {
+        let diff = diff_file("a.txt", &FileState::default(), &state("a\n"), &with_hunks()).unwrap();
+        let hunk = &diff.hunks.unwrap()[0];
+        assert_eq!((hunk.old_start, hunk.old_lines), (0, 0));
+        assert_eq!((hunk.new_start, hunk.new_lines), (1, 1));
+    }
+
+    #[test]
+    fn context_option_merges_nearby_hunks() {
+        let old = numbered(20);
+        let new = old
+            .replace("line 5\n", "five\n")
+            .replace("line 12\n", "twelve\n");
+        let options = |context| DiffOptions {
+            hunks: true,
+            context: Some(context),
+            ..Default::default()
+        };
+        let hunks = |context| {
+            diff_file("a.txt", &state(&old), &state(&new), &options(context))
+                .unwrap()
+                .hunks
+                .unwrap()
+                .len()
+        };
+        assert_eq!(hunks(1), 2);
+        assert_eq!(hunks(4), 1);
+    }
+
+    #[test]
+    fn binary_files_are_not_diffed_by_line() {
+        let old = FileState {
+            exists: true,
+            bytes: vec![0xff, 0x00],
+            mode: None,
+        };
+        let diff = diff_file("a.bin", &old, &state("text\n"), &with_hunks()).unwrap();
+        assert!(diff.binary);
+        assert_eq!((diff.additions, diff.deletions), (0, 0));
+        assert!(diff.hunks.is_none());
+    }
+
+    #[test]
+    fn unified_headers_name_missing_sides() {
+        let options = DiffOptions {
+            unified: true,
+            ..Default::default()
+        };
+        let diff = diff_file("a.txt", &FileState::default(), &state("a\n"), &options).unwrap();
+        let unified = diff.unified.unwrap();
+        assert!(unified.starts_with("--- /dev/null\n+++ b/a.txt\n"));
+        assert!(unified.contains("+a\n"));
+    }
+
+    #[test]
+    fn collect_filters_paths_and_totals() {
+        let mut pairs = BTreeMap::new();
+        pairs.insert("a.txt".to_string(), (state("a\n"), state("b\n")));
+        pairs.insert("b.txt".to_string(), (state("a\n"), state("a\nb\n")));
+        pairs.insert("c.txt".to_string(), (state("same\n"), state("same\n")));
+        let all = collect(pairs.clone(), &DiffOptions::default());
+        assert_eq!(all.files.len(), 2);
+        assert_eq!((all.additions, all.deletions), (2, 1));
+
+        let only_b = DiffOptions {
+            paths: Some(vec!["b.txt".to_string()]),
+            ..Default::default()
+        };
+        let some = collect(pairs, &only_b);
+        assert_eq!(some.files.len(), 1);
+        assert_eq!((some.additions, some.deletions), (1, 0));
+    }
+}
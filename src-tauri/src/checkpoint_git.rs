@@ -0,0 +1,744 @@
+//! Git-backed checkpoint storage for projects inside a git work tree.
+//!
+//! Each checkpoint is a commit on its own hidden ref,
+//! `refs/banshee/checkpoints/<session>/<checkpoint id>`, built with plumbing
+//! commands and a throwaway index so the user's index, branch and work tree
+//! are never touched. The commit's tree holds `original/<path>`,
+//! `current/<path>`, `metadata.json` and `modes.json`, and its parent is the
+//! parent checkpoint's commit, so `git log` on a checkpoint ref walks its
+//! history. Tree entries only tell executable files from others, so the exact
+//! permission bits of each side are kept in `modes.json`.
+//!
+//! New checkpoints go here when settings contain
+//! `"checkpoints": { "storage": "git" }`, globally or under
+//! `projects.<dir>`. Checkpoints stored as files stay readable either way.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::checkpoint::{checkpoint_setting, CheckpointMetadata, FileSnapshot, FileState};
+use crate::checkpoint_path::CheckpointPath;
+
+const REF_PREFIX: &str = "refs/banshee/checkpoints";
+const METADATA_FILE: &str = "metadata.json";
+const MODES_FILE: &str = "modes.json";
+
+pub(crate) struct GitCheckpoints {
+    work_tree: PathBuf,
+}
+
+/// A checkpoint ref and the commit it points at.
+#[derive(Clone)]
+struct CheckpointRef {
+    name: String,
+    commit: String,
+}
+
+fn git_storage_enabled(project_dir: &Path) -> bool {
+    checkpoint_setting(project_dir, "storage").is_some_and(|storage| storage == "git")
+}
+
+/// Keeps a ref name component to characters git accepts anywhere.
+pub(crate) fn ref_component(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Unix permission bits as a git tree entry mode.
+fn git_mode(mode: Option<u32>) -> &'static str {
+    match mode {
+        Some(mode) if mode & 0o111 != 0 => "100755",
+        _ => "100644",
+    }
+}
+
+impl GitCheckpoints {
+    /// The store for `project_dir`, if it is inside a git work tree.
+    pub(crate) fn open(project_dir: &Path) -> Option<Self> {
+        let output = Command::new("git")
+            .args(["rev-parse", "--is-inside-work-tree"])
+            .current_dir(project_dir)
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        (output.status.success() && output.stdout.starts_with(b"true")).then(|| GitCheckpoints {
+            work_tree: project_dir.to_path_buf(),
+        })
+    }
+
+    /// The store new checkpoints of `project_dir` are written to, if git
+    /// storage is enabled and the project is a git work tree.
+    pub(crate) fn for_writing(project_dir: &Path) -> Option<Self> {
+        if git_storage_enabled(project_dir) {
+            Self::open(project_dir)
+        } else {
+            None
+        }
+    }
+
+    fn run(
+        &self,
+        args: &[&str],
+        index: Option<&Path>,
+        input: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, String> {
+        let mut command = Command::new("git");
+        command
+            .args(args)
+            .current_dir(&self.work_tree)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Checkpoint commits are the app's, not the user's.
+            .env("GIT_AUTHOR_NAME", "Checkpoints")
+            .env("GIT_AUTHOR_EMAIL", "checkpoints@localhost")
+            .env("GIT_COMMITTER_NAME", "Checkpoints")
+            .env("GIT_COMMITTER_EMAIL", "checkpoints@localhost");
+        if let Some(index) = index {
+            command.env("GIT_INDEX_FILE", index);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("Failed to run git: {}", e))?;
+        // Feed stdin from a thread so a large output cannot stall the write.
+        let writer = match (input, child.stdin.take()) {
+            (Some(input), Some(mut stdin)) => {
+                Some(std::thread::spawn(move || stdin.write_all(&input)))
+            }
+            _ => None,
+        };
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Failed to run git: {}", e))?;
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        if !output.status.success() {
+            return Err(format!(
+                "git {} failed: {}",
+                args.first().copied().unwrap_or_default(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(output.stdout)
+    }
+
+    fn run_text(
+        &self,
+        args: &[&str],
+        index: Option<&Path>,
+        input: Option<Vec<u8>>,
+    ) -> Result<String, String> {
+        self.run(args, index, input)
+            .map(|out| String::from_utf8_lossy(&out).trim().to_string())
+    }
+
+    fn hash_object(&self, bytes: &[u8]) -> Result<String, String> {
+        self.run_text(
+            &["hash-object", "-w", "--stdin"],
+            None,
+            Some(bytes.to_vec()),
+        )
+    }
+
+    /// Writes every item of `contents` as a blob in one git process, staging
+    /// them as files in `scratch`. Returns their ids in order.
+    fn hash_objects(&self, scratch: &Path, contents: &[&[u8]]) -> Result<Vec<String>, String> {
+        let mut paths = String::new();
+        for (index, bytes) in contents.iter().enumerate() {
+            let path = scratch.join(format!("blob-{}", index));
+            std::fs::write(&path, bytes)
+                .map_err(|e| format!("Failed to stage checkpoint blob: {}", e))?;
+            paths.push_str(&path.to_string_lossy());
+            paths.push('\n');
+        }
+        let out = self.run_text(
+            &["hash-object", "-w", "--no-filters", "--stdin-paths"],
+            None,
+            Some(paths.into_bytes()),
+        )?;
+        let shas: Vec<String> = out.lines().map(str::to_string).collect();
+        if shas.len() != contents.len() {
+            return Err("Unexpected git hash-object output".to_string());
+        }
+        Ok(shas)
+    }
+
+    /// Reads objects by revision (`<sha>` or `<commit>:<path>`) in one git
+    /// process; `None` for objects that do not exist.
+    fn cat_objects(&self, specs: &[String]) -> Result<Vec<Option<Vec<u8>>>, String> {
+        if specs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let input = specs.iter().map(|s| format!("{}\n", s)).collect::<String>();
+        let output = self.run(&["cat-file", "--batch"], None, Some(input.into_bytes()))?;
+
+        let mut objects = Vec::with_capacity(specs.len());
+        let mut rest = output.as_slice();
+        for _ in specs {
+            let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+                return Err("Truncated git cat-file output".to_string());
+            };
+            let header = String::from_utf8_lossy(&rest[..end]).to_string();
+            rest = &rest[end + 1..];
+            if header.ends_with(" missing") || header.ends_with(" ambiguous") {
+                objects.push(None);
+                continue;
+            }
+            let size: usize = header
+                .rsplit(' ')
+                .next()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| format!("Unexpected git cat-file header: {}", header))?;
+            if rest.len() < size + 1 {
+                return Err("Truncated git cat-file output".to_string());
+            }
+            objects.push(Some(rest[..size].to_vec()));
+            rest = &rest[size + 1..];
+        }
+        Ok(objects)
+    }
+
+    fn refs(&self) -> Result<Vec<CheckpointRef>, String> {
+        let out = self.run_text(
+            &[
+                "for-each-ref",
+                "--format=%(objectname) %(refname)",
+                &format!("{}/", REF_PREFIX),
+            ],
+            None,
+            None,
+        )?;
+        Ok(out
+            .lines()
+            .filter_map(|line| {
+                let (commit, name) = line.split_once(' ')?;
+                Some(CheckpointRef {
+                    name: name.to_string(),
+                    commit: commit.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// The ref of a checkpoint among `refs`, listed once by callers that
+    /// look up several.
+    fn find<'a>(refs: &'a [CheckpointRef], checkpoint_id: &str) -> Option<&'a CheckpointRef> {
+        let suffix = format!("/{}", ref_component(checkpoint_id));
+        refs.iter().find(|r| r.name.ends_with(&suffix))
+    }
+
+    fn find_one(&self, checkpoint_id: &str) -> Result<Option<CheckpointRef>, String> {
+        Ok(Self::find(&self.refs()?, checkpoint_id).cloned())
+    }
+
+    /// Writes a commit for `metadata` over `tree` and points its ref at it.
+    fn commit(
+        &self,
+        refs: &[CheckpointRef],
+        session_id: &str,
+        metadata: &CheckpointMetadata,
+        tree: &str,
+    ) -> Result<(), String> {
+        let parent = metadata
+            .parent_id
+            .as_deref()
+            .and_then(|parent_id| Self::find(refs, parent_id))
+            .map(|r| r.commit.clone());
+        let subject = metadata
+            .name
+            .as_deref()
+            .or(metadata.trigger.as_deref())
+            .unwrap_or(&metadata.id);
+        let message = format!(
+            "{}\n\nCheckpoint-Id: {}\nCheckpoint-Type: {}\n",
+            subject, metadata.id, metadata.checkpoint_type
+        );
+        let mut args = vec!["commit-tree", tree];
+        if let Some(parent) = &parent {
+            args.extend(["-p", parent.as_str()]);
+        }
+        let commit = self.run_text(&args, None, Some(message.into_bytes()))?;
+
+        let ref_name = match Self::find(refs, &metadata.id) {
+            Some(existing) => existing.name.clone(),
+            None => format!(
+                "{}/{}/{}",
+                REF_PREFIX,
+                ref_component(session_id),
+                ref_component(&metadata.id)
+            ),
+        };
+        self.run(&["update-ref", &ref_name, &commit], None, None)?;
+        Ok(())
+    }
+
+    /// Stores a checkpoint as a commit on its own ref.
+    pub(crate) fn save(
+        &self,
+        session_id: &str,
+        metadata: &CheckpointMetadata,
+        files: &[FileSnapshot],
+    ) -> Result<(), String> {
+        // (tree path, tree entry mode, contents)
+        let mut entries: Vec<(String, &str, &[u8])> = Vec::new();
+        let mut modes: BTreeMap<String, u32> = BTreeMap::new();
+        for file in files {
+            let path = self.tree_path(&file.path)?;
+            let sides = [
+                ("original", file.original_data(), file.original_mode),
+                ("current", file.current_data(), file.current_mode),
+            ];
+            for (side, data, mode) in sides {
+                let Some(data) = data else {
+                    continue;
+                };
+                let entry = format!("{}/{}", side, path);
+                if let Some(mode) = mode {
+                    modes.insert(entry.clone(), mode);
+                }
+                entries.push((entry, git_mode(mode), data));
+            }
+        }
+        let metadata_json = serde_json::to_vec_pretty(metadata)
+            .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+        let modes_json = serde_json::to_vec_pretty(&modes)
+            .map_err(|e| format!("Failed to serialize file modes: {}", e))?;
+        entries.push((METADATA_FILE.to_string(), "100644", &metadata_json));
+        entries.push((MODES_FILE.to_string(), "100644", &modes_json));
+
+        let scratch =
+            tempfile::tempdir().map_err(|e| format!("Failed to create temporary index: {}", e))?;
+        let contents: Vec<&[u8]> = entries.iter().map(|(_, _, data)| *data).collect();
+        let shas = self.hash_objects(scratch.path(), &contents)?;
+        let mut index_info = String::new();
+        for ((entry, mode, _), sha) in entries.iter().zip(&shas) {
+            index_info.push_str(&format!("{} {}\t{}\n", mode, sha, entry));
+        }
+
+        let index = scratch.path().join("index");
+        self.run(
+            &["update-index", "--add", "--index-info"],
+            Some(&index),
+            Some(index_info.into_bytes()),
+        )?;
+        let tree = self.run_text(&["write-tree"], Some(&index), None)?;
+        self.commit(&self.refs()?, session_id, metadata, &tree)
+    }
+
+    /// Rewrites a checkpoint's commit with new metadata, keeping its files.
+    /// Returns false if the checkpoint is not stored in git.
+    pub(crate) fn update_metadata(&self, metadata: &CheckpointMetadata) -> Result<bool, String> {
+        let refs = self.refs()?;
+        let Some(existing) = Self::find(&refs, &metadata.id) else {
+            return Ok(false);
+        };
+        let metadata_json = serde_json::to_vec_pretty(metadata)
+            .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+        let metadata_sha = self.hash_object(&metadata_json)?;
+
+        let scratch =
+            tempfile::tempdir().map_err(|e| format!("Failed to create temporary index: {}", e))?;
+        let index = scratch.path().join("index");
+        self.run(&["read-tree", &existing.commit], Some(&index), None)?;
+        self.run(
+            &[
+                "update-index",
+                "--add",
+                "--cacheinfo",
+                &format!("100644,{},{}", metadata_sha, METADATA_FILE),
+            ],
+            Some(&index),
+            None,
+        )?;
+        let tree = self.run_text(&["write-tree"], Some(&index), None)?;
+        let session = existing
+            .name
+            .trim_start_matches(&format!("{}/", REF_PREFIX))
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        self.commit(&refs, &session, metadata, &tree)?;
+        Ok(true)
+    }
+
+    /// Metadata of every checkpoint stored in git.
+    pub(crate) fn list(&self) -> Result<Vec<CheckpointMetadata>, String> {
+        let specs: Vec<String> = self
+            .refs()?
+            .iter()
+            .map(|r| format!("{}:{}", r.commit, METADATA_FILE))
+            .collect();
+        Ok(self
+            .cat_objects(&specs)?
+            .into_iter()
+            .flatten()
+            .filter_map(|json| serde_json::from_slice(&json).ok())
+            .collect())
+    }
+
+    pub(crate) fn metadata(
+        &self,
+        checkpoint_id: &str,
+    ) -> Result<Option<CheckpointMetadata>, String> {
+        let Some(found) = self.find_one(checkpoint_id)? else {
+            return Ok(None);
+        };
+        let spec = format!("{}:{}", found.commit, METADATA_FILE);
+        match self.cat_objects(&[spec])?.pop().flatten() {
+            Some(json) => serde_json::from_slice(&json)
+                .map(Some)
+                .map_err(|e| format!("Failed to parse metadata: {}", e)),
+            None => Ok(None),
+        }
+    }
+
+    /// The id of every checkpoint stored in git, as it appears in ref names.
+    pub(crate) fn ref_ids(&self) -> Result<Vec<String>, String> {
+        Ok(self
+            .refs()?
+            .into_iter()
+            .filter_map(|r| r.name.rsplit('/').next().map(str::to_string))
+            .collect())
+    }
+
+    /// The paths of a checkpoint's files, without reading their contents.
+    pub(crate) fn paths(&self, checkpoint_id: &str) -> Result<Vec<String>, String> {
+        let Some(found) = self.find_one(checkpoint_id)? else {
+            return Ok(Vec::new());
+        };
+        let listing = self.run(
+            &["ls-tree", "-r", "-z", "--name-only", &found.commit],
+            None,
+            None,
+        )?;
+        let mut paths: Vec<String> = listing
+            .split(|&b| b == 0)
+            .filter_map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                entry
+                    .strip_prefix("original/")
+                    .or_else(|| entry.strip_prefix("current/"))
+                    .map(str::to_string)
+            })
+            .collect();
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// The files of a checkpoint, or `None` if it is not stored in git.
+    pub(crate) fn snapshots(
+        &self,
+        checkpoint_id: &str,
+    ) -> Result<Option<Vec<FileSnapshot>>, String> {
+        let Some(found) = self.find_one(checkpoint_id)? else {
+            return Ok(None);
+        };
+        let listing = self.run(&["ls-tree", "-r", "-z", &found.commit], None, None)?;
+
+        // path -> (original, current) as (mode, blob sha)
+        type Side = Option<(String, String)>;
+        let mut files: BTreeMap<String, (Side, Side)> = BTreeMap::new();
+        for entry in listing.split(|&b| b == 0).filter(|e| !e.is_empty()) {
+            let entry = String::from_utf8_lossy(entry);
+            let Some((info, path)) = entry.split_once('\t') else {
+                continue;
+            };
+            let mut info = info.split(' ');
+            let (Some(mode), Some(_kind), Some(sha)) = (info.next(), info.next(), info.next())
+            else {
+                continue;
+            };
+            let side = Some((mode.to_string(), sha.to_string()));
+            if let Some(path) = path.strip_prefix("original/") {
+                files.entry(path.to_string()).or_default().0 = side;
+            } else if let Some(path) = path.strip_prefix("current/") {
+                files.entry(path.to_string()).or_default().1 = side;
+            }
+        }
+
+        let mut specs = vec![format!("{}:{}", found.commit, MODES_FILE)];
+        specs.extend(
+            files
+                .values()
+                .flat_map(|(original, current)| [original, current])
+                .flatten()
+                .map(|(_, sha)| sha.clone()),
+        );
+        let mut blobs = self.cat_objects(&specs)?.into_iter();
+        // Absent from checkpoints saved before modes were recorded.
+        let modes: Option<BTreeMap<String, u32>> = blobs
+            .next()
+            .flatten()
+            .and_then(|json| serde_json::from_slice(&json).ok());
+        let mut state = |side: &Side, entry: String| -> Result<FileState, String> {
+            let Some((mode, sha)) = side else {
+                return Ok(FileState::default());
+            };
+            let bytes = blobs
+                .next()
+                .flatten()
+                .ok_or_else(|| format!("Checkpoint blob {} is missing", sha))?;
+            let mode = match &modes {
+                Some(modes) => modes.get(&entry).copied(),
+                None => Some(if mode == "100755" { 0o755 } else { 0o644 }),
+            };
+            Ok(FileState {
+                exists: true,
+                bytes,
+                mode,
+            })
+        };
+
+        let mut snapshots = Vec::with_capacity(files.len());
+        for (path, (original, current)) in &files {
+            let original = state(original, format!("original/{}", path))?;
+            let current = state(current, format!("current/{}", path))?;
+            snapshots.push(FileSnapshot::from_states(path.clone(), original, current));
+        }
+        Ok(Some(snapshots))
+    }
+
+    /// Drops a checkpoint's ref; its objects go with the next `git gc`.
+    /// Returns false if the checkpoint is not stored in git.
+    pub(crate) fn delete(&self, checkpoint_id: &str) -> Result<bool, String> {
+        let Some(found) = self.find_one(checkpoint_id)? else {
+            return Ok(false);
+        };
+        self.run(&["update-ref", "-d", &found.name], None, None)?;
+        Ok(true)
+    }
+
+    /// `path` relative to the work tree, as stored in checkpoint trees.
+    fn tree_path(&self, path: &str) -> Result<String, String> {
+        let relative = CheckpointPath::resolve(&self.work_tree, path)?
+            .key()
+            .replace('\\', "/");
+        if relative.contains(['\n', '\t']) {
+            return Err(format!("Invalid checkpoint path: {}", path));
+        }
+        Ok(relative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::fs;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@localhost"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// A repository with one commit, and its checkpoint store.
+    fn repo() -> (tempfile::TempDir, GitCheckpoints) {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q"]);
+        fs::write(dir.path().join("tracked.txt"), "committed").unwrap();
+        git(dir.path(), &["add", "tracked.txt"]);
+        git(dir.path(), &["commit", "-q", "-m", "initial"]);
+        let store = GitCheckpoints::open(dir.path()).unwrap();
+        (dir, store)
+    }
+
+    fn metadata(id: &str, parent_id: Option<&str>) -> CheckpointMetadata {
+        CheckpointMetadata {
+            id: id.to_string(),
+            timestamp: Utc::now(),
+            name: None,
+            checkpoint_type: "auto".to_string(),
+            trigger: Some("edit:a.txt".to_string()),
+            file_count: 1,
+            git_branch: None,
+            git_commit: None,
+            tags: Vec::new(),
+            parent_id: parent_id.map(str::to_string),
+            imported: None,
+        }
+    }
+
+    fn state(contents: &str, mode: Option<u32>) -> FileState {
+        FileState {
+            exists: true,
+            bytes: contents.as_bytes().to_vec(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn round_trips_checkpoints() {
+        let (_dir, store) = repo();
+        let files = vec![
+            FileSnapshot::from_states("a.txt".into(), state("before", None), state("after", None)),
+            FileSnapshot::from_states(
+                "src/new.rs".into(),
+                FileState::default(),
+                state("fn main() {}", None),
+            ),
+            FileSnapshot::from_states("gone.txt".into(), state("old", None), FileState::default()),
+        ];
+        let saved = metadata("cp-1", None);
+        store.save("session/1", &saved, &files).unwrap();
+
+        let read = store.metadata("cp-1").unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&saved).unwrap()
+        );
+        assert_eq!(store.list().unwrap().len(), 1);
+        assert_eq!(store.ref_ids().unwrap(), vec!["cp-1"]);
+        assert_eq!(
+            store.paths("cp-1").unwrap(),
+            vec!["a.txt", "gone.txt", "src/new.rs"]
+        );
+
+        let snapshots = store.snapshots("cp-1").unwrap().unwrap();
+        let by_path = |path: &str| snapshots.iter().find(|s| s.path == path).unwrap();
+        assert_eq!(by_path("a.txt").original_data(), Some(&b"before"[..]));
+        assert_eq!(by_path("a.txt").current_data(), Some(&b"after"[..]));
+        assert_eq!(by_path("src/new.rs").original_data(), None);
+        assert_eq!(by_path("gone.txt").current_data(), None);
+        assert!(store.snapshots("cp-missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn links_checkpoints_to_their_parents() {
+        let (dir, store) = repo();
+        let file = || {
+            vec![FileSnapshot::from_states(
+                "a.txt".into(),
+                state("a", None),
+                state("b", None),
+            )]
+        };
+        store.save("s1", &metadata("first", None), &file()).unwrap();
+        store
+            .save("s1", &metadata("second", Some("first")), &file())
+            .unwrap();
+
+        let parent = git(
+            dir.path(),
+            &["rev-parse", &format!("{}/s1/second^", REF_PREFIX)],
+        );
+        let first = git(
+            dir.path(),
+            &["rev-parse", &format!("{}/s1/first", REF_PREFIX)],
+        );
+        assert_eq!(parent, first);
+    }
+
+    #[test]
+    fn keeps_exact_file_modes() {
+        let (_dir, store) = repo();
+        let files = vec![
+            FileSnapshot::from_states(
+                "run.sh".into(),
+                state("echo", Some(0o644)),
+                state("echo hi", Some(0o755)),
+            ),
+            FileSnapshot::from_states(
+                "secret.txt".into(),
+                state("a", Some(0o600)),
+                state("b", None),
+            ),
+        ];
+        store.save("s1", &metadata("modes", None), &files).unwrap();
+
+        let snapshots = store.snapshots("modes").unwrap().unwrap();
+        assert_eq!(snapshots[0].path, "run.sh");
+        assert_eq!(snapshots[0].original_mode, Some(0o644));
+        assert_eq!(snapshots[0].current_mode, Some(0o755));
+        assert_eq!(snapshots[1].original_mode, Some(0o600));
+        assert_eq!(snapshots[1].current_mode, None);
+    }
+
+    #[test]
+    fn updates_metadata_and_keeps_files() {
+        let (_dir, store) = repo();
+        let files = vec![FileSnapshot::from_states(
+            "a.txt".into(),
+            state("a", None),
+            state("b", None),
+        )];
+        store.save("s1", &metadata("cp", None), &files).unwrap();
+
+        let mut renamed = metadata("cp", None);
+        renamed.name = Some("Before refactor".to_string());
+        assert!(store.update_metadata(&renamed).unwrap());
+        assert!(!store.update_metadata(&metadata("other", None)).unwrap());
+
+        let read = store.metadata("cp").unwrap().unwrap();
+        assert_eq!(read.name.as_deref(), Some("Before refactor"));
+        assert_eq!(store.paths("cp").unwrap(), vec!["a.txt"]);
+    }
+
+    #[test]
+    fn deletes_only_the_checkpoint_ref() {
+        let (_dir, store) = repo();
+        let files = vec![FileSnapshot::from_states(
+            "a.txt".into(),
+            state("a", None),
+            state("b", None),
+        )];
+        store.save("s1", &metadata("keep", None), &files).unwrap();
+        store.save("s1", &metadata("drop", None), &files).unwrap();
+
+        assert!(store.delete("drop").unwrap());
+        assert!(!store.delete("drop").unwrap());
+        assert_eq!(store.ref_ids().unwrap(), vec!["keep"]);
+        assert!(store.metadata("drop").unwrap().is_none());
+    }
+
+    #[test]
+    fn leaves_the_users_index_head_and_work_tree_alone() {
+        let (dir, store) = repo();
+        fs::write(dir.path().join("tracked.txt"), "staged").unwrap();
+        git(dir.path(), &["add", "tracked.txt"]);
+        fs::write(dir.path().join("tracked.txt"), "unstaged").unwrap();
+        let head = git(dir.path(), &["rev-parse", "HEAD"]);
+        let branch = git(dir.path(), &["symbolic-ref", "HEAD"]);
+        let index = fs::read(dir.path().join(".git/index")).unwrap();
+        let status = git(dir.path(), &["status", "--porcelain"]);
+
+        let files = vec![FileSnapshot::from_states(
+            "tracked.txt".into(),
+            state("committed", None),
+            state("unstaged", None),
+        )];
+        store.save("s1", &metadata("cp", None), &files).unwrap();
+        store.delete("cp").unwrap();
+
+        assert_eq!(git(dir.path(), &["rev-parse", "HEAD"]), head);
+        assert_eq!(git(dir.path(), &["symbolic-ref", "HEAD"]), branch);
+        assert_eq!(fs::read(dir.path().join(".git/index")).unwrap(), index);
+        assert_eq!(git(dir.path(), &["status", "--porcelain"]), status);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("tracked.txt")).unwrap(),
+            "unstaged"
+        );
+    }
+}
@@ -0,0 +1,619 @@
+//! Append-only index of a project's checkpoints, so listing and querying them
+//! does not parse every `metadata.json`.
+//!
+//! `index.jsonl` in the checkpoints directory gets a line for every change: a
+//! saved checkpoint's metadata and file paths, new metadata for a renamed or
+//! re-tagged one, or the id of a deleted one. Reading replays the lines in
+//! order. Checkpoints the index does not know about (saved by an older
+//! version, moved in by `migrate_checkpoints`, or lost to a failed append) are
+//! picked up the first time it is read in a process and whenever one of the
+//! checkpoint directories changed since, and the file is rewritten compacted
+//! once it holds many more lines than checkpoints.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tauri::command;
+
+use crate::checkpoint::{checkpoints_dir, project_root_for, CheckpointMetadata};
+use crate::checkpoint_git::{ref_component, GitCheckpoints};
+use crate::checkpoint_location;
+use crate::checkpoint_store;
+
+const INDEX_FILE: &str = "index.jsonl";
+// Slack before a rewrite, so a few updates do not trigger one each.
+const COMPACT_SLACK: usize = 64;
+
+// Appends, replays and rewrites of any index are serialized.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+// Modification times of a project's checkpoint roots when its index, keyed
+// by index directory, was last reconciled.
+static RECONCILED: Lazy<Mutex<HashMap<PathBuf, Vec<Option<SystemTime>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexEntry {
+    #[serde(flatten)]
+    pub metadata: CheckpointMetadata,
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum IndexRecord {
+    Put(IndexEntry),
+    /// New metadata for a checkpoint already indexed; its files are unchanged.
+    Update(CheckpointMetadata),
+    Delete(String),
+}
+
+fn append(session_id: &str, record: &IndexRecord) {
+    let result = checkpoints_dir(session_id).and_then(|root| {
+        let line = serde_json::to_string(record)
+            .map_err(|e| format!("Failed to serialize index record: {}", e))?;
+        let _guard = INDEX_LOCK.lock().unwrap();
+        fs::create_dir_all(&root)
+            .map_err(|e| format!("Failed to create checkpoints directory: {}", e))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(root.join(INDEX_FILE))
+            .map_err(|e| format!("Failed to open checkpoint index: {}", e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write checkpoint index: {}", e))
+    });
+    // The index repairs itself from the stored checkpoints on the next read.
+    if let Err(e) = result {
+        eprintln!("[RUST] {}", e);
+    }
+}
+
+pub(crate) fn record_saved(session_id: &str, metadata: &CheckpointMetadata, files: Vec<String>) {
+    append(
+        session_id,
+        &IndexRecord::Put(IndexEntry {
+            metadata: metadata.clone(),
+            files,
+        }),
+    );
+}
+
+pub(crate) fn record_updated(session_id: &str, metadata: &CheckpointMetadata) {
+    append(session_id, &IndexRecord::Update(metadata.clone()));
+}
+
+pub(crate) fn record_deleted(session_id: &str, checkpoint_id: &str) {
+    append(session_id, &IndexRecord::Delete(checkpoint_id.to_string()));
+}
+
+/// Replays the index. Returns the entries by id and the number of lines.
+fn replay(root: &Path) -> (HashMap<String, IndexEntry>, usize) {
+    let mut entries: HashMap<String, IndexEntry> = HashMap::new();
+    let Ok(contents) = fs::read_to_string(root.join(INDEX_FILE)) else {
+        return (entries, 0);
+    };
+    let mut lines = 0;
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        lines += 1;
+        // A line cut short by a crash is skipped; reconciling restores it.
+        match serde_json::from_str(line) {
+            Ok(IndexRecord::Put(entry)) => {
+                entries.insert(entry.metadata.id.clone(), entry);
+            }
+            Ok(IndexRecord::Update(metadata)) => {
+                if let Some(entry) = entries.get_mut(&metadata.id) {
+                    entry.metadata = metadata;
+                }
+            }
+            Ok(IndexRecord::Delete(id)) => {
+                entries.remove(&id);
+            }
+            Err(_) => {}
+        }
+    }
+    (entries, lines)
+}
+
+/// Paths of the files of a checkpoint stored as a directory.
+fn stored_paths(dir: &Path) -> Vec<String> {
+    if let Ok(Some(manifest)) = checkpoint_store::read_manifest(dir) {
+        return manifest.files.into_iter().map(|f| f.path).collect();
+    }
+    fs::read_to_string(dir.join("file_mapping.json"))
+        .ok()
+        .and_then(|json| serde_json::from_str::<HashMap<String, usize>>(&json).ok())
+        .map(|mapping| mapping.into_keys().collect())
+        .unwrap_or_default()
+}
+
+fn read_stored(dir: &Path) -> Option<IndexEntry> {
+    let json = fs::read_to_string(dir.join("metadata.json")).ok()?;
+    Some(IndexEntry {
+        metadata: serde_json::from_str(&json).ok()?,
+        files: stored_paths(dir),
+    })
+}
+
+/// Brings `entries` in line with the checkpoints actually stored under
+/// `roots` and in git: adds the ones missing and drops the ones gone. Returns
+/// whether anything changed.
+fn reconcile(
+    roots: &[PathBuf],
+    git: Option<&GitCheckpoints>,
+    entries: &mut HashMap<String, IndexEntry>,
+) -> bool {
+    let mut changed = false;
+    let mut stored: HashSet<String> = HashSet::new();
+    for root in roots {
+        let Ok(dirs) = fs::read_dir(root) else {
+            continue;
+        };
+        for dir in dirs.flatten().map(|d| d.path()) {
+            if !dir.join("metadata.json").is_file() {
+                continue;
+            }
+            let Some(id) = dir.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                continue;
+            };
+            if !stored.insert(id.clone()) || entries.contains_key(&id) {
+                continue;
+            }
+            if let Some(entry) = read_stored(&dir) {
+                entries.insert(id, entry);
+                changed = true;
+            }
+        }
+    }
+
+    // `None` when git could not be asked; its checkpoints are kept as indexed.
+    let git_ids: Option<HashSet<String>> = match git {
+        Some(git) => git.ref_ids().ok().map(|ids| ids.into_iter().collect()),
+        None => Some(HashSet::new()),
+    };
+    let before = entries.len();
+    entries.retain(|id, _| {
+        stored.contains(id)
+            || git_ids
+                .as_ref()
+                .map_or(true, |ids| ids.contains(&ref_component(id)))
+    });
+    changed |= entries.len() != before;
+
+    let indexed: HashSet<String> = entries.keys().map(|id| ref_component(id)).collect();
+    let unindexed = git_ids.is_some_and(|ids| ids.iter().any(|id| !indexed.contains(id)));
+    if let Some(git) = git.filter(|_| unindexed) {
+        for metadata in git.list().unwrap_or_default() {
+            if indexed.contains(&ref_component(&metadata.id)) {
+                continue;
+            }
+            let files = git.paths(&metadata.id).unwrap_or_default();
+            entries.insert(metadata.id.clone(), IndexEntry { metadata, files });
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The modification time of each root, which changes when a checkpoint
+/// directory is added to or removed from it.
+fn root_times(roots: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    roots
+        .iter()
+        .map(|root| fs::metadata(root).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+fn rewrite(root: &Path, entries: &[IndexEntry]) -> Result<(), String> {
+    fs::create_dir_all(root)
+        .map_err(|e| format!("Failed to create checkpoints directory: {}", e))?;
+    let mut contents = String::new();
+    for entry in entries {
+        let line = serde_json::to_string(&IndexRecord::Put(entry.clone()))
+            .map_err(|e| format!("Failed to serialize index record: {}", e))?;
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    let temp = root.join(format!("{}.tmp", INDEX_FILE));
+    fs::write(&temp, contents).map_err(|e| format!("Failed to write checkpoint index: {}", e))?;
+    fs::rename(&temp, root.join(INDEX_FILE))
+        .map_err(|e| format!("Failed to replace checkpoint index: {}", e))
+}
+
+/// Folds the index at `source` into the one at `destination`, whose entries
+/// win, for checkpoints moved from one to the other.
+pub(crate) fn merge(source: &Path, destination: &Path) -> Result<(), String> {
+    let _guard = INDEX_LOCK.lock().unwrap();
+    let (mut entries, _) = replay(source);
+    if entries.is_empty() {
+        return Ok(());
+    }
+    entries.extend(replay(destination).0);
+    let mut entries: Vec<IndexEntry> = entries.into_values().collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.metadata.timestamp));
+    rewrite(destination, &entries)
+}
+
+/// Every checkpoint of the session's project, newest first.
+pub(crate) fn entries(session_id: &str) -> Result<Vec<IndexEntry>, String> {
+    let project_dir = project_root_for(session_id)?;
+    let root = checkpoints_dir(session_id)?;
+    let roots = checkpoint_location::roots(&project_dir);
+
+    let _guard = INDEX_LOCK.lock().unwrap();
+    let (mut entries, lines) = replay(&root);
+    let times = root_times(&roots);
+    let stale = RECONCILED.lock().unwrap().get(&root) != Some(&times);
+    let changed = stale && {
+        let git = GitCheckpoints::open(&project_dir);
+        reconcile(&roots, git.as_ref(), &mut entries)
+    };
+    let mut entries: Vec<IndexEntry> = entries.into_values().collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.metadata.timestamp));
+    if changed || lines > entries.len() * 2 + COMPACT_SLACK {
+        if let Err(e) = rewrite(&root, &entries) {
+            eprintln!("[RUST] {}", e);
+        }
+    }
+    if stale {
+        // Times from before reconciling, so a checkpoint stored meanwhile is
+        // not missed; a rewrite above only costs one more reconcile.
+        RECONCILED.lock().unwrap().insert(root, times);
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CheckpointQuery {
+    /// Only checkpoints taken at or after / before this time.
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// A trigger, or its prefix before `:` (`"edit"` matches `"edit:src/a.rs"`).
+    pub trigger: Option<String>,
+    pub git_branch: Option<String>,
+    /// Only checkpoints holding this file, as stored in the checkpoint.
+    pub path: Option<String>,
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl CheckpointQuery {
+    fn matches(&self, entry: &IndexEntry) -> bool {
+        let metadata = &entry.metadata;
+        self.since.map_or(true, |since| metadata.timestamp >= since)
+            && self.until.map_or(true, |until| metadata.timestamp < until)
+            && self.trigger.as_ref().map_or(true, |wanted| {
+                metadata.trigger.as_deref().is_some_and(|trigger| {
+                    trigger == wanted
+                        || trigger
+                            .strip_prefix(wanted.as_str())
+                            .is_some_and(|rest| rest.starts_with(':'))
+                })
+            })
+            && self
+                .git_branch
+                .as_ref()
+                .map_or(true, |branch| metadata.git_branch.as_ref() == Some(branch))
+            && self
+                .path
+                .as_ref()
+                .map_or(true, |path| entry.files.contains(path))
+    }
+}
+
+/// One page of matching checkpoints, newest first.
+#[derive(Debug, Serialize)]
+pub struct CheckpointPage {
+    pub checkpoints: Vec<CheckpointMetadata>,
+    /// Matching checkpoints across all pages.
+    pub total: usize,
+}
+
+fn page(entries: Vec<IndexEntry>, query: &CheckpointQuery) -> CheckpointPage {
+    let matching: Vec<IndexEntry> = entries
+        .into_iter()
+        .filter(|entry| query.matches(entry))
+        .collect();
+    let total = matching.len();
+    let checkpoints = matching
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(|entry| entry.metadata)
+        .collect();
+    CheckpointPage { checkpoints, total }
+}
+
+#[command]
+pub async fn query_checkpoints(
+    session_id: String,
+    query: Option<CheckpointQuery>,
+) -> Result<CheckpointPage, String> {
+    Ok(page(entries(&session_id)?, &query.unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint_store::{Manifest, ManifestEntry};
+    use chrono::TimeZone;
+
+    fn metadata(id: &str, minute: u32) -> CheckpointMetadata {
+        CheckpointMetadata {
+            id: id.to_string(),
+            timestamp: Utc.with_ymd_and_hms(2026, 3, 10, 12, minute, 0).unwrap(),
+            name: None,
+            checkpoint_type: "auto".to_string(),
+            trigger: None,
+            file_count: 1,
+            git_branch: None,
+            git_commit: None,
+            tags: Vec::new(),
+            parent_id: None,
+            imported: None,
+        }
+    }
+
+    fn entry(id: &str, minute: u32, files: &[&str]) -> IndexEntry {
+        IndexEntry {
+            metadata: metadata(id, minute),
+            files: files.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    fn write_records(root: &Path, records: &[IndexRecord]) {
+        let mut contents = String::new();
+        for record in records {
+            contents.push_str(&serde_json::to_string(record).unwrap());
+            contents.push('\n');
+        }
+        fs::write(root.join(INDEX_FILE), contents).unwrap();
+    }
+
+    /// A checkpoint directory as `write_checkpoint` leaves it.
+    fn store(root: &Path, metadata: &CheckpointMetadata, paths: &[&str]) {
+        let dir = root.join(&metadata.id);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("metadata.json"),
+            serde_json::to_string(metadata).unwrap(),
+        )
+        .unwrap();
+        let files = paths
+            .iter()
+            .map(|path| ManifestEntry {
+                path: path.to_string(),
+                original: None,
+                current: None,
+                original_mode: None,
+                current_mode: None,
+            })
+            .collect();
+        checkpoint_store::write_manifest(&dir, &Manifest::new(files)).unwrap();
+    }
+
+    fn ids(entries: &HashMap<String, IndexEntry>) -> Vec<String> {
+        let mut ids: Vec<String> = entries.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn replay_applies_records_in_order() {
+        let root = tempfile::tempdir().unwrap();
+        let mut renamed = metadata("a", 1);
+        renamed.name = Some("renamed".to_string());
+        write_records(
+            root.path(),
+            &[
+                IndexRecord::Put(entry("a", 1, &["src/a.rs"])),
+                IndexRecord::Put(entry("b", 2, &[])),
+                IndexRecord::Update(renamed),
+                IndexRecord::Delete("b".to_string()),
+                // Updates for unknown checkpoints are ignored.
+                IndexRecord::Update(metadata("c", 3)),
+            ],
+        );
+        let (entries, lines) = replay(root.path());
+        assert_eq!(lines, 5);
+        assert_eq!(ids(&entries), vec!["a"]);
+        let a = &entries["a"];
+        assert_eq!(a.metadata.name.as_deref(), Some("renamed"));
+        assert_eq!(a.files, vec!["src/a.rs"]);
+    }
+
+    #[test]
+    fn replay_skips_torn_lines() {
+        let root = tempfile::tempdir().unwrap();
+        let good = serde_json::to_string(&IndexRecord::Put(entry("a", 1, &[]))).unwrap();
+        let torn = &good[..good.len() / 2];
+        fs::write(
+            root.path().join(INDEX_FILE),
+            format!("{}\n{}\n\n", good, torn),
+        )
+        .unwrap();
+        let (entries, lines) = replay(root.path());
+        assert_eq!(lines, 2);
+        assert_eq!(ids(&entries), vec!["a"]);
+    }
+
+    #[test]
+    fn missing_index_is_empty() {
+        let root = tempfile::tempdir().unwrap();
+        let (entries, lines) = replay(root.path());
+        assert!(entries.is_empty());
+        assert_eq!(lines, 0);
+    }
+
+    #[test]
+    fn rewrite_compacts_to_one_line_per_checkpoint() {
+        let root = tempfile::tempdir().unwrap();
+        let entries = vec![entry("b", 2, &["b.rs"]), entry("a", 1, &["a.rs"])];
+        rewrite(root.path(), &entries).unwrap();
+        let (replayed, lines) = replay(root.path());
+        assert_eq!(lines, 2);
+        assert_eq!(ids(&replayed), vec!["a", "b"]);
+        assert_eq!(replayed["b"].files, vec!["b.rs"]);
+    }
+
+    #[test]
+    fn root_times_change_when_checkpoints_come_and_go() {
+        let root = tempfile::tempdir().unwrap();
+        let roots = vec![root.path().to_path_buf(), root.path().join("missing")];
+        let before = root_times(&roots);
+        assert!(before[0].is_some());
+        assert!(before[1].is_none());
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        store(root.path(), &metadata("a", 1), &["a.rs"]);
+        let stored = root_times(&roots);
+        assert_ne!(stored, before);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(root.path().join("a").join("metadata.json"), "{}").unwrap();
+        assert_eq!(root_times(&roots), stored);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::remove_dir_all(root.path().join("a")).unwrap();
+        assert_ne!(root_times(&roots), stored);
+    }
+
+    #[test]
+    fn reconcile_adds_stored_and_drops_missing_checkpoints() {
+        let active = tempfile::tempdir().unwrap();
+        let legacy = tempfile::tempdir().unwrap();
+        store(active.path(), &metadata("indexed", 1), &["a.rs"]);
+        store(active.path(), &metadata("unindexed", 2), &["b.rs", "c.rs"]);
+        store(legacy.path(), &metadata("legacy", 3), &["d.rs"]);
+        // The same id in two roots is indexed once.
+        store(legacy.path(), &metadata("indexed", 1), &["a.rs"]);
+
+        let mut entries = HashMap::new();
+        entries.insert("indexed".to_string(), entry("indexed", 1, &["a.rs"]));
+        entries.insert("deleted".to_string(), entry("deleted", 4, &[]));
+        let roots = vec![active.path().to_path_buf(), legacy.path().to_path_buf()];
+
+        assert!(reconcile(&roots, None, &mut entries));
+        assert_eq!(ids(&entries), vec!["indexed", "legacy", "unindexed"]);
+        let mut files = entries["unindexed"].files.clone();
+        files.sort();
+        assert_eq!(files, vec!["b.rs", "c.rs"]);
+
+        assert!(!reconcile(&roots, None, &mut entries));
+    }
+
+    #[test]
+    fn reconcile_reads_legacy_file_mappings() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("old");
+        fs::create_dir(&dir).unwrap();
+        fs::write(
+            dir.join("metadata.json"),
+            serde_json::to_string(&metadata("old", 1)).unwrap(),
+        )
+        .unwrap();
+        fs::write(dir.join("file_mapping.json"), r#"{"src/lib.rs": 0}"#).unwrap();
+
+        let mut entries = HashMap::new();
+        assert!(reconcile(&[root.path().to_path_buf()], None, &mut entries));
+        assert_eq!(entries["old"].files, vec!["src/lib.rs"]);
+    }
+
+    #[test]
+    fn merge_keeps_destination_entries() {
+        let source = tempfile::tempdir().unwrap();
+        let destination = tempfile::tempdir().unwrap();
+        write_records(
+            source.path(),
+            &[
+                IndexRecord::Put(entry("moved", 1, &["a.rs"])),
+                IndexRecord::Put(entry("both", 2, &["from-source.rs"])),
+            ],
+        );
+        write_records(
+            destination.path(),
+            &[IndexRecord::Put(entry("both", 2, &["from-destination.rs"]))],
+        );
+        merge(source.path(), destination.path()).unwrap();
+        let (entries, _) = replay(destination.path());
+        assert_eq!(ids(&entries), vec!["both", "moved"]);
+        assert_eq!(entries["both"].files, vec!["from-destination.rs"]);
+    }
+
+    #[test]
+    fn query_filters() {
+        let mut edit = entry("edit", 10, &["src/a.rs"]);
+        edit.metadata.trigger = Some("edit:src/a.rs".to_string());
+        edit.metadata.git_branch = Some("main".to_string());
+        let matches = |query: CheckpointQuery| query.matches(&edit);
+
+        assert!(matches(CheckpointQuery::default()));
+        assert!(matches(CheckpointQuery {
+            trigger: Some("edit".to_string()),
+            ..Default::default()
+        }));
+        assert!(matches(CheckpointQuery {
+            trigger: Some("edit:src/a.rs".to_string()),
+            ..Default::default()
+        }));
+        assert!(!matches(CheckpointQuery {
+            trigger: Some("ed".to_string()),
+            ..Default::default()
+        }));
+        assert!(!matches(CheckpointQuery {
+            git_branch: Some("dev".to_string()),
+            ..Default::default()
+        }));
+        assert!(matches(CheckpointQuery {
+            path: Some("src/a.rs".to_string()),
+            ..Default::default()
+        }));
+        assert!(!matches(CheckpointQuery {
+            path: Some("src".to_string()),
+            ..Default::default()
+        }));
+
+        let at = edit.metadata.timestamp;
+        assert!(matches(CheckpointQuery {
+            since: Some(at),
+            ..Default::default()
+        }));
+        // `until` is exclusive.
+        assert!(!matches(CheckpointQuery {
+            until: Some(at),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn pages_count_every_match() {
+        let entries: Vec<IndexEntry> = (0..5)
+            .rev()
+            .map(|minute| entry(&format!("cp{}", minute), minute, &[]))
+            .collect();
+        let query = CheckpointQuery {
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let result = page(entries.clone(), &query);
+        assert_eq!(result.total, 5);
+        let ids: Vec<&str> = result.checkpoints.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["cp3", "cp2"]);
+
+        let past_end = CheckpointQuery {
+            offset: 10,
+            ..Default::default()
+        };
+        let result = page(entries, &past_end);
+        assert_eq!(result.total, 5);
+        assert!(result.checkpoints.is_empty());
+    }
+}
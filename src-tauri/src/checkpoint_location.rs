@@ -0,0 +1,346 @@
+//! Where a project's checkpoints are stored.
+//!
+//! Set in the `checkpoints` settings section (or `projects.<dir>.checkpoints`):
+//!
+//! - `"location": "project"` (the default) keeps them in
+//!   `<project>/.conductor/hartford/.checkpoints`,
+//! - `"location": "appData"` in `~/.claude-code/checkpoints/<project hash>`,
+//! - `"location": "custom"` in `<path>/<project hash>`, with `"path"` set.
+//!
+//! Checkpoints left in another layout are still read until
+//! `migrate_checkpoints` moves them into the configured one.
+
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::command;
+
+use crate::checkpoint::{checkpoint_setting, project_root_for, read_heads, HEADS_FILE};
+use crate::checkpoint_index;
+use crate::checkpoint_store;
+
+fn project_dir_layout(project_dir: &Path) -> PathBuf {
+    project_dir
+        .join(".conductor")
+        .join("hartford")
+        .join(".checkpoints")
+}
+
+/// A short, stable name for the project in directories outside it.
+pub(crate) fn project_key(project_dir: &Path) -> String {
+    checkpoint_store::hash(project_dir.to_string_lossy().as_bytes())[..16].to_string()
+}
+
+fn app_data_layout(project_dir: &Path) -> Result<PathBuf, String> {
+    let home = dirs::home_dir().ok_or("Failed to get home directory")?;
+    Ok(home
+        .join(".claude-code")
+        .join("checkpoints")
+        .join(project_key(project_dir)))
+}
+
+fn custom_layout(project_dir: &Path, path: &str) -> PathBuf {
+    Path::new(path).join(project_key(project_dir))
+}
+
+/// The directory new checkpoints of the project are written to.
+pub(crate) fn active_root(project_dir: &Path) -> Result<PathBuf, String> {
+    let setting =
+        |key| checkpoint_setting(project_dir, key).and_then(|v| v.as_str().map(str::to_string));
+    layout(
+        project_dir,
+        setting("location").as_deref(),
+        setting("path").as_deref(),
+    )
+}
+
+/// The directory for the `location` and `path` settings.
+fn layout(
+    project_dir: &Path,
+    location: Option<&str>,
+    path: Option<&str>,
+) -> Result<PathBuf, String> {
+    match location {
+        None | Some("project") => Ok(project_dir_layout(project_dir)),
+        Some("appData") => app_data_layout(project_dir),
+        Some("custom") => match path {
+            Some(path) if !path.is_empty() => Ok(custom_layout(project_dir, path)),
+            _ => Err("Custom checkpoint location has no path configured".to_string()),
+        },
+        Some(other) => Err(format!("Unknown checkpoint location: {}", other)),
+    }
+}
+
+/// Every directory holding checkpoints of the project, the active one first.
+pub(crate) fn roots(project_dir: &Path) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if let Ok(active) = active_root(project_dir) {
+        roots.push(active);
+    }
+    let mut others = vec![project_dir_layout(project_dir)];
+    others.extend(app_data_layout(project_dir).ok());
+    for other in others {
+        if other.is_dir() && !roots.contains(&other) {
+            roots.push(other);
+        }
+    }
+    roots
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationReport {
+    pub destination: String,
+    /// Checkpoint ids moved into `destination`.
+    pub moved: Vec<String>,
+    /// Ids already present in `destination`; the other copy is left in place.
+    pub skipped: Vec<String>,
+    /// Directories emptied and removed.
+    pub removed: Vec<String>,
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Renames `from` to `to`, copying instead when they are on different
+/// filesystems.
+fn move_path(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let copied = if from.is_dir() {
+        copy_dir(from, to)
+    } else {
+        fs::copy(from, to).map(|_| ())
+    };
+    copied.map_err(|e| format!("Failed to move {}: {}", from.display(), e))?;
+    let removed = if from.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    };
+    removed.map_err(|e| format!("Failed to remove {}: {}", from.display(), e))
+}
+
+/// Moves everything under `source` into `destination`. Checkpoints, blobs,
+/// heads and index entries already in `destination` win. Blobs go first, so
+/// a checkpoint never arrives before the contents it refers to.
+fn migrate_root(
+    source: &Path,
+    destination: &Path,
+    report: &mut MigrationReport,
+) -> Result<(), String> {
+    let blobs = source.join("blobs");
+    for shard in fs::read_dir(&blobs).into_iter().flatten().flatten() {
+        for blob in fs::read_dir(shard.path()).into_iter().flatten().flatten() {
+            let hash = blob.file_name().to_string_lossy().to_string();
+            // Leftovers of interrupted writes are not blobs.
+            if hash.ends_with(".tmp") {
+                continue;
+            }
+            checkpoint_store::move_blob(source, destination, &hash)?;
+        }
+    }
+
+    let entries =
+        fs::read_dir(source).map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name == HEADS_FILE || path == blobs || !path.is_dir() {
+            continue;
+        }
+        let target = destination.join(&name);
+        if target.exists() {
+            report.skipped.push(name);
+        } else {
+            move_path(&path, &target)?;
+            report.moved.push(name);
+        }
+    }
+
+    let mut heads = read_heads(source);
+    heads.extend(read_heads(destination));
+    if !heads.is_empty() {
+        let json = serde_json::to_string_pretty(&heads)
+            .map_err(|e| format!("Failed to serialize checkpoint heads: {}", e))?;
+        fs::write(destination.join(HEADS_FILE), json)
+            .map_err(|e| format!("Failed to write checkpoint heads: {}", e))?;
+    }
+    checkpoint_index::merge(source, destination)
+}
+
+/// Moves checkpoints from every other layout, and from `from` (a custom path
+/// used before), into the configured location.
+#[command]
+pub async fn migrate_checkpoints(
+    session_id: String,
+    from: Option<String>,
+) -> Result<MigrationReport, String> {
+    let project_dir = project_root_for(&session_id)?;
+    let destination = active_root(&project_dir)?;
+    fs::create_dir_all(&destination)
+        .map_err(|e| format!("Failed to create checkpoints directory: {}", e))?;
+
+    let mut sources = roots(&project_dir);
+    if let Some(from) = from {
+        let custom = custom_layout(&project_dir, &from);
+        if custom.is_dir() && !sources.contains(&custom) {
+            sources.push(custom);
+        }
+    }
+
+    let mut report = MigrationReport {
+        destination: destination.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    for source in sources.into_iter().filter(|s| *s != destination) {
+        migrate_root(&source, &destination, &mut report)?;
+        let skipped_here = report.skipped.iter().any(|id| source.join(id).exists());
+        if !skipped_here {
+            fs::remove_dir_all(&source)
+                .map_err(|e| format!("Failed to remove {}: {}", source.display(), e))?;
+            report.removed.push(source.to_string_lossy().to_string());
+            // Drop the `.conductor/hartford` the project layout leaves behind,
+            // if nothing else lives there.
+            for parent in source.ancestors().skip(1).take(2) {
+                if parent.starts_with(&project_dir) && parent != project_dir {
+                    let _ = fs::remove_dir(parent);
+                }
+            }
+        }
+    }
+    eprintln!(
+        "[RUST] Migrated {} checkpoints to {}",
+        report.moved.len(),
+        report.destination
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A checkpoint directory whose metadata is just `marker`.
+    fn checkpoint(root: &Path, id: &str, marker: &str) {
+        fs::create_dir_all(root.join(id)).unwrap();
+        fs::write(root.join(id).join("metadata.json"), marker).unwrap();
+    }
+
+    fn write_heads(root: &Path, heads: &[(&str, &str)]) {
+        let heads: HashMap<&str, &str> = heads.iter().copied().collect();
+        fs::write(
+            root.join(HEADS_FILE),
+            serde_json::to_string(&heads).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn resolves_layouts() {
+        let project = Path::new("/work/app");
+        let key = project_key(project);
+        assert_eq!(key.len(), 16);
+        assert_ne!(key, project_key(Path::new("/work/other")));
+
+        let in_project = project.join(".conductor/hartford/.checkpoints");
+        assert_eq!(layout(project, None, None).unwrap(), in_project);
+        assert_eq!(layout(project, Some("project"), None).unwrap(), in_project);
+        assert_eq!(
+            layout(project, Some("appData"), None).unwrap(),
+            dirs::home_dir()
+                .unwrap()
+                .join(".claude-code/checkpoints")
+                .join(&key)
+        );
+        assert_eq!(
+            layout(project, Some("custom"), Some("/backups")).unwrap(),
+            Path::new("/backups").join(&key)
+        );
+        assert!(layout(project, Some("custom"), None).is_err());
+        assert!(layout(project, Some("custom"), Some("")).is_err());
+        assert!(layout(project, Some("cloud"), None).is_err());
+    }
+
+    #[test]
+    fn moves_blobs_checkpoints_and_heads() {
+        let source = tempfile::tempdir().unwrap();
+        let destination = tempfile::tempdir().unwrap();
+        let hash = checkpoint_store::write_blob(source.path(), b"contents").unwrap();
+        checkpoint(source.path(), "a", "from source");
+        write_heads(source.path(), &[("s1", "a")]);
+
+        let mut report = MigrationReport::default();
+        migrate_root(source.path(), destination.path(), &mut report).unwrap();
+
+        assert_eq!(report.moved, vec!["a"]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(
+            checkpoint_store::read_blob(destination.path(), &hash).unwrap(),
+            b"contents"
+        );
+        assert!(checkpoint_store::read_blob(source.path(), &hash).is_err());
+        assert!(destination.path().join("a/metadata.json").is_file());
+        assert!(!source.path().join("a").exists());
+        assert_eq!(read_heads(destination.path())["s1"], "a");
+    }
+
+    #[test]
+    fn leaves_colliding_ids_in_place() {
+        let source = tempfile::tempdir().unwrap();
+        let destination = tempfile::tempdir().unwrap();
+        checkpoint(source.path(), "a", "from source");
+        checkpoint(source.path(), "b", "from source");
+        checkpoint(destination.path(), "b", "from destination");
+        write_heads(source.path(), &[("s1", "a"), ("s2", "b")]);
+        write_heads(destination.path(), &[("s2", "c")]);
+
+        let mut report = MigrationReport::default();
+        migrate_root(source.path(), destination.path(), &mut report).unwrap();
+
+        assert_eq!(report.moved, vec!["a"]);
+        assert_eq!(report.skipped, vec!["b"]);
+        assert_eq!(
+            fs::read_to_string(destination.path().join("b/metadata.json")).unwrap(),
+            "from destination"
+        );
+        assert!(source.path().join("b/metadata.json").is_file());
+        let heads = read_heads(destination.path());
+        assert_eq!(heads["s1"], "a");
+        assert_eq!(heads["s2"], "c");
+    }
+
+    #[test]
+    fn keeps_blobs_that_do_not_match_their_hash() {
+        let source = tempfile::tempdir().unwrap();
+        let destination = tempfile::tempdir().unwrap();
+        let hash = checkpoint_store::write_blob(source.path(), b"contents").unwrap();
+        let blob = source.path().join("blobs").join(&hash[..2]).join(&hash);
+        fs::write(&blob, "corrupted").unwrap();
+        checkpoint(source.path(), "a", "from source");
+
+        let mut report = MigrationReport::default();
+        assert!(migrate_root(source.path(), destination.path(), &mut report).is_err());
+
+        assert!(blob.is_file());
+        assert!(checkpoint_store::read_blob(destination.path(), &hash).is_err());
+        assert!(source.path().join("a").is_dir());
+        assert!(report.moved.is_empty());
+    }
+}
@@ -0,0 +1,358 @@
+//! Hunk-level restore from checkpoints.
+//!
+//! Hunks are numbered as `diff_checkpoint` returns them for the same context
+//! size. Restoring a hunk to the original side undoes that part of the
+//! checkpoint's change; restoring it to the current side redoes it. Each edit
+//! is merged into the working tree three-way, with the checkpoint side being
+//! replaced as the base: an edit lands only where the working tree still
+//! matches that base around it, and is reported as a conflict otherwise.
+
+use serde::{Deserialize, Serialize};
+use similar::DiffTag;
+use std::collections::{BTreeMap, BTreeSet};
+use tauri::command;
+
+use crate::checkpoint::{
+    finish_restore, load_snapshots, project_root_for, FileState, RestoreTransaction,
+};
+use crate::checkpoint_diff::{line_diff, DEFAULT_CONTEXT};
+use crate::checkpoint_path::CheckpointPath;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HunkSelection {
+    pub path: String,
+    /// Indexes into the file's hunks.
+    pub hunks: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HunkConflict {
+    pub path: String,
+    pub hunk: usize,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct HunkRestoreResult {
+    /// Hunks written to the working tree, per file.
+    pub applied: Vec<HunkSelection>,
+    /// Hunks left alone; the rest of the file's selection is still applied.
+    pub conflicts: Vec<HunkConflict>,
+    /// Checkpoint of the files as they were before any hunk was written.
+    pub pre_restore_checkpoint: Option<String>,
+}
+
+/// Replaces base lines `start..end` with `lines`.
+struct Edit {
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+}
+
+/// Where base lines `start..end` sit in the working tree, provided the working
+/// tree left them and the lines on either side untouched.
+fn locate(map: &[Option<usize>], working_len: usize, start: usize, end: usize) -> Option<usize> {
+    if map.is_empty() {
+        return (working_len == 0).then_some(0);
+    }
+    let lo = start.saturating_sub(1);
+    let hi = (end + 1).min(map.len());
+    let first = map[lo]?;
+    for (offset, index) in (lo..hi).enumerate() {
+        if map[index]? != first + offset {
+            return None;
+        }
+    }
+    // A hunk at either end of the file also needs that end unchanged.
+    if start == 0 && first != 0 {
+        return None;
+    }
+    if end == map.len() && map[hi - 1]? + 1 != working_len {
+        return None;
+    }
+    Some(first + (start - lo))
+}
+
+/// Merges the selected hunks into `working` and returns the new text, the
+/// hunks applied and the conflicts.
+fn merge_file(
+    path: &str,
+    original: &str,
+    current: &str,
+    working: &str,
+    hunks: &[usize],
+    to_current: bool,
+    context: usize,
+) -> (String, Vec<usize>, Vec<HunkConflict>) {
+    let checkpoint = line_diff(original, current);
+    let groups = checkpoint.grouped_ops(context);
+    let (base, base_lines, target_lines) = if to_current {
+        (original, checkpoint.old_slices(), checkpoint.new_slices())
+    } else {
+        (current, checkpoint.new_slices(), checkpoint.old_slices())
+    };
+
+    // Base line index -> working tree line index, for lines both still share.
+    let tree = line_diff(base, working);
+    let working_lines = tree.new_slices();
+    let mut map = vec![None; base_lines.len()];
+    for op in tree.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            for (b, w) in old_range.zip(new_range) {
+                map[b] = Some(w);
+            }
+        }
+    }
+
+    let mut applied = Vec::new();
+    let mut conflicts = Vec::new();
+    // (working start, working end, replacement), for every applied edit.
+    let mut splices: Vec<(usize, usize, Vec<String>)> = Vec::new();
+    // Each hunk is spliced in once, however often it was asked for.
+    let hunks: BTreeSet<usize> = hunks.iter().copied().collect();
+    for hunk in hunks {
+        let conflict = |reason: &str| HunkConflict {
+            path: path.to_string(),
+            hunk,
+            reason: reason.to_string(),
+        };
+        let Some(group) = groups.get(hunk) else {
+            conflicts.push(conflict("No such hunk in checkpoint"));
+            continue;
+        };
+        let edits: Vec<Edit> = group
+            .iter()
+            .filter(|op| op.tag() != DiffTag::Equal)
+            .map(|op| {
+                let (base_range, target_range) = if to_current {
+                    (op.old_range(), op.new_range())
+                } else {
+                    (op.new_range(), op.old_range())
+                };
+                Edit {
+                    start: base_range.start,
+                    end: base_range.end,
+                    lines: target_lines[target_range]
+                        .iter()
+                        .map(|line| line.to_string())
+                        .collect(),
+                }
+            })
+            .collect();
+        let located: Option<Vec<(usize, usize, Vec<String>)>> = edits
+            .into_iter()
+            .map(|edit| {
+                let at = locate(&map, working_lines.len(), edit.start, edit.end)?;
+                Some((at, at + (edit.end - edit.start), edit.lines))
+            })
+            .collect();
+        match located {
+            Some(found) => {
+                splices.extend(found);
+                applied.push(hunk);
+            }
+            None => conflicts.push(conflict("Working tree changed around this hunk")),
+        }
+    }
+
+    let mut lines: Vec<String> = working_lines.iter().map(|l| l.to_string()).collect();
+    // Back to front, so earlier positions stay valid.
+    splices.sort_by_key(|&(start, end, _)| std::cmp::Reverse((start, end)));
+    for (start, end, replacement) in splices {
+        lines.splice(start..end, replacement);
+    }
+    (lines.concat(), applied, conflicts)
+}
+
+/// Restores individual hunks of a checkpoint into the working tree. With
+/// `mode == "current"` the hunks are re-applied, otherwise they are reverted
+/// to the original side, as in `restore_checkpoint_with_mode`. `context` must
+/// match the one the hunks were listed with. Files are written all or nothing,
+/// after a pre-restore checkpoint, like a whole-checkpoint restore.
+#[command]
+pub async fn restore_checkpoint_hunks(
+    session_id: String,
+    checkpoint_id: String,
+    selections: Vec<HunkSelection>,
+    mode: String,
+    context: Option<usize>,
+) -> Result<HunkRestoreResult, String> {
+    let base = project_root_for(&session_id)?;
+    let snapshots = load_snapshots(&session_id, &checkpoint_id)?;
+    let to_current = mode == "current";
+    let context = context.unwrap_or(DEFAULT_CONTEXT);
+
+    // A file or hunk selected more than once is restored once.
+    let mut wanted: BTreeMap<String, BTreeSet<usize>> = BTreeMap::new();
+    for selection in selections {
+        wanted
+            .entry(selection.path)
+            .or_default()
+            .extend(selection.hunks);
+    }
+    if let Some(missing) = wanted
+        .keys()
+        .find(|path| !snapshots.iter().any(|s| &s.path == *path))
+    {
+        return Err(format!("File not found in checkpoint: {}", missing));
+    }
+    let targets = wanted
+        .keys()
+        .map(|path| CheckpointPath::resolve(&base, path))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut result = HunkRestoreResult::default();
+    let mut restore = RestoreTransaction::default();
+    let mut stage_all = || -> Result<(), String> {
+        for ((path, hunks), target) in wanted.iter().zip(&targets) {
+            let hunks: Vec<usize> = hunks.iter().copied().collect();
+            let conflict_all = |result: &mut HunkRestoreResult, reason: &str| {
+                result
+                    .conflicts
+                    .extend(hunks.iter().map(|&hunk| HunkConflict {
+                        path: path.clone(),
+                        hunk,
+                        reason: reason.to_string(),
+                    }));
+            };
+            let Some(snapshot) = snapshots.iter().find(|s| &s.path == path) else {
+                continue;
+            };
+            let working = FileState::read(target.absolute())?;
+            let (original, current) = (snapshot.original_state(), snapshot.current_state());
+            let base_side = if to_current { &original } else { &current };
+            let target_side = if to_current { &current } else { &original };
+            if base_side.exists && !working.exists {
+                conflict_all(&mut result, "File is missing from the working tree");
+                continue;
+            }
+
+            let texts = (
+                std::str::from_utf8(&original.bytes),
+                std::str::from_utf8(&current.bytes),
+                std::str::from_utf8(&working.bytes),
+            );
+            let (Ok(original_text), Ok(current_text), Ok(working_text)) = texts else {
+                conflict_all(&mut result, "Binary files can only be restored whole");
+                continue;
+            };
+
+            let (merged, applied, conflicts) = merge_file(
+                path,
+                original_text,
+                current_text,
+                working_text,
+                &hunks,
+                to_current,
+                context,
+            );
+            result.conflicts.extend(conflicts);
+            if applied.is_empty() {
+                continue;
+            }
+
+            // Restoring every change of a file created or deleted by the
+            // checkpoint brings back that side's absence too.
+            let after = if !target_side.exists && merged.is_empty() {
+                FileState::default()
+            } else {
+                FileState {
+                    exists: true,
+                    bytes: merged.into_bytes(),
+                    // An existing file keeps its permissions.
+                    mode: if working.exists {
+                        None
+                    } else {
+                        target_side.mode
+                    },
+                }
+            };
+            restore.stage(path.clone(), target.absolute().to_path_buf(), after)?;
+            result.applied.push(HunkSelection {
+                path: path.clone(),
+                hunks: applied,
+            });
+        }
+        Ok(())
+    };
+    let staged = stage_all();
+    result.pre_restore_checkpoint =
+        finish_restore(&session_id, &checkpoint_id, &mut restore, staged)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+    // Two separate changes: line b, and line k.
+    const CURRENT: &str = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nK\nl\n";
+
+    fn revert(working: &str, hunks: &[usize]) -> (String, Vec<usize>, Vec<HunkConflict>) {
+        merge_file("f.txt", ORIGINAL, CURRENT, working, hunks, false, 1)
+    }
+
+    #[test]
+    fn reverts_only_selected_hunk() {
+        let (merged, applied, conflicts) = revert(CURRENT, &[1]);
+        assert_eq!(merged, "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n");
+        assert_eq!(applied, vec![1]);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn reapplies_hunk_to_current_side() {
+        let (merged, applied, _) = merge_file("f.txt", ORIGINAL, CURRENT, ORIGINAL, &[0], true, 1);
+        assert_eq!(merged, "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n");
+        assert_eq!(applied, vec![0]);
+    }
+
+    #[test]
+    fn duplicate_hunks_apply_once() {
+        let (merged, applied, conflicts) = revert(CURRENT, &[0, 0, 1, 0]);
+        assert_eq!(merged, ORIGINAL);
+        assert_eq!(applied, vec![0, 1]);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn follows_lines_moved_by_other_edits() {
+        let working = "new\nlines\na\nB\nc\nd\ne\nf\ng\nh\ni\nj\nK\nl\n";
+        let (merged, _, conflicts) = revert(working, &[1]);
+        assert_eq!(merged, "new\nlines\na\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n");
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn conflicts_when_working_tree_changed_around_hunk() {
+        // The line next to the first change was edited since.
+        let working = "a\nB\nC\nd\ne\nf\ng\nh\ni\nj\nK\nl\n";
+        let (merged, applied, conflicts) = revert(working, &[0, 1]);
+        assert_eq!(applied, vec![1]);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].hunk, 0);
+        assert_eq!(merged, "a\nB\nC\nd\ne\nf\ng\nh\ni\nj\nk\nl\n");
+    }
+
+    #[test]
+    fn reports_unknown_hunks() {
+        let (merged, applied, conflicts) = revert(CURRENT, &[5]);
+        assert_eq!(merged, CURRENT);
+        assert!(applied.is_empty());
+        assert_eq!(conflicts[0].reason, "No such hunk in checkpoint");
+    }
+
+    #[test]
+    fn hunk_at_file_end_needs_end_unchanged() {
+        let map = [Some(0), Some(1), Some(2)];
+        assert_eq!(locate(&map, 3, 2, 3), Some(2));
+        // Working tree has a line appended after the end of the base.
+        assert_eq!(locate(&map, 4, 2, 3), None);
+        assert_eq!(locate(&map, 3, 0, 1), Some(0));
+        assert_eq!(locate(&[None, Some(0)], 1, 0, 1), None);
+        assert_eq!(locate(&[], 0, 0, 0), Some(0));
+        assert_eq!(locate(&[], 2, 0, 0), None);
+    }
+}
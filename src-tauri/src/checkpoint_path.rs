@@ -0,0 +1,177 @@
+//! Validated locations of checkpointed files.
+//!
+//! Checkpoints key their files by path relative to the project. Those keys
+//! come from the webview, handlers and imported archives, so before anything
+//! is read or written for one it is resolved here: `..` components, absolute
+//! paths outside the project and symlinks leading out of it are refused.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A checkpointed file known to lie inside the project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CheckpointPath {
+    relative: PathBuf,
+    absolute: PathBuf,
+}
+
+impl CheckpointPath {
+    /// Resolves `file_path`, relative to `base` or absolute under it.
+    pub(crate) fn resolve(base: &Path, file_path: &str) -> Result<Self, String> {
+        let path = Path::new(file_path);
+        let canonical_base = fs::canonicalize(base).unwrap_or_else(|_| base.to_path_buf());
+        let stripped = if path.is_absolute() {
+            path.strip_prefix(base)
+                .or_else(|_| path.strip_prefix(&canonical_base))
+                .map_err(|_| format!("Path is outside the project: {}", file_path))?
+        } else {
+            path
+        };
+
+        let mut relative = PathBuf::new();
+        for component in stripped.components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    return Err(format!("Path leaves the project: {}", file_path))
+                }
+                Component::RootDir | Component::Prefix(_) => {
+                    return Err(format!("Path is outside the project: {}", file_path))
+                }
+            }
+        }
+        if relative.as_os_str().is_empty() {
+            return Err(format!("Not a file path: {:?}", file_path));
+        }
+
+        let absolute = base.join(&relative);
+        if let Some(resolved) = resolve_links(&absolute, file_path)? {
+            if !resolved.starts_with(&canonical_base) {
+                return Err(format!(
+                    "Path leads outside the project through a symlink: {}",
+                    file_path
+                ));
+            }
+        }
+        Ok(CheckpointPath { relative, absolute })
+    }
+
+    /// The key the file is stored under in checkpoints.
+    pub(crate) fn key(&self) -> String {
+        self.relative.to_string_lossy().to_string()
+    }
+
+    pub(crate) fn absolute(&self) -> &Path {
+        &self.absolute
+    }
+}
+
+/// Where the deepest existing part of `path` really is, following symlinks.
+/// `None` when nothing of it exists yet. A dangling symlink is refused, as
+/// writing through it could create a file anywhere.
+fn resolve_links(path: &Path, file_path: &str) -> Result<Option<PathBuf>, String> {
+    for existing in path.ancestors() {
+        let Ok(metadata) = fs::symlink_metadata(existing) else {
+            continue;
+        };
+        return match fs::canonicalize(existing) {
+            Ok(resolved) => Ok(Some(resolved)),
+            Err(_) if metadata.file_type().is_symlink() => Err(format!(
+                "Path goes through a dangling symlink: {}",
+                file_path
+            )),
+            Err(e) => Err(format!("Failed to resolve {}: {}", file_path, e)),
+        };
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let base = fs::canonicalize(dir.path()).unwrap().join("project");
+        fs::create_dir(&base).unwrap();
+        (dir, base)
+    }
+
+    #[test]
+    fn keeps_relative_structure() {
+        let (_dir, base) = project();
+        let path = CheckpointPath::resolve(&base, "src/nested/lib.rs").unwrap();
+        assert_eq!(path.key(), Path::new("src/nested/lib.rs").to_string_lossy());
+        assert_eq!(path.absolute(), base.join("src/nested/lib.rs"));
+
+        let path = CheckpointPath::resolve(&base, "./src/./main.rs").unwrap();
+        assert_eq!(path.absolute(), base.join("src/main.rs"));
+    }
+
+    #[test]
+    fn absolute_inside_project_becomes_relative() {
+        let (_dir, base) = project();
+        let file = base.join("src").join("main.rs");
+        let path = CheckpointPath::resolve(&base, &file.to_string_lossy()).unwrap();
+        assert_eq!(path.key(), Path::new("src/main.rs").to_string_lossy());
+        assert_eq!(path.absolute(), file);
+    }
+
+    #[test]
+    fn refuses_absolute_outside_project() {
+        let (dir, base) = project();
+        let outside = dir.path().join("other").join("main.rs");
+        assert!(CheckpointPath::resolve(&base, &outside.to_string_lossy()).is_err());
+        // Escaping through `..` after the project prefix is caught as well.
+        let sneaky = base.join("..").join("other").join("main.rs");
+        assert!(CheckpointPath::resolve(&base, &sneaky.to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn refuses_traversal() {
+        let (_dir, base) = project();
+        for path in ["../secret", "src/../../secret", "src/.."] {
+            assert!(CheckpointPath::resolve(&base, path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn refuses_empty_paths() {
+        let (_dir, base) = project();
+        assert!(CheckpointPath::resolve(&base, "").is_err());
+        assert!(CheckpointPath::resolve(&base, ".").is_err());
+        assert!(CheckpointPath::resolve(&base, &base.to_string_lossy()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlink_escapes() {
+        use std::os::unix::fs::symlink;
+        let (dir, base) = project();
+        let outside = dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret"), "x").unwrap();
+
+        symlink(&outside, base.join("linked_dir")).unwrap();
+        assert!(CheckpointPath::resolve(&base, "linked_dir/secret").is_err());
+        assert!(CheckpointPath::resolve(&base, "linked_dir/new_file").is_err());
+
+        symlink(outside.join("secret"), base.join("linked_file")).unwrap();
+        assert!(CheckpointPath::resolve(&base, "linked_file").is_err());
+
+        symlink(outside.join("missing"), base.join("dangling")).unwrap();
+        assert!(CheckpointPath::resolve(&base, "dangling").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allows_symlinks_within_project() {
+        use std::os::unix::fs::symlink;
+        let (_dir, base) = project();
+        fs::create_dir(base.join("real")).unwrap();
+        symlink(base.join("real"), base.join("alias")).unwrap();
+        let path = CheckpointPath::resolve(&base, "alias/file.rs").unwrap();
+        assert_eq!(path.absolute(), base.join("alias/file.rs"));
+    }
+}
@@ -0,0 +1,415 @@
+//! Retention policies for checkpoints.
+//!
+//! A policy is read from the `checkpoints.retention` settings section (or
+//! `projects.<dir>.checkpoints.retention`), for example:
+//!
+//! ```json
+//! { "maxAgeDays": 30, "maxTotalBytes": 1073741824, "keepManual": true,
+//!   "hourlyAfterHours": 24, "dailyAfterDays": 7 }
+//! ```
+//!
+//! It is applied once an hour to the projects of sessions with a running
+//! handler, and on demand through `apply_checkpoint_retention`.
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter};
+
+use crate::checkpoint::{
+    checkpoint_setting, collect_blobs, project_root_for, read_checkpoint_list, remove_checkpoint,
+    CheckpointMetadata,
+};
+use crate::checkpoint_location;
+
+const FIRST_RUN_DELAY: Duration = Duration::from_secs(5 * 60);
+const RUN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    /// Keep at most this many checkpoints, newest first.
+    pub keep_count: Option<usize>,
+    pub max_age_days: Option<i64>,
+    /// Cap on the size of the checkpoint directories; the oldest checkpoints
+    /// go first. Checkpoints stored in git are not counted.
+    pub max_total_bytes: Option<u64>,
+    /// Never remove manual, named or tagged checkpoints. Defaults to true.
+    pub keep_manual: Option<bool>,
+    /// Past this age, keep only the newest automatic checkpoint per hour.
+    pub hourly_after_hours: Option<i64>,
+    /// Past this age, keep only the newest automatic checkpoint per day.
+    pub daily_after_days: Option<i64>,
+}
+
+impl RetentionPolicy {
+    fn from_settings(project_dir: &Path) -> Self {
+        checkpoint_setting(project_dir, "retention")
+            .and_then(|value| serde_json::from_value(value).ok())
+            .unwrap_or_default()
+    }
+
+    fn is_unset(&self) -> bool {
+        self.keep_count.is_none()
+            && self.max_age_days.is_none()
+            && self.max_total_bytes.is_none()
+            && self.hourly_after_hours.is_none()
+            && self.daily_after_days.is_none()
+    }
+
+    fn protects(&self, metadata: &CheckpointMetadata) -> bool {
+        self.keep_manual.unwrap_or(true)
+            && (metadata.checkpoint_type == "manual"
+                || metadata.name.is_some()
+                || !metadata.tags.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionReport {
+    pub removed: Vec<String>,
+    pub remaining: usize,
+    /// Size of the checkpoint directories before and after.
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub reclaimed_bytes: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct RetentionEvent {
+    project_dir: String,
+    report: RetentionReport,
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Size of every directory holding checkpoints of the project.
+fn stored_size(project_dir: &Path) -> u64 {
+    checkpoint_location::roots(project_dir)
+        .iter()
+        .map(|root| dir_size(root))
+        .sum()
+}
+
+/// Checkpoints (newest first) the count, age and thinning rules remove at
+/// `now`; the size cap is applied separately.
+fn outdated(
+    policy: &RetentionPolicy,
+    checkpoints: &[CheckpointMetadata],
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let mut outdated = Vec::new();
+    let mut hours_kept = HashSet::new();
+    let mut days_kept = HashSet::new();
+    // Newest first, so thinning keeps the newest checkpoint of each bucket.
+    for (index, metadata) in checkpoints.iter().enumerate() {
+        if policy.protects(metadata) {
+            continue;
+        }
+        let age = now - metadata.timestamp;
+        let seconds = metadata.timestamp.timestamp();
+        let beyond_count = policy.keep_count.is_some_and(|count| index >= count);
+        let expired = policy
+            .max_age_days
+            .is_some_and(|days| age > ChronoDuration::days(days));
+        let thinned = if policy
+            .daily_after_days
+            .is_some_and(|days| age > ChronoDuration::days(days))
+        {
+            !days_kept.insert(seconds.div_euclid(86_400))
+        } else if policy
+            .hourly_after_hours
+            .is_some_and(|hours| age > ChronoDuration::hours(hours))
+        {
+            !hours_kept.insert(seconds.div_euclid(3_600))
+        } else {
+            false
+        };
+        if beyond_count || expired || thinned {
+            outdated.push(metadata.id.clone());
+        }
+    }
+    outdated
+}
+
+fn apply(session_id: &str, policy: &RetentionPolicy) -> Result<RetentionReport, String> {
+    let project_dir = project_root_for(session_id)?;
+    let bytes_before = stored_size(&project_dir);
+    let checkpoints = read_checkpoint_list(session_id);
+
+    let mut removed: Vec<String> = Vec::new();
+    for id in outdated(policy, &checkpoints, Utc::now()) {
+        remove_checkpoint(session_id, &id)?;
+        removed.push(id);
+    }
+    collect_blobs(session_id);
+
+    if let Some(max_bytes) = policy.max_total_bytes {
+        let mut candidates: Vec<&CheckpointMetadata> = checkpoints
+            .iter()
+            .filter(|m| !policy.protects(m) && !removed.contains(&m.id))
+            .collect();
+        let mut size = stored_size(&project_dir);
+        while size > max_bytes {
+            let Some(oldest) = candidates.pop() else {
+                break;
+            };
+            remove_checkpoint(session_id, &oldest.id)?;
+            removed.push(oldest.id.clone());
+            collect_blobs(session_id);
+            let new_size = stored_size(&project_dir);
+            // Nothing freed here means the rest lives in git; stop before
+            // removing checkpoints that do not count toward the cap.
+            if new_size >= size {
+                break;
+            }
+            size = new_size;
+        }
+    }
+
+    let bytes_after = stored_size(&project_dir);
+    Ok(RetentionReport {
+        remaining: checkpoints.len() - removed.len(),
+        removed,
+        bytes_before,
+        bytes_after,
+        reclaimed_bytes: bytes_before.saturating_sub(bytes_after),
+    })
+}
+
+/// Applies `policy`, or the configured one, to the session's project.
+#[command]
+pub async fn apply_checkpoint_retention(
+    session_id: String,
+    policy: Option<RetentionPolicy>,
+) -> Result<RetentionReport, String> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => RetentionPolicy::from_settings(&project_root_for(&session_id)?),
+    };
+    if policy.is_unset() {
+        return Err("No checkpoint retention policy configured".to_string());
+    }
+    apply(&session_id, &policy)
+}
+
+fn run_scheduled(app: &AppHandle) {
+    let running = crate::MODEL_HANDLERS.lock().unwrap().sessions();
+    // Checkpoints are stored per project, so one session per project will do.
+    let projects: BTreeMap<String, String> = crate::SESSION_MANAGER
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(session_id, _)| running.contains(*session_id))
+        .map(|(session_id, runtime)| (runtime.project_dir.clone(), session_id.clone()))
+        .collect();
+    for (project_dir, session_id) in projects {
+        if !Path::new(&project_dir).is_dir() {
+            continue;
+        }
+        let policy = RetentionPolicy::from_settings(Path::new(&project_dir));
+        if policy.is_unset() {
+            continue;
+        }
+        match apply(&session_id, &policy) {
+            Ok(report) if report.removed.is_empty() => {}
+            Ok(report) => {
+                eprintln!(
+                    "[RUST] Checkpoint retention removed {} checkpoints in {}, reclaiming {} bytes",
+                    report.removed.len(),
+                    project_dir,
+                    report.reclaimed_bytes
+                );
+                let _ = app.emit(
+                    "checkpoints:retention",
+                    RetentionEvent {
+                        project_dir,
+                        report,
+                    },
+                );
+            }
+            Err(e) => eprintln!(
+                "[RUST] Checkpoint retention failed for {}: {}",
+                project_dir, e
+            ),
+        }
+    }
+}
+
+/// Applies the configured policies in the background for as long as the app
+/// runs.
+pub(crate) fn start_scheduler(app: AppHandle) {
+    std::thread::spawn(move || {
+        std::thread::sleep(FIRST_RUN_DELAY);
+        loop {
+            run_scheduled(&app);
+            std::thread::sleep(RUN_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap()
+    }
+
+    fn auto(id: &str, age: ChronoDuration) -> CheckpointMetadata {
+        CheckpointMetadata {
+            id: id.to_string(),
+            timestamp: now() - age,
+            name: None,
+            checkpoint_type: "auto".to_string(),
+            trigger: None,
+            file_count: 1,
+            git_branch: None,
+            git_commit: None,
+            tags: Vec::new(),
+            parent_id: None,
+            imported: None,
+        }
+    }
+
+    fn minutes(n: i64) -> ChronoDuration {
+        ChronoDuration::minutes(n)
+    }
+
+    #[test]
+    fn protects_manual_named_and_tagged_by_default() {
+        let policy = RetentionPolicy::default();
+        let mut manual = auto("m", minutes(1));
+        manual.checkpoint_type = "manual".to_string();
+        let mut named = auto("n", minutes(1));
+        named.name = Some("before refactor".to_string());
+        let mut tagged = auto("t", minutes(1));
+        tagged.tags.push("keep".to_string());
+        for metadata in [&manual, &named, &tagged] {
+            assert!(policy.protects(metadata), "{}", metadata.id);
+        }
+        assert!(!policy.protects(&auto("a", minutes(1))));
+
+        let unprotected = RetentionPolicy {
+            keep_manual: Some(false),
+            ..Default::default()
+        };
+        assert!(!unprotected.protects(&manual));
+    }
+
+    #[test]
+    fn only_limits_make_a_policy() {
+        assert!(RetentionPolicy::default().is_unset());
+        let keep_manual_only = RetentionPolicy {
+            keep_manual: Some(true),
+            ..Default::default()
+        };
+        assert!(keep_manual_only.is_unset());
+        let limited = RetentionPolicy {
+            hourly_after_hours: Some(1),
+            ..Default::default()
+        };
+        assert!(!limited.is_unset());
+    }
+
+    #[test]
+    fn keep_count_drops_the_oldest_unprotected() {
+        let mut named = auto("b", minutes(2));
+        named.name = Some("named".to_string());
+        let checkpoints = vec![
+            auto("a", minutes(1)),
+            named,
+            auto("c", minutes(3)),
+            auto("d", minutes(4)),
+        ];
+        let policy = RetentionPolicy {
+            keep_count: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(outdated(&policy, &checkpoints, now()), vec!["c", "d"]);
+    }
+
+    #[test]
+    fn max_age_drops_older_checkpoints() {
+        let checkpoints = vec![
+            auto("fresh", ChronoDuration::days(1)),
+            auto("old", ChronoDuration::days(31)),
+        ];
+        let policy = RetentionPolicy {
+            max_age_days: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(outdated(&policy, &checkpoints, now()), vec!["old"]);
+    }
+
+    #[test]
+    fn hourly_thinning_keeps_the_newest_per_hour() {
+        // `now` is on the hour, so these fall into two hour buckets.
+        let checkpoints = vec![
+            auto("recent-1", minutes(10)),
+            auto("recent-2", minutes(20)),
+            auto("hour-3-newest", minutes(130)),
+            auto("hour-3-older", minutes(150)),
+            auto("hour-4-newest", minutes(190)),
+            auto("hour-4-older", minutes(200)),
+        ];
+        let policy = RetentionPolicy {
+            hourly_after_hours: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            outdated(&policy, &checkpoints, now()),
+            vec!["hour-3-older", "hour-4-older"]
+        );
+    }
+
+    #[test]
+    fn daily_thinning_takes_over_from_hourly() {
+        let hours = ChronoDuration::hours;
+        let checkpoints = vec![
+            auto("today-1", hours(2)),
+            auto("today-2", hours(3)),
+            auto("day-8-newest", hours(8 * 24 + 1)),
+            auto("day-8-older", hours(8 * 24 + 2)),
+            auto("day-8-oldest", hours(8 * 24 + 3)),
+        ];
+        let policy = RetentionPolicy {
+            hourly_after_hours: Some(1),
+            daily_after_days: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(
+            outdated(&policy, &checkpoints, now()),
+            vec!["day-8-older", "day-8-oldest"]
+        );
+    }
+
+    #[test]
+    fn thinning_skips_protected_checkpoints() {
+        let mut tagged = auto("tagged", minutes(125));
+        tagged.tags.push("release".to_string());
+        let checkpoints = vec![tagged, auto("same-hour", minutes(130))];
+        let policy = RetentionPolicy {
+            hourly_after_hours: Some(1),
+            ..Default::default()
+        };
+        // The protected checkpoint does not use up its hour's slot.
+        assert!(outdated(&policy, &checkpoints, now()).is_empty());
+    }
+}
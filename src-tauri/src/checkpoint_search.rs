@@ -0,0 +1,317 @@
+//! Searching a project's checkpoints by file path and contents.
+//!
+//! Paths are matched against the checkpoint index, so only checkpoints holding
+//! a matching file are opened. Contents are searched on both sides of each
+//! file, the original side only where it differs from the current one, and
+//! each distinct content only once per search.
+
+use chrono::{DateTime, Utc};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::command;
+
+use crate::checkpoint::{load_snapshots, FileState};
+use crate::checkpoint_index;
+use crate::checkpoint_store;
+
+const DEFAULT_LIMIT: usize = 200;
+const MAX_LINES_PER_FILE: usize = 20;
+const MAX_SNIPPET_CHARS: usize = 200;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CheckpointSearch {
+    /// Glob on file paths as stored in checkpoints, e.g. `src/**/*.rs`; `*`
+    /// does not cross `/`.
+    pub path: Option<String>,
+    /// Text to look for in file contents; a regular expression with `regex`.
+    pub content: Option<String>,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub ignore_case: bool,
+    /// Stop after this many matching files; defaults to 200.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineMatch {
+    /// 1-based.
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileMatch {
+    pub path: String,
+    /// `original` or `current`; absent for a path-only search.
+    pub side: Option<String>,
+    /// Matching lines, at most 20 per file; empty for a path-only search.
+    pub lines: Vec<LineMatch>,
+}
+
+/// A checkpoint with matching files, newest first.
+#[derive(Debug, Serialize)]
+pub struct CheckpointMatch {
+    pub checkpoint_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub name: Option<String>,
+    pub trigger: Option<String>,
+    pub files: Vec<FileMatch>,
+}
+
+fn path_matcher(search: &CheckpointSearch) -> Result<Option<GlobMatcher>, String> {
+    let Some(path) = search
+        .path
+        .as_deref()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+    else {
+        return Ok(None);
+    };
+    GlobBuilder::new(path)
+        .literal_separator(true)
+        .build()
+        .map(|g| Some(g.compile_matcher()))
+        .map_err(|e| format!("Invalid glob '{}': {}", path, e))
+}
+
+fn content_matcher(search: &CheckpointSearch) -> Result<Option<Regex>, String> {
+    let Some(content) = search.content.as_deref().filter(|c| !c.is_empty()) else {
+        return Ok(None);
+    };
+    let pattern = if search.regex {
+        content.to_string()
+    } else {
+        regex::escape(content)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(search.ignore_case)
+        .build()
+        .map(Some)
+        .map_err(|e| format!("Invalid regex '{}': {}", content, e))
+}
+
+fn matching_lines(state: &FileState, pattern: &Regex) -> Vec<LineMatch> {
+    if !state.exists {
+        return Vec::new();
+    }
+    // Binary contents have no lines to show.
+    let Ok(text) = std::str::from_utf8(&state.bytes) else {
+        return Vec::new();
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| pattern.is_match(line))
+        .take(MAX_LINES_PER_FILE)
+        .map(|(index, line)| LineMatch {
+            line: index + 1,
+            text: line.trim_end().chars().take(MAX_SNIPPET_CHARS).collect(),
+        })
+        .collect()
+}
+
+/// Searches every checkpoint of the session's project. At least one of
+/// `path` and `content` must be given.
+#[command]
+pub async fn search_checkpoints(
+    session_id: String,
+    search: CheckpointSearch,
+) -> Result<Vec<CheckpointMatch>, String> {
+    let glob = path_matcher(&search)?;
+    let pattern = content_matcher(&search)?;
+    if glob.is_none() && pattern.is_none() {
+        return Err("Search needs a path glob or content to look for".to_string());
+    }
+    let path_matches = |path: &str| glob.as_ref().map_or(true, |g| g.is_match(path));
+
+    // Matching lines by content hash; most files are unchanged between
+    // checkpoints.
+    let mut searched: HashMap<String, Vec<LineMatch>> = HashMap::new();
+    let mut remaining = search.limit.unwrap_or(DEFAULT_LIMIT);
+    let mut results = Vec::new();
+    for entry in checkpoint_index::entries(&session_id)? {
+        if remaining == 0 {
+            break;
+        }
+        if !entry.files.iter().any(|path| path_matches(path)) {
+            continue;
+        }
+        let mut files = Vec::new();
+        match &pattern {
+            None => {
+                for path in entry.files.iter().filter(|path| path_matches(path)) {
+                    files.push(FileMatch {
+                        path: path.clone(),
+                        side: None,
+                        lines: Vec::new(),
+                    });
+                }
+            }
+            Some(pattern) => {
+                let snapshots = match load_snapshots(&session_id, &entry.metadata.id) {
+                    Ok(snapshots) => snapshots,
+                    Err(e) => {
+                        eprintln!(
+                            "[RUST] Search skipped checkpoint {}: {}",
+                            entry.metadata.id, e
+                        );
+                        continue;
+                    }
+                };
+                for snapshot in snapshots.iter().filter(|s| path_matches(&s.path)) {
+                    let current = snapshot.current_state();
+                    let original = snapshot.original_state();
+                    let mut sides = vec![("current", &current)];
+                    if original.exists != current.exists || original.bytes != current.bytes {
+                        sides.push(("original", &original));
+                    }
+                    for (side, state) in sides {
+                        let lines = searched
+                            .entry(checkpoint_store::hash(&state.bytes))
+                            .or_insert_with(|| matching_lines(state, pattern))
+                            .clone();
+                        if !lines.is_empty() {
+                            files.push(FileMatch {
+                                path: snapshot.path.clone(),
+                                side: Some(side.to_string()),
+                                lines,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        if files.is_empty() {
+            continue;
+        }
+        files.truncate(remaining);
+        remaining -= files.len();
+        results.push(CheckpointMatch {
+            checkpoint_id: entry.metadata.id,
+            timestamp: entry.metadata.timestamp,
+            name: entry.metadata.name,
+            trigger: entry.metadata.trigger,
+            files,
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(path: Option<&str>, content: Option<&str>, regex: bool) -> CheckpointSearch {
+        CheckpointSearch {
+            path: path.map(str::to_string),
+            content: content.map(str::to_string),
+            regex,
+            ..Default::default()
+        }
+    }
+
+    fn text(contents: &str) -> FileState {
+        FileState {
+            exists: true,
+            bytes: contents.as_bytes().to_vec(),
+            mode: None,
+        }
+    }
+
+    #[test]
+    fn star_stays_within_a_directory() {
+        let glob = path_matcher(&search(Some("src/*.rs"), None, false))
+            .unwrap()
+            .unwrap();
+        assert!(glob.is_match("src/lib.rs"));
+        assert!(!glob.is_match("src/nested/lib.rs"));
+
+        let glob = path_matcher(&search(Some("src/**/*.rs"), None, false))
+            .unwrap()
+            .unwrap();
+        assert!(glob.is_match("src/lib.rs"));
+        assert!(glob.is_match("src/nested/lib.rs"));
+        assert!(!glob.is_match("tests/lib.rs"));
+    }
+
+    #[test]
+    fn blank_or_invalid_globs() {
+        assert!(path_matcher(&search(Some("  "), None, false))
+            .unwrap()
+            .is_none());
+        assert!(path_matcher(&search(Some("src/[a"), None, false)).is_err());
+    }
+
+    #[test]
+    fn plain_text_is_not_a_regex() {
+        let pattern = content_matcher(&search(None, Some("a.b("), false))
+            .unwrap()
+            .unwrap();
+        assert!(pattern.is_match("call a.b(1)"));
+        assert!(!pattern.is_match("axb("));
+    }
+
+    #[test]
+    fn regex_and_case() {
+        let pattern = content_matcher(&search(None, Some(r"fn \w+\("), true))
+            .unwrap()
+            .unwrap();
+        assert!(pattern.is_match("pub fn main() {"));
+
+        let mut ignoring = search(None, Some("TODO"), false);
+        ignoring.ignore_case = true;
+        let pattern = content_matcher(&ignoring).unwrap().unwrap();
+        assert!(pattern.is_match("// todo: later"));
+
+        assert!(content_matcher(&search(None, Some("(unclosed"), true)).is_err());
+        assert!(content_matcher(&search(None, Some(""), false))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn matching_lines_are_numbered_from_one() {
+        let pattern = content_matcher(&search(None, Some("needle"), false))
+            .unwrap()
+            .unwrap();
+        let lines = matching_lines(&text("hay\nneedle one\nhay\nneedle two  \n"), &pattern);
+        let found: Vec<(usize, &str)> = lines.iter().map(|l| (l.line, l.text.as_str())).collect();
+        assert_eq!(found, vec![(2, "needle one"), (4, "needle two")]);
+    }
+
+    #[test]
+    fn matching_lines_skips_missing_and_binary_files() {
+        let pattern = content_matcher(&search(None, Some("x"), false))
+            .unwrap()
+            .unwrap();
+        assert!(matching_lines(&FileState::default(), &pattern).is_empty());
+        let binary = FileState {
+            exists: true,
+            bytes: vec![b'x', 0xff, 0xfe],
+            mode: None,
+        };
+        assert!(matching_lines(&binary, &pattern).is_empty());
+    }
+
+    #[test]
+    fn matching_lines_are_capped() {
+        let pattern = content_matcher(&search(None, Some("x"), false))
+            .unwrap()
+            .unwrap();
+        let many = "x\n".repeat(MAX_LINES_PER_FILE + 5);
+        assert_eq!(
+            matching_lines(&text(&many), &pattern).len(),
+            MAX_LINES_PER_FILE
+        );
+        let long = "x".repeat(MAX_SNIPPET_CHARS * 2);
+        assert_eq!(
+            matching_lines(&text(&long), &pattern)[0]
+                .text
+                .chars()
+                .count(),
+            MAX_SNIPPET_CHARS
+        );
+    }
+}
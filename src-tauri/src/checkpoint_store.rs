@@ -0,0 +1,317 @@
+//! Content-addressed storage for checkpoint file contents.
+//!
+//! Contents live once in `<checkpoints>/blobs/<xx>/<sha256>` no matter how many
+//! checkpoints reference them. Each checkpoint directory holds a
+//! `manifest.json` that lists its files by blob hash.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+const BLOBS_DIR: &str = "blobs";
+// Blobs younger than this are never collected, so a checkpoint that is still
+// being written cannot lose blobs its manifest does not list yet.
+const GC_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ManifestEntry {
+    pub path: String,
+    /// Blob hashes; `None` when the file did not exist on that side.
+    pub original: Option<String>,
+    pub current: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub version: u32,
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub(crate) fn new(files: Vec<ManifestEntry>) -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            files,
+        }
+    }
+
+    fn blobs(&self) -> impl Iterator<Item = &str> {
+        self.files
+            .iter()
+            .flat_map(|f| [f.original.as_deref(), f.current.as_deref()])
+            .flatten()
+    }
+}
+
+pub(crate) fn hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn blob_path(root: &Path, hash: &str) -> Result<PathBuf, String> {
+    if hash.len() < 3 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid blob hash: {}", hash));
+    }
+    Ok(root.join(BLOBS_DIR).join(&hash[..2]).join(hash))
+}
+
+/// Stores `bytes` unless an identical blob exists and returns its hash.
+pub(crate) fn write_blob(root: &Path, bytes: &[u8]) -> Result<String, String> {
+    let hash = hash(bytes);
+    let path = blob_path(root, &hash)?;
+    if path.exists() {
+        // Refresh the mtime so a concurrent collection treats the blob as new.
+        let _ = fs::File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()));
+        return Ok(hash);
+    }
+    let parent = path.parent().expect("blob path has a parent");
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create blob directory: {}", e))?;
+    // Write under a temporary name so a crash never leaves a truncated blob.
+    let tmp = parent.join(format!("{}.{}.tmp", hash, uuid::Uuid::new_v4().simple()));
+    fs::write(&tmp, bytes).map_err(|e| format!("Failed to write blob: {}", e))?;
+    fs::rename(&tmp, &path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        format!("Failed to store blob: {}", e)
+    })?;
+    Ok(hash)
+}
+
+/// Stores the blob read from `reader` without holding it in memory. Fails,
+/// storing nothing, unless the contents hash to `expected`.
+pub(crate) fn write_blob_from(
+    root: &Path,
+    expected: &str,
+    reader: &mut impl Read,
+) -> Result<(), String> {
+    let path = blob_path(root, expected)?;
+    let parent = path.parent().expect("blob path has a parent");
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create blob directory: {}", e))?;
+    let tmp = parent.join(format!(
+        "{}.{}.tmp",
+        expected,
+        uuid::Uuid::new_v4().simple()
+    ));
+    let copied = (|| -> std::io::Result<String> {
+        let mut file = fs::File::create(&tmp)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            file.write_all(&buffer[..read])?;
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    })();
+    let stored = match copied {
+        Ok(actual) if actual == expected => {
+            fs::rename(&tmp, &path).map_err(|e| format!("Failed to store blob: {}", e))
+        }
+        Ok(_) => Err(format!("Blob {} does not match its hash", expected)),
+        Err(e) => Err(format!("Failed to write blob: {}", e)),
+    };
+    if stored.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    stored
+}
+
+/// Moves a blob from the store at `from` into the one at `to`, removing the
+/// source only once the copy is stored and matches its hash.
+pub(crate) fn move_blob(from: &Path, to: &Path, hash: &str) -> Result<(), String> {
+    let source = blob_path(from, hash)?;
+    // Blob names are their hashes, so one already present is the same.
+    if !blob_path(to, hash)?.exists() {
+        let mut file =
+            fs::File::open(&source).map_err(|e| format!("Failed to read blob {}: {}", hash, e))?;
+        write_blob_from(to, hash, &mut file)?;
+    }
+    fs::remove_file(&source).map_err(|e| format!("Failed to remove blob {}: {}", hash, e))
+}
+
+pub(crate) fn read_blob(root: &Path, hash: &str) -> Result<Vec<u8>, String> {
+    fs::read(blob_path(root, hash)?).map_err(|e| format!("Failed to read blob {}: {}", hash, e))
+}
+
+pub(crate) fn write_manifest(checkpoint_dir: &Path, manifest: &Manifest) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(manifest)
+        .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    fs::write(checkpoint_dir.join(MANIFEST_FILE), json)
+        .map_err(|e| format!("Failed to write manifest: {}", e))
+}
+
+/// Reads a checkpoint's manifest; `None` for checkpoints saved before the blob
+/// store existed.
+pub(crate) fn read_manifest(checkpoint_dir: &Path) -> Result<Option<Manifest>, String> {
+    let path = checkpoint_dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = fs::read_to_string(&path).map_err(|e| format!("Failed to read manifest: {}", e))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse manifest: {}", e))
+}
+
+/// Deletes blobs no manifest under `root` references. Returns how many were
+/// removed.
+pub(crate) fn collect_garbage(root: &Path) -> Result<usize, String> {
+    let blobs_dir = root.join(BLOBS_DIR);
+    if !blobs_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut referenced: HashSet<String> = HashSet::new();
+    let entries =
+        fs::read_dir(root).map_err(|e| format!("Failed to read checkpoints directory: {}", e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() || path == blobs_dir {
+            continue;
+        }
+        // An unreadable manifest keeps everything: better to leak than to lose.
+        match read_manifest(&path) {
+            Ok(Some(manifest)) => referenced.extend(manifest.blobs().map(str::to_string)),
+            Ok(None) => {}
+            Err(e) => return Err(format!("Skipping blob collection: {}", e)),
+        }
+    }
+
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for shard in fs::read_dir(&blobs_dir)
+        .map_err(|e| format!("Failed to read blob directory: {}", e))?
+        .flatten()
+    {
+        for blob in fs::read_dir(shard.path()).into_iter().flatten().flatten() {
+            let name = blob.file_name().to_string_lossy().to_string();
+            if referenced.contains(&name) {
+                continue;
+            }
+            let recent = blob
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .map_or(true, |age| age < GC_GRACE);
+            if recent {
+                continue;
+            }
+            if fs::remove_file(blob.path()).is_ok() {
+                removed += 1;
+            }
+        }
+        let _ = fs::remove_dir(shard.path());
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn age(root: &Path, hash: &str, by: Duration) {
+        let file = fs::File::options()
+            .append(true)
+            .open(blob_path(root, hash).unwrap())
+            .unwrap();
+        file.set_modified(SystemTime::now() - by).unwrap();
+    }
+
+    fn checkpoint_with(root: &Path, id: &str, hashes: &[&str]) {
+        let dir = root.join(id);
+        fs::create_dir_all(&dir).unwrap();
+        let files = hashes
+            .iter()
+            .map(|hash| ManifestEntry {
+                path: format!("{}.txt", hash),
+                original: None,
+                current: Some(hash.to_string()),
+                original_mode: None,
+                current_mode: None,
+            })
+            .collect();
+        write_manifest(&dir, &Manifest::new(files)).unwrap();
+    }
+
+    #[test]
+    fn blobs_are_stored_once_by_hash() {
+        let root = tempfile::tempdir().unwrap();
+        let first = write_blob(root.path(), b"contents").unwrap();
+        let second = write_blob(root.path(), b"contents").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, hash(b"contents"));
+        assert_eq!(read_blob(root.path(), &first).unwrap(), b"contents");
+        let shard = root.path().join(BLOBS_DIR).join(&first[..2]);
+        assert_eq!(fs::read_dir(shard).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn refuses_malformed_hashes() {
+        let root = tempfile::tempdir().unwrap();
+        for hash in ["", "ab", "../../etc/passwd", "zz00"] {
+            assert!(read_blob(root.path(), hash).is_err(), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn missing_manifest_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_manifest(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn collects_only_old_unreferenced_blobs() {
+        let root = tempfile::tempdir().unwrap();
+        let kept = write_blob(root.path(), b"kept").unwrap();
+        let orphan = write_blob(root.path(), b"orphan").unwrap();
+        let fresh = write_blob(root.path(), b"fresh").unwrap();
+        checkpoint_with(root.path(), "cp1", &[&kept]);
+        age(root.path(), &kept, GC_GRACE * 2);
+        age(root.path(), &orphan, GC_GRACE * 2);
+
+        assert_eq!(collect_garbage(root.path()).unwrap(), 1);
+        assert!(read_blob(root.path(), &kept).is_ok());
+        assert!(read_blob(root.path(), &orphan).is_err());
+        // Within the grace period, as a checkpoint being written would be.
+        assert!(read_blob(root.path(), &fresh).is_ok());
+    }
+
+    #[test]
+    fn rewriting_a_blob_restarts_its_grace_period() {
+        let root = tempfile::tempdir().unwrap();
+        let hash = write_blob(root.path(), b"reused").unwrap();
+        age(root.path(), &hash, GC_GRACE * 2);
+        write_blob(root.path(), b"reused").unwrap();
+        assert_eq!(collect_garbage(root.path()).unwrap(), 0);
+        assert!(read_blob(root.path(), &hash).is_ok());
+    }
+
+    #[test]
+    fn unreadable_manifest_keeps_everything() {
+        let root = tempfile::tempdir().unwrap();
+        let orphan = write_blob(root.path(), b"orphan").unwrap();
+        age(root.path(), &orphan, GC_GRACE * 2);
+        let broken = root.path().join("broken");
+        fs::create_dir(&broken).unwrap();
+        fs::write(broken.join(MANIFEST_FILE), "{").unwrap();
+
+        assert!(collect_garbage(root.path()).is_err());
+        assert!(read_blob(root.path(), &orphan).is_ok());
+    }
+}
@@ -1,13 +1,10 @@
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::fs;
-use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use std::process::Command;
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 use std::sync::Mutex;
-use std::thread;
-use tauri::{Emitter, Manager};
 
 mod terminal;
 use terminal::{LspManager, TerminalManager};
@@ -15,145 +12,48 @@ use terminal::{LspManager, TerminalManager};
 mod checkpoint;
 use checkpoint::*;
 
-mod browser;
-use browser::*;
+mod checkpoint_store;
 
+mod checkpoint_archive;
 
-trait ModelHandler: Send {
-    fn start(&mut self, app: tauri::AppHandle, project_dir: &str) -> Result<(), String>;
-    fn send(&mut self, input: &str) -> Result<(), String>;
-    fn stop(&mut self) -> Result<(), String>;
-}
+mod checkpoint_diff;
 
-struct NodeModelHandler {
-    model: &'static str,
-    script: &'static str,
-    child: Option<Child>,
-}
+mod checkpoint_git;
+mod checkpoint_index;
+mod checkpoint_location;
 
-impl NodeModelHandler {
-    fn new(model: &'static str, script: &'static str) -> Self {
-        Self {
-            model,
-            script,
-            child: None,
-        }
-    }
-}
+mod checkpoint_retention;
+mod checkpoint_search;
 
-impl ModelHandler for NodeModelHandler {
-    fn start(&mut self, app: tauri::AppHandle, project_dir: &str) -> Result<(), String> {
-        if self.child.is_some() {
-            return Ok(());
-        }
+mod checkpoint_merge;
+mod checkpoint_path;
 
-        let mut handler_path = app.path().resource_dir().map_err(|e| {
-            eprintln!("[RUST] Failed to get resource dir: {}", e);
-            format!("Failed to get resource dir: {}", e)
-        })?;
-        handler_path.push(self.script);
-        if !handler_path.exists() {
-            handler_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(self.script);
-        }
-        if !handler_path.exists() {
-            eprintln!("[RUST] Handler file does not exist at {:?}", handler_path);
-            return Err(format!("Handler file not found: {:?}", handler_path));
-        }
+mod browser;
+use browser::*;
 
-        let mut cmd = Command::new("node");
-        cmd.arg(&handler_path)
-            .current_dir(project_dir)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-        eprintln!(
-            "[RUST] Spawning persistent handler: node {:?} in dir {:?}",
-            handler_path, project_dir
-        );
-        let mut child = cmd.spawn().map_err(|e| {
-            eprintln!("[RUST] Failed to spawn handler: {}", e);
-            format!("Failed to spawn handler: {}", e)
-        })?;
-        eprintln!(
-            "[RUST] Persistent handler process spawned successfully for model {}",
-            self.model
-        );
-
-        if let Some(stdout) = child.stdout.take() {
-            let app_handle = app.clone();
-            let model_clone = self.model.to_string();
-            thread::spawn(move || {
-                let reader = BufReader::new(stdout);
-                for line in reader.lines() {
-                    if let Ok(l) = line {
-                        if !l.trim().is_empty() {
-                            let event_name = format!("{}:stream", model_clone);
-                            let _ = app_handle.emit(&event_name, l);
-                        }
-                    }
-                }
-            });
-        }
+mod model_handler;
+use model_handler::{CommandAck, HandlerConfig, ModelHandlerRegistry, ACK_TIMEOUT};
 
-        if let Some(stderr) = child.stderr.take() {
-            let app_handle = app.clone();
-            let model_clone = self.model.to_string();
-            thread::spawn(move || {
-                let reader = BufReader::new(stderr);
-                for line in reader.lines() {
-                    if let Ok(l) = line {
-                        eprintln!("[model handler {} stderr] {}", model_clone, l);
-                        let event_name = format!("{}:error", model_clone);
-                        let _ = app_handle.emit(&event_name, l);
-                    }
-                }
-            });
-        }
+mod agent_tools;
 
-        self.child = Some(child);
-        Ok(())
-    }
+mod audit;
 
-    fn send(&mut self, input: &str) -> Result<(), String> {
-        if let Some(child) = self.child.as_mut() {
-            if let Some(stdin) = child.stdin.as_mut() {
-                stdin
-                    .write_all(input.as_bytes())
-                    .map_err(|e| e.to_string())?;
-                stdin.write_all(b"\n").map_err(|e| e.to_string())?;
-                stdin.flush().map_err(|e| e.to_string())?;
-                Ok(())
-            } else {
-                Err("Handler stdin unavailable".into())
-            }
-        } else {
-            Err("Handler not started".into())
-        }
-    }
+mod auto_checkpoint;
 
-    fn stop(&mut self) -> Result<(), String> {
-        if let Some(mut child) = self.child.take() {
-            let _ = child.kill();
-        }
-        Ok(())
-    }
-}
+mod openai_handler;
+
+mod permissions;
+
+mod protocol;
+use protocol::{HandlerCommand, RegisterOptions};
+
+mod sessions;
 
 static TERMINAL_MANAGER: Lazy<TerminalManager> = Lazy::new(|| TerminalManager::new());
 static LSP_MANAGER: Lazy<LspManager> = Lazy::new(|| LspManager::new());
-// Registry of model handlers
-static MODEL_HANDLERS: Lazy<Mutex<HashMap<String, Box<dyn ModelHandler + Send + 'static>>>> =
-    Lazy::new(|| {
-        let mut m: HashMap<String, Box<dyn ModelHandler + Send + 'static>> = HashMap::new();
-        m.insert(
-            "codex".into(),
-            Box::new(NodeModelHandler::new(
-                "codex",
-                "model_handlers/codex-handler.js",
-            )),
-        );
-        Mutex::new(m)
-    });
+// Model handler processes, keyed by session id and model
+static MODEL_HANDLERS: Lazy<Mutex<ModelHandlerRegistry>> =
+    Lazy::new(|| Mutex::new(ModelHandlerRegistry::new()));
 
 struct SessionRuntime {
     project_dir: String,
@@ -172,6 +72,11 @@ impl SessionRuntime {
 static SESSION_MANAGER: Lazy<Mutex<HashMap<String, SessionRuntime>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// Drops the in-memory runtime of a session.
+fn forget_session(session_id: &str) {
+    SESSION_MANAGER.lock().unwrap().remove(session_id);
+}
+
 pub(crate) fn get_session_project_dir(session_id: &str) -> Option<String> {
     SESSION_MANAGER
         .lock()
@@ -241,51 +146,53 @@ fn start_codex(
         "[start_codex] session={} incoming={} resolved={}",
         session_id, project_dir, resolved_str
     );
-    let mut sessions = SESSION_MANAGER.lock().unwrap();
-    let entry = sessions
-        .entry(session_id.clone())
-        .or_insert_with(|| SessionRuntime::new(resolved_str.clone()));
-
-    entry.project_dir = resolved_str.clone();
-
     {
-        let mut registry = MODEL_HANDLERS.lock().unwrap();
-        if let Some(handler) = registry.get_mut("codex") {
-            handler.start(app.clone(), &resolved_str)?;
-
-            let mut options = serde_json::Map::new();
-            options.insert(
-                "workingDirectory".to_string(),
-                serde_json::Value::String(resolved_str.clone()),
-            );
-            options.insert(
-                "skipGitRepoCheck".to_string(),
-                serde_json::Value::Bool(true),
-            );
-            // Do not pass model to Codex SDK to avoid invalid model errors
-            if let Some(sandbox_value) = sandbox_mode.clone() {
-                options.insert(
-                    "sandboxMode".to_string(),
-                    serde_json::Value::String(sandbox_value),
-                );
-            }
-
-            let register_payload = serde_json::json!({
-                "type": "register",
-                "sessionId": session_id,
-                "threadId": thread_id,
-                "options": options,
-            });
+        let mut sessions = SESSION_MANAGER.lock().unwrap();
+        let entry = sessions
+            .entry(session_id.clone())
+            .or_insert_with(|| SessionRuntime::new(resolved_str.clone()));
 
-            handler.send(&serde_json::to_string(&register_payload).map_err(|e| e.to_string())?)?;
-        }
+        entry.project_dir = resolved_str.clone();
     }
+    sessions::record_start(
+        &session_id,
+        &resolved_str,
+        thread_id.as_deref(),
+        model.as_deref(),
+        sandbox_mode.as_deref(),
+    );
 
-    Ok(())
+    let mut registry = MODEL_HANDLERS.lock().unwrap();
+    let handler = registry.get_or_create(&session_id, "codex")?;
+    handler.start(app.clone(), &resolved_str)?;
+
+    // Do not pass model to Codex SDK to avoid invalid model errors
+    handler
+        .send(&HandlerCommand::Register {
+            session_id,
+            thread_id,
+            options: RegisterOptions {
+                working_directory: resolved_str,
+                skip_git_repo_check: true,
+                sandbox_mode,
+            },
+        })
+        .map(|_| ())
+}
+
+fn ack_timeout(timeout_ms: Option<u64>) -> std::time::Duration {
+    timeout_ms
+        .map(std::time::Duration::from_millis)
+        .unwrap_or(ACK_TIMEOUT)
 }
 
 #[tauri::command]
-fn send_to_codex(app: tauri::AppHandle, session_id: String, input: String) -> Result<(), String> {
+async fn send_to_codex(
+    app: tauri::AppHandle,
+    session_id: String,
+    input: String,
+    timeout_ms: Option<u64>,
+) -> Result<CommandAck, String> {
     let project_dir = {
         let mut sessions = SESSION_MANAGER.lock().unwrap();
         let entry = sessions
@@ -296,35 +203,31 @@ fn send_to_codex(app: tauri::AppHandle, session_id: String, input: String) -> Re
         }
         entry.project_dir.clone()
     };
+    sessions::touch(&session_id);
 
-    let payload = serde_json::json!({
-        "type": "run",
-        "sessionId": session_id,
-        "payload": input,
-    });
-
-    let mut registry = MODEL_HANDLERS.lock().unwrap();
-    let handler = registry
-        .get_mut("codex")
-        .ok_or_else(|| "Codex handler not registered".to_string())?;
+    let receipt = {
+        let mut registry = MODEL_HANDLERS.lock().unwrap();
+        let handler = registry.get_or_create(&session_id, "codex")?;
 
-    handler.start(app.clone(), &project_dir)?;
-    handler.send(&serde_json::to_string(&payload).map_err(|e| e.to_string())?)
+        handler.start(app.clone(), &project_dir)?;
+        handler.send(&HandlerCommand::Run {
+            session_id,
+            payload: input,
+        })?
+    };
+    receipt.wait(ack_timeout(timeout_ms)).await
 }
 
 #[tauri::command]
 fn interrupt_codex(session_id: String) -> Result<(), String> {
-    let payload = serde_json::json!({
-        "type": "interrupt",
-        "sessionId": session_id,
-    });
-
     let mut registry = MODEL_HANDLERS.lock().unwrap();
     let handler = registry
-        .get_mut("codex")
-        .ok_or_else(|| "Codex handler not registered".to_string())?;
+        .get_mut(&session_id, "codex")
+        .ok_or_else(|| "Codex handler not running for session".to_string())?;
 
-    handler.send(&serde_json::to_string(&payload).map_err(|e| e.to_string())?)
+    handler
+        .send(&HandlerCommand::Interrupt { session_id })
+        .map(|_| ())
 }
 
 #[tauri::command]
@@ -333,18 +236,17 @@ fn resolve_codex_permission(
     request_id: String,
     allow: bool,
     scope: String,
+    pattern: Option<String>,
 ) -> Result<(), String> {
-    let payload = serde_json::json!({
-        "type": "permission",
-        "sessionId": session_id,
-        "requestId": request_id,
-        "allow": allow,
-        "scope": scope,
-    });
-
+    let allow = permissions::resolve(&session_id, &request_id, allow, &scope, pattern)?;
     let mut registry = MODEL_HANDLERS.lock().unwrap();
-    if let Some(handler) = registry.get_mut("codex") {
-        let _ = handler.send(&serde_json::to_string(&payload).map_err(|e| e.to_string())?);
+    if let Some(handler) = registry.get_mut(&session_id, "codex") {
+        let _ = handler.send(&HandlerCommand::Permission {
+            session_id,
+            request_id,
+            allow,
+            scope,
+        });
     }
     Ok(())
 }
@@ -358,12 +260,13 @@ fn restart_codex(app: tauri::AppHandle, session_id: String) -> Result<(), String
 }
 
 #[tauri::command]
-fn send_to_model(
+async fn send_to_model(
     app: tauri::AppHandle,
     session_id: String,
     input: String,
     model: String,
-) -> Result<(), String> {
+    timeout_ms: Option<u64>,
+) -> Result<CommandAck, String> {
     let model = model.to_lowercase();
     eprintln!("[RUST] send_to_model called with model: {}", model);
     eprintln!("[RUST] Input length: {}", input.len());
@@ -380,25 +283,25 @@ fn send_to_model(
         return Err("Project directory not set.".into());
     }
     eprintln!("[RUST] Project directory: {}", project_dir);
+    sessions::touch(&session_id);
 
-    let mut registry = MODEL_HANDLERS.lock().unwrap();
-    let handler = registry.get_mut(&model).ok_or_else(|| {
-        eprintln!("[RUST] Error: Unknown model: {}", model);
-        format!("Unknown model: {}", model)
-    })?;
-    handler.start(app, &project_dir)?;
-    handler.send(&input)?;
-    Ok(())
+    let command = HandlerCommand::from_input(&session_id, &input);
+
+    let receipt = {
+        let mut registry = MODEL_HANDLERS.lock().unwrap();
+        let handler = registry.get_or_create(&session_id, &model).map_err(|e| {
+            eprintln!("[RUST] Error: {}", e);
+            e
+        })?;
+        handler.start(app, &project_dir)?;
+        handler.send(&command)?
+    };
+    receipt.wait(ack_timeout(timeout_ms)).await
 }
 
 #[tauri::command]
 fn stop_codex(session_id: String) -> Result<(), String> {
-    {
-        let mut registry = MODEL_HANDLERS.lock().unwrap();
-        for handler in registry.values_mut() {
-            let _ = handler.stop();
-        }
-    }
+    MODEL_HANDLERS.lock().unwrap().stop_session(&session_id);
 
     let mut sessions = SESSION_MANAGER.lock().unwrap();
     if let Some(runtime) = sessions.get_mut(&session_id) {
@@ -412,15 +315,27 @@ fn stop_codex(session_id: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn stop_model(model: String) -> Result<(), String> {
+fn stop_model(model: String, session_id: Option<String>) -> Result<(), String> {
     let m = model.to_lowercase();
     let mut registry = MODEL_HANDLERS.lock().unwrap();
-    if let Some(handler) = registry.get_mut(&m) {
-        handler.stop()?;
+    match session_id {
+        Some(session_id) => registry.stop(&session_id, &m),
+        None => registry.stop_model(&m),
     }
     Ok(())
 }
 
+#[tauri::command]
+fn register_model_handler(config: HandlerConfig) -> Result<(), String> {
+    eprintln!("[RUST] Registering model handler: {}", config.name);
+    MODEL_HANDLERS.lock().unwrap().register(config)
+}
+
+#[tauri::command]
+fn list_model_handlers() -> Result<Vec<HandlerConfig>, String> {
+    Ok(MODEL_HANDLERS.lock().unwrap().configs())
+}
+
 #[tauri::command]
 fn get_cwd() -> Result<String, String> {
     std::env::current_dir()
@@ -429,10 +344,10 @@ fn get_cwd() -> Result<String, String> {
 }
 
 #[derive(serde::Serialize)]
-struct CommandResult {
-    output: String,
-    exit_code: i32,
-    cwd: String,
+pub(crate) struct CommandResult {
+    pub output: String,
+    pub exit_code: i32,
+    pub cwd: String,
 }
 
 #[tauri::command]
@@ -521,10 +436,20 @@ async fn run_command(command: String, cwd: Option<String>) -> Result<CommandResu
 
 #[tauri::command]
 async fn execute_command(session_id: String, command: String) -> Result<String, String> {
+    execute_session_command(&session_id, &command)
+        .await
+        .map(|result| result.output)
+}
+
+/// Runs `command` with `sh -c` in the session's project directory.
+pub(crate) async fn execute_session_command(
+    session_id: &str,
+    command: &str,
+) -> Result<CommandResult, String> {
     let project_dir = {
         let sessions = SESSION_MANAGER.lock().unwrap();
         sessions
-            .get(&session_id)
+            .get(session_id)
             .map(|s| s.project_dir.clone())
             .unwrap_or_default()
     };
@@ -535,7 +460,7 @@ async fn execute_command(session_id: String, command: String) -> Result<String,
         project_dir.clone()
     };
 
-    let command_owned = command.clone();
+    let command_owned = command.to_string();
     let working_dir_for_spawn = working_dir.clone();
 
     let output = tauri::async_runtime::spawn_blocking(move || {
@@ -552,11 +477,21 @@ async fn execute_command(session_id: String, command: String) -> Result<String,
     let stdout = String::from_utf8_lossy(&output.stdout).to_string();
     let stderr = String::from_utf8_lossy(&output.stderr).to_string();
 
-    if output.status.success() {
-        Ok(if stdout.is_empty() { stderr } else { stdout })
+    let output_text = if output.status.success() {
+        if stdout.is_empty() {
+            stderr
+        } else {
+            stdout
+        }
     } else {
-        Ok(format!("{}{}", stdout, stderr))
-    }
+        format!("{}{}", stdout, stderr)
+    };
+
+    Ok(CommandResult {
+        output: output_text,
+        exit_code: output.status.code().unwrap_or(-1),
+        cwd: working_dir,
+    })
 }
 
 #[tauri::command]
@@ -578,6 +513,7 @@ fn create_terminal(
 
         if let Some(runtime) = runtime {
             runtime.terminal_id = Some(id.clone());
+            sessions::record_terminal(&session_id, Some(&id));
 
             match provided_dir {
                 Some(dir) => Some(dir),
@@ -615,12 +551,24 @@ fn close_terminal(session_id: String, id: String) -> Result<(), String> {
     if let Some(runtime) = sessions.get_mut(&session_id) {
         if runtime.terminal_id.as_ref() == Some(&id) {
             runtime.terminal_id = None;
+            sessions::record_terminal(&session_id, None);
         }
     }
 
     TERMINAL_MANAGER.close_terminal(&id)
 }
 
+/// Writes to the terminal attached to a session, if it has one.
+pub(crate) fn write_to_session_terminal(session_id: &str, data: &str) -> Result<(), String> {
+    let terminal_id = SESSION_MANAGER
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .and_then(|s| s.terminal_id.clone())
+        .ok_or_else(|| "Session has no open terminal".to_string())?;
+    TERMINAL_MANAGER.write_to_terminal(&terminal_id, data)
+}
+
 #[tauri::command]
 fn get_session_terminal_id(session_id: String) -> Result<Option<String>, String> {
     let sessions = SESSION_MANAGER.lock().unwrap();
@@ -736,10 +684,14 @@ fn clear_terminal_session() -> Result<(), String> {
     Ok(())
 }
 
+pub(crate) fn settings_path() -> Result<PathBuf, String> {
+    let home = std::env::var("HOME").map_err(|e| format!("Failed to get HOME: {}", e))?;
+    Ok(std::path::Path::new(&home).join(".config/claude/settings.json"))
+}
+
 #[tauri::command]
 fn load_settings() -> Result<serde_json::Value, String> {
-    let home = std::env::var("HOME").map_err(|e| format!("Failed to get HOME: {}", e))?;
-    let settings_path = std::path::Path::new(&home).join(".config/claude/settings.json");
+    let settings_path = settings_path()?;
 
     if !settings_path.exists() {
         // Return empty object if settings file doesn't exist
@@ -754,14 +706,14 @@ fn load_settings() -> Result<serde_json::Value, String> {
 
 #[tauri::command]
 fn save_settings(settings: serde_json::Value) -> Result<(), String> {
-    let home = std::env::var("HOME").map_err(|e| format!("Failed to get HOME: {}", e))?;
-    let config_dir = std::path::Path::new(&home).join(".config/claude");
+    let settings_path = settings_path()?;
 
     // Create directory if it doesn't exist
-    std::fs::create_dir_all(&config_dir)
-        .map_err(|e| format!("Failed to create config directory: {}", e))?;
+    if let Some(config_dir) = settings_path.parent() {
+        std::fs::create_dir_all(config_dir)
+            .map_err(|e| format!("Failed to create config directory: {}", e))?;
+    }
 
-    let settings_path = config_dir.join("settings.json");
     let contents = serde_json::to_string_pretty(&settings)
         .map_err(|e| format!("Failed to serialize settings: {}", e))?;
 
@@ -815,6 +767,21 @@ pub fn run() {
             }
             let _ = app.handle().plugin(tauri_plugin_dialog::init());
             let _ = app.handle().plugin(tauri_plugin_fs::init());
+            if let Ok(settings) = load_settings() {
+                MODEL_HANDLERS.lock().unwrap().load_from_settings(&settings);
+            }
+            match sessions::load_all() {
+                Ok(saved) => {
+                    let mut runtimes = SESSION_MANAGER.lock().unwrap();
+                    for session in saved {
+                        runtimes
+                            .entry(session.session_id)
+                            .or_insert_with(|| SessionRuntime::new(session.project_dir));
+                    }
+                }
+                Err(e) => eprintln!("[RUST] Failed to load saved sessions: {}", e),
+            }
+            checkpoint_retention::start_scheduler(app.handle().clone());
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -826,6 +793,15 @@ pub fn run() {
             send_to_model,
             stop_codex,
             stop_model,
+            register_model_handler,
+            list_model_handlers,
+            permissions::list_permission_grants,
+            permissions::revoke_permission_grant,
+            audit::query_audit_log,
+            audit::export_audit_log,
+            sessions::list_sessions,
+            sessions::resume_session,
+            sessions::delete_session,
             get_cwd,
             run_command,
             execute_command,
@@ -842,6 +818,7 @@ pub fn run() {
             save_settings,
             save_checkpoint_files,
             restore_checkpoint,
+            check_checkpoint_conflicts,
             delete_checkpoint,
             list_checkpoint_files,
             get_checkpoint_metadata,
@@ -851,6 +828,21 @@ pub fn run() {
             restore_checkpoint_with_mode,
             clean_old_checkpoints,
             list_checkpoints,
+            create_checkpoint,
+            rename_checkpoint,
+            tag_checkpoint,
+            untag_checkpoint,
+            get_checkpoint_tree,
+            checkpoint_retention::apply_checkpoint_retention,
+            checkpoint_archive::export_checkpoints,
+            checkpoint_archive::import_checkpoints,
+            checkpoint_location::migrate_checkpoints,
+            checkpoint_index::query_checkpoints,
+            checkpoint_search::search_checkpoints,
+            checkpoint_diff::diff_checkpoint,
+            checkpoint_diff::diff_checkpoint_to_working_tree,
+            checkpoint_diff::diff_checkpoints,
+            checkpoint_merge::restore_checkpoint_hunks,
             save_temp_image,
             clone_repo,
             start_browser_session,
@@ -0,0 +1,880 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::oneshot;
+
+use crate::audit;
+use crate::auto_checkpoint;
+use crate::openai_handler::OpenAiModelHandler;
+use crate::permissions;
+use crate::protocol::{
+    HandlerCommand, HandlerEvent, HandlerMessage, HandlerRequest, PROTOCOL_VERSION,
+};
+use crate::sessions;
+
+/// How long a command waits for the handler's acknowledgement by default.
+pub(crate) const ACK_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub(crate) trait ModelHandler: Send {
+    fn start(&mut self, app: AppHandle, project_dir: &str) -> Result<(), String>;
+    fn send(&mut self, command: &HandlerCommand) -> Result<CommandReceipt, String>;
+    fn stop(&mut self) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CommandAck {
+    pub command_id: String,
+    pub queued: bool,
+}
+
+type AckSender = oneshot::Sender<Result<CommandAck, String>>;
+
+/// Returned by [`ModelHandler::send`]; await it to learn whether the handler
+/// accepted the command. Dropping it is fine for fire-and-forget commands.
+pub(crate) struct CommandReceipt {
+    pub command_id: String,
+    outcome: oneshot::Receiver<Result<CommandAck, String>>,
+}
+
+impl CommandReceipt {
+    pub(crate) fn new(command_id: String) -> (Self, AckSender) {
+        let (tx, rx) = oneshot::channel();
+        (
+            Self {
+                command_id,
+                outcome: rx,
+            },
+            tx,
+        )
+    }
+
+    pub(crate) async fn wait(self, timeout: Duration) -> Result<CommandAck, String> {
+        match tokio::time::timeout(timeout, self.outcome).await {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(_)) => Err("Handler exited before acknowledging the command".into()),
+            Err(_) => Err(format!(
+                "Timed out waiting for the handler to acknowledge command {}",
+                self.command_id
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HandlerRuntime {
+    #[default]
+    Node,
+    Python,
+    Native,
+    /// In-process client for an OpenAI-compatible chat completions endpoint.
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+/// A model handler declared in settings under `modelHandlers`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HandlerConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub runtime: HandlerRuntime,
+    /// Script path for node/python, or the executable for native handlers.
+    /// Relative paths resolve against the bundled resources.
+    #[serde(default)]
+    pub script: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Endpoint root for `openai` handlers, e.g. `http://localhost:11434/v1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Model name sent to `openai` handlers; defaults to the composer's model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Variable (in `env` or the process environment) holding the API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+}
+
+impl HandlerConfig {
+    fn builtin_codex() -> Self {
+        Self {
+            name: "codex".to_string(),
+            runtime: HandlerRuntime::Node,
+            script: "model_handlers/codex-handler.js".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            base_url: None,
+            model: None,
+            api_key_env: None,
+        }
+    }
+
+    fn resolve_script(&self, app: &AppHandle) -> Result<PathBuf, String> {
+        let script = Path::new(&self.script);
+        if script.is_absolute() {
+            return if script.exists() {
+                Ok(script.to_path_buf())
+            } else {
+                Err(format!("Handler file not found: {:?}", script))
+            };
+        }
+
+        let mut handler_path = app.path().resource_dir().map_err(|e| {
+            eprintln!("[RUST] Failed to get resource dir: {}", e);
+            format!("Failed to get resource dir: {}", e)
+        })?;
+        handler_path.push(script);
+        if !handler_path.exists() {
+            handler_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(script);
+        }
+        if !handler_path.exists() && self.runtime == HandlerRuntime::Native {
+            if let Ok(found) = which::which(&self.script) {
+                handler_path = found;
+            }
+        }
+        if !handler_path.exists() {
+            eprintln!("[RUST] Handler file does not exist at {:?}", handler_path);
+            return Err(format!("Handler file not found: {:?}", handler_path));
+        }
+        Ok(handler_path)
+    }
+
+    fn command(&self, handler_path: &Path) -> Command {
+        let mut cmd = match self.runtime {
+            HandlerRuntime::Node => {
+                let mut cmd = Command::new("node");
+                cmd.arg(handler_path);
+                cmd
+            }
+            HandlerRuntime::Python => {
+                let mut cmd = Command::new("python3");
+                cmd.arg(handler_path);
+                cmd
+            }
+            HandlerRuntime::Native => Command::new(handler_path),
+            HandlerRuntime::OpenAi => unreachable!("openai handlers run in process"),
+        };
+        cmd.args(&self.args).envs(&self.env);
+        cmd
+    }
+}
+
+const SUPERVISOR_POLL: Duration = Duration::from_millis(250);
+const RESTART_BASE_DELAY: Duration = Duration::from_millis(500);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(30);
+const MAX_RESTART_ATTEMPTS: u32 = 5;
+// A process that stayed up this long is considered healthy again.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Process state shared between a handler and its supervisor thread.
+#[derive(Default)]
+struct HandlerProcess {
+    child: Option<Child>,
+    project_dir: Option<String>,
+    // Bumped on every spawn and stop so stale supervisors retire.
+    generation: u64,
+    // Last `register` line per session, replayed after a restart.
+    registrations: HashMap<String, String>,
+    stderr_tail: VecDeque<String>,
+    // Version announced by the handler's `hello`, if it sent one; commands
+    // are refused while it differs from ours.
+    protocol_version: Option<u32>,
+    // Commands written to stdin that are still waiting for an ack.
+    pending: HashMap<String, AckSender>,
+}
+
+impl HandlerProcess {
+    fn resolve(&mut self, command_id: &str, outcome: Result<CommandAck, String>) {
+        if let Some(tx) = self.pending.remove(command_id) {
+            let _ = tx.send(outcome);
+        }
+    }
+
+    fn fail_pending(&mut self, message: &str) {
+        for (_, tx) in self.pending.drain() {
+            let _ = tx.send(Err(message.to_string()));
+        }
+    }
+
+    /// Registers every session known from earlier processes again.
+    fn replay_registrations(&self, stdin: &mut impl Write) {
+        for line in self.registrations.values() {
+            if let Err(e) = write_line(stdin, line) {
+                eprintln!("[RUST] Failed to replay registration: {}", e);
+            }
+        }
+    }
+}
+
+/// What the supervisor does after a process exits.
+#[derive(Debug, PartialEq, Eq)]
+enum Recovery {
+    Restart { attempt: u32, delay: Duration },
+    GiveUp { attempt: u32 },
+}
+
+/// Counts the exit of a process that ran for `ran_for` as a new attempt, or
+/// as the first one when it had been stable. Restarts back off exponentially
+/// until `MAX_RESTART_ATTEMPTS`.
+fn recovery(previous_attempt: u32, ran_for: Duration) -> Recovery {
+    let attempt = if ran_for >= STABLE_RUN {
+        1
+    } else {
+        previous_attempt.saturating_add(1)
+    };
+    if attempt > MAX_RESTART_ATTEMPTS {
+        return Recovery::GiveUp { attempt };
+    }
+    let delay = RESTART_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(RESTART_MAX_DELAY);
+    Recovery::Restart { attempt, delay }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HandlerExit {
+    session_id: String,
+    model: String,
+    code: Option<i32>,
+    stderr_tail: Vec<String>,
+    restarting: bool,
+    attempt: u32,
+}
+
+pub(crate) struct ProcessModelHandler {
+    config: HandlerConfig,
+    session_id: String,
+    process: Arc<Mutex<HandlerProcess>>,
+}
+
+impl ProcessModelHandler {
+    pub(crate) fn new(config: HandlerConfig, session_id: &str) -> Self {
+        Self {
+            config,
+            session_id: session_id.to_string(),
+            process: Arc::new(Mutex::new(HandlerProcess::default())),
+        }
+    }
+
+    fn spawn(&self, app: &AppHandle, project_dir: &str, attempt: u32) -> Result<(), String> {
+        spawn_process(
+            &self.config,
+            &self.session_id,
+            app,
+            &self.process,
+            project_dir,
+            attempt,
+        )
+    }
+}
+
+impl ModelHandler for ProcessModelHandler {
+    fn start(&mut self, app: AppHandle, project_dir: &str) -> Result<(), String> {
+        {
+            let mut process = self.process.lock().unwrap();
+            let alive = matches!(
+                process.child.as_mut().map(|child| child.try_wait()),
+                Some(Ok(None))
+            );
+            if alive {
+                if process.project_dir.as_deref() == Some(project_dir) {
+                    return Ok(());
+                }
+                // The session moved to another project; respawn in the new
+                // directory. Its registrations name the old one.
+                kill_child(&mut process);
+                process.registrations.clear();
+            }
+        }
+        self.spawn(&app, project_dir, 0)
+    }
+
+    fn send(&mut self, command: &HandlerCommand) -> Result<CommandReceipt, String> {
+        let command_id = uuid::Uuid::new_v4().to_string();
+        let line = HandlerRequest {
+            command_id: &command_id,
+            command,
+        }
+        .to_line()?;
+        let mut process = self.process.lock().unwrap();
+        if let Some(version) = process
+            .protocol_version
+            .filter(|version| *version != PROTOCOL_VERSION)
+        {
+            return Err(format!(
+                "Handler speaks protocol version {}, expected {}",
+                version, PROTOCOL_VERSION
+            ));
+        }
+        let child = process
+            .child
+            .as_mut()
+            .ok_or_else(|| "Handler not started".to_string())?;
+        let stdin = child
+            .stdin
+            .as_mut()
+            .ok_or_else(|| "Handler stdin unavailable".to_string())?;
+        write_line(stdin, &line)?;
+
+        if let HandlerCommand::Register { session_id, .. } = command {
+            process.registrations.insert(session_id.clone(), line);
+        }
+
+        let (receipt, tx) = CommandReceipt::new(command_id.clone());
+        process.pending.retain(|_, tx| !tx.is_closed());
+        process.pending.insert(command_id, tx);
+        Ok(receipt)
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        let mut process = self.process.lock().unwrap();
+        kill_child(&mut process);
+        process.project_dir = None;
+        process.registrations.clear();
+        Ok(())
+    }
+}
+
+fn write_line(stdin: &mut impl Write, line: &str) -> Result<(), String> {
+    stdin
+        .write_all(line.as_bytes())
+        .map_err(|e| e.to_string())?;
+    stdin.write_all(b"\n").map_err(|e| e.to_string())?;
+    stdin.flush().map_err(|e| e.to_string())
+}
+
+/// Writes a command the backend issues on its own; no ack is awaited.
+fn write_command(shared: &Mutex<HandlerProcess>, command: &HandlerCommand) -> Result<(), String> {
+    let mut process = shared.lock().unwrap();
+    let stdin = process
+        .child
+        .as_mut()
+        .and_then(|c| c.stdin.as_mut())
+        .ok_or_else(|| "Handler stdin unavailable".to_string())?;
+    write_line(stdin, &command.to_line()?)
+}
+
+fn kill_child(process: &mut HandlerProcess) {
+    process.generation += 1;
+    process.fail_pending("Handler stopped before acknowledging the command");
+    if let Some(mut child) = process.child.take() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Spawns the handler in `project_dir`, greets it and replays the session
+/// registrations kept from earlier processes.
+fn spawn_process(
+    config: &HandlerConfig,
+    session_id: &str,
+    app: &AppHandle,
+    shared: &Arc<Mutex<HandlerProcess>>,
+    project_dir: &str,
+    attempt: u32,
+) -> Result<(), String> {
+    let handler_path = config.resolve_script(app)?;
+    let hello = HandlerCommand::hello().to_line()?;
+    // Cleared before the new process can answer the hello.
+    shared.lock().unwrap().protocol_version = None;
+    let mut cmd = config.command(&handler_path);
+    cmd.current_dir(project_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    eprintln!(
+        "[RUST] Spawning handler for session {}: {:?} ({:?}) in dir {:?}",
+        session_id, config.runtime, handler_path, project_dir
+    );
+    let mut child = cmd.spawn().map_err(|e| {
+        eprintln!("[RUST] Failed to spawn handler: {}", e);
+        format!("Failed to spawn handler: {}", e)
+    })?;
+    eprintln!(
+        "[RUST] Handler process spawned successfully for model {} (session {})",
+        config.name, session_id
+    );
+
+    if let Some(stdout) = child.stdout.take() {
+        let app_handle = app.clone();
+        let model = config.name.clone();
+        let event_name = format!("{}:stream", model);
+        let session_id = session_id.to_string();
+        let shared = Arc::clone(shared);
+        thread::spawn(move || {
+            let reader = BufReader::new(stdout);
+            for line in reader.lines().map_while(Result::ok) {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let mut message = HandlerMessage::parse(&line, &session_id);
+                let mut denial = None;
+                match &message.event {
+                    HandlerEvent::CommandAck { command_id, queued } => {
+                        let ack = CommandAck {
+                            command_id: command_id.clone(),
+                            queued: *queued,
+                        };
+                        shared.lock().unwrap().resolve(command_id, Ok(ack));
+                        continue;
+                    }
+                    HandlerEvent::CommandError {
+                        command_id,
+                        message,
+                    } => {
+                        shared
+                            .lock()
+                            .unwrap()
+                            .resolve(command_id, Err(message.clone()));
+                        continue;
+                    }
+                    HandlerEvent::ThreadUpdate { thread_id } => {
+                        let thread_session = message.session_id.as_deref().unwrap_or(&session_id);
+                        sessions::record_thread(thread_session, thread_id);
+                    }
+                    HandlerEvent::PermissionRequest {
+                        id, tools, details, ..
+                    } => {
+                        let request_session = message
+                            .session_id
+                            .clone()
+                            .unwrap_or_else(|| session_id.clone());
+                        if let Some(allow) =
+                            permissions::screen_request(&request_session, id, tools, details)
+                        {
+                            eprintln!(
+                                "[RUST] Permission request {} answered by policy: allow={}",
+                                id, allow
+                            );
+                            let answer = HandlerCommand::Permission {
+                                session_id: request_session,
+                                request_id: id.clone(),
+                                allow,
+                                scope: "once".to_string(),
+                            };
+                            if let Err(e) = write_command(&shared, &answer) {
+                                eprintln!("[RUST] Failed to answer permission request: {}", e);
+                            }
+                            continue;
+                        }
+                    }
+                    HandlerEvent::ToolStart { id, tool, args } => {
+                        // Handlers without an approval hook are stopped here.
+                        let action_session = message
+                            .session_id
+                            .clone()
+                            .unwrap_or_else(|| session_id.clone());
+                        if let Some(subject) =
+                            permissions::screen_action(&action_session, id, tool, args)
+                        {
+                            eprintln!(
+                                "[RUST] Interrupting session {}: {} denied by policy",
+                                action_session, subject
+                            );
+                            let interrupt = HandlerCommand::Interrupt {
+                                session_id: action_session.clone(),
+                            };
+                            if let Err(e) = write_command(&shared, &interrupt) {
+                                eprintln!("[RUST] Failed to interrupt denied action: {}", e);
+                            }
+                            denial = Some(HandlerMessage {
+                                session_id: Some(action_session),
+                                ts: message.ts,
+                                event: HandlerEvent::ToolOutput {
+                                    id: id.clone(),
+                                    chunk: format!("Denied by permission policy: {}", subject),
+                                    stream: None,
+                                    done: true,
+                                    exit_code: None,
+                                },
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+                if let HandlerEvent::Hello {
+                    protocol_version, ..
+                } = message.event
+                {
+                    shared.lock().unwrap().protocol_version = Some(protocol_version);
+                    if protocol_version == PROTOCOL_VERSION {
+                        continue;
+                    }
+                    message = HandlerMessage::protocol_error(
+                        &session_id,
+                        format!(
+                            "Handler speaks protocol version {}, expected {}",
+                            protocol_version, PROTOCOL_VERSION
+                        ),
+                        &line,
+                    );
+                }
+                for message in std::iter::once(message).chain(denial) {
+                    // Snapshot before the audit entry so it can link the checkpoint.
+                    auto_checkpoint::observe(&message);
+                    audit::observe(&model, &message);
+                    let _ = app_handle.emit(&event_name, message);
+                }
+            }
+        });
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        let app_handle = app.clone();
+        let model_clone = config.name.clone();
+        let session_id = session_id.to_string();
+        let shared = Arc::clone(shared);
+        thread::spawn(move || {
+            let reader = BufReader::new(stderr);
+            let event_name = format!("{}:error", model_clone);
+            for line in reader.lines().map_while(Result::ok) {
+                eprintln!(
+                    "[model handler {} ({}) stderr] {}",
+                    model_clone, session_id, line
+                );
+                {
+                    let mut process = shared.lock().unwrap();
+                    if process.stderr_tail.len() == STDERR_TAIL_LINES {
+                        process.stderr_tail.pop_front();
+                    }
+                    process.stderr_tail.push_back(line.clone());
+                }
+                let payload = serde_json::json!({
+                    "sessionId": session_id,
+                    "message": line,
+                });
+                let _ = app_handle.emit(&event_name, payload.to_string());
+            }
+        });
+    }
+
+    if let Some(stdin) = child.stdin.as_mut() {
+        if let Err(e) = write_line(stdin, &hello) {
+            // The reader threads end with the process.
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    }
+
+    let generation = {
+        let mut process = shared.lock().unwrap();
+        kill_child(&mut process);
+        process.project_dir = Some(project_dir.to_string());
+        process.stderr_tail.clear();
+        if let Some(mut stdin) = child.stdin.take() {
+            process.replay_registrations(&mut stdin);
+            child.stdin = Some(stdin);
+        }
+        process.child = Some(child);
+        process.generation
+    };
+
+    let config = config.clone();
+    let session_id = session_id.to_string();
+    let app = app.clone();
+    let shared = Arc::clone(shared);
+    thread::spawn(move || supervise(config, session_id, app, shared, generation, attempt));
+    Ok(())
+}
+
+/// Watches one handler process. When it exits on its own, reports the exit and
+/// respawns it with exponential backoff, replaying every session registration.
+fn supervise(
+    config: HandlerConfig,
+    session_id: String,
+    app: AppHandle,
+    shared: Arc<Mutex<HandlerProcess>>,
+    generation: u64,
+    attempt: u32,
+) {
+    let started = Instant::now();
+    let code = loop {
+        thread::sleep(SUPERVISOR_POLL);
+        let mut process = shared.lock().unwrap();
+        if process.generation != generation {
+            return;
+        }
+        let status = match process.child.as_mut().map(|child| child.try_wait()) {
+            Some(Ok(Some(status))) => status,
+            Some(Ok(None)) => continue,
+            Some(Err(e)) => {
+                eprintln!("[RUST] Failed to poll handler {}: {}", config.name, e);
+                continue;
+            }
+            None => return,
+        };
+        process.child = None;
+        process.fail_pending(&format!(
+            "Handler exited with code {:?} before acknowledging the command",
+            status.code()
+        ));
+        break status.code();
+    };
+
+    let recovery = recovery(attempt, started.elapsed());
+    let (attempt, restarting) = match recovery {
+        Recovery::Restart { attempt, .. } => (attempt, true),
+        Recovery::GiveUp { attempt } => (attempt, false),
+    };
+    let (stderr_tail, project_dir) = {
+        let process = shared.lock().unwrap();
+        (
+            process.stderr_tail.iter().cloned().collect::<Vec<_>>(),
+            process.project_dir.clone(),
+        )
+    };
+    eprintln!(
+        "[RUST] Handler {} for session {} exited with code {:?} (restarting: {})",
+        config.name, session_id, code, restarting
+    );
+    let _ = app.emit(
+        &format!("{}:exited", config.name),
+        HandlerExit {
+            session_id: session_id.clone(),
+            model: config.name.clone(),
+            code,
+            stderr_tail,
+            restarting,
+            attempt,
+        },
+    );
+
+    let (Recovery::Restart { delay, .. }, Some(project_dir)) = (recovery, project_dir) else {
+        return;
+    };
+    thread::sleep(delay);
+
+    if shared.lock().unwrap().generation != generation {
+        // Stopped or restarted by a command while we were backing off.
+        return;
+    }
+    if let Err(e) = spawn_process(&config, &session_id, &app, &shared, &project_dir, attempt) {
+        eprintln!("[RUST] Failed to restart handler {}: {}", config.name, e);
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct HandlerKey {
+    session_id: String,
+    model: String,
+}
+
+impl HandlerKey {
+    fn new(session_id: &str, model: &str) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            model: model.to_string(),
+        }
+    }
+}
+
+/// Model handler processes, one per (session, model) pair, spawned from the
+/// registered handler configs.
+pub(crate) struct ModelHandlerRegistry {
+    configs: HashMap<String, HandlerConfig>,
+    handlers: HashMap<HandlerKey, Box<dyn ModelHandler>>,
+}
+
+impl ModelHandlerRegistry {
+    pub(crate) fn new() -> Self {
+        let mut configs = HashMap::new();
+        let codex = HandlerConfig::builtin_codex();
+        configs.insert(codex.name.clone(), codex);
+        Self {
+            configs,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Registers every entry of the `modelHandlers` settings object, keyed by name.
+    pub(crate) fn load_from_settings(&mut self, settings: &serde_json::Value) {
+        let Some(entries) = settings.get("modelHandlers").and_then(|v| v.as_object()) else {
+            return;
+        };
+        for (name, value) in entries {
+            match serde_json::from_value::<HandlerConfig>(value.clone()) {
+                Ok(mut config) => {
+                    config.name = name.clone();
+                    if let Err(e) = self.register(config) {
+                        eprintln!("[RUST] Skipping model handler {}: {}", name, e);
+                    }
+                }
+                Err(e) => eprintln!("[RUST] Invalid model handler config {}: {}", name, e),
+            }
+        }
+    }
+
+    /// Adds or replaces a handler config. Running processes of a replaced
+    /// handler are stopped so the next command respawns them.
+    pub(crate) fn register(&mut self, mut config: HandlerConfig) -> Result<(), String> {
+        config.name = config.name.trim().to_lowercase();
+        if config.name.is_empty() {
+            return Err("Model handler name is required".into());
+        }
+        if config.runtime != HandlerRuntime::OpenAi && config.script.trim().is_empty() {
+            return Err(format!("Model handler {} has no script", config.name));
+        }
+        let name = config.name.clone();
+        if self.configs.insert(name.clone(), config).is_some() {
+            self.stop_model(&name);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn configs(&self) -> Vec<HandlerConfig> {
+        let mut configs: Vec<HandlerConfig> = self.configs.values().cloned().collect();
+        configs.sort_by(|a, b| a.name.cmp(&b.name));
+        configs
+    }
+
+    pub(crate) fn get_mut(
+        &mut self,
+        session_id: &str,
+        model: &str,
+    ) -> Option<&mut Box<dyn ModelHandler>> {
+        self.handlers.get_mut(&HandlerKey::new(session_id, model))
+    }
+
+    pub(crate) fn get_or_create(
+        &mut self,
+        session_id: &str,
+        model: &str,
+    ) -> Result<&mut Box<dyn ModelHandler>, String> {
+        let key = HandlerKey::new(session_id, model);
+        if !self.handlers.contains_key(&key) {
+            let config = self
+                .configs
+                .get(model)
+                .cloned()
+                .ok_or_else(|| format!("Unknown model: {}", model))?;
+            let handler: Box<dyn ModelHandler> = match config.runtime {
+                HandlerRuntime::OpenAi => Box::new(OpenAiModelHandler::new(config, session_id)),
+                _ => Box::new(ProcessModelHandler::new(config, session_id)),
+            };
+            self.handlers.insert(key.clone(), handler);
+        }
+        Ok(self.handlers.get_mut(&key).expect("handler inserted above"))
+    }
+
+    /// Sessions that have a handler, i.e. were used since they were last stopped.
+    pub(crate) fn sessions(&self) -> HashSet<String> {
+        self.handlers
+            .keys()
+            .map(|key| key.session_id.clone())
+            .collect()
+    }
+
+    pub(crate) fn stop_session(&mut self, session_id: &str) {
+        self.stop_where(|key| key.session_id == session_id);
+    }
+
+    pub(crate) fn stop_model(&mut self, model: &str) {
+        self.stop_where(|key| key.model == model);
+    }
+
+    pub(crate) fn stop(&mut self, session_id: &str, model: &str) {
+        self.stop_where(|key| key.session_id == session_id && key.model == model);
+    }
+
+    fn stop_where(&mut self, predicate: impl Fn(&HandlerKey) -> bool) {
+        let keys: Vec<HandlerKey> = self
+            .handlers
+            .keys()
+            .filter(|key| predicate(key))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(mut handler) = self.handlers.remove(&key) {
+                let _ = handler.stop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_cap() {
+        let delays: Vec<Duration> = (0..MAX_RESTART_ATTEMPTS)
+            .map(|previous| match recovery(previous, Duration::ZERO) {
+                Recovery::Restart { attempt, delay } => {
+                    assert_eq!(attempt, previous + 1);
+                    delay
+                }
+                other => panic!("expected a restart, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 8000].map(Duration::from_millis)
+        );
+        assert!(delays.iter().all(|delay| *delay <= RESTART_MAX_DELAY));
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        assert_eq!(
+            recovery(MAX_RESTART_ATTEMPTS, Duration::from_secs(1)),
+            Recovery::GiveUp {
+                attempt: MAX_RESTART_ATTEMPTS + 1
+            }
+        );
+        assert_eq!(
+            recovery(u32::MAX, Duration::ZERO),
+            Recovery::GiveUp { attempt: u32::MAX }
+        );
+    }
+
+    #[test]
+    fn a_stable_run_starts_counting_again() {
+        assert_eq!(
+            recovery(MAX_RESTART_ATTEMPTS, STABLE_RUN),
+            Recovery::Restart {
+                attempt: 1,
+                delay: RESTART_BASE_DELAY
+            }
+        );
+    }
+
+    #[test]
+    fn replays_every_registration() {
+        let mut process = HandlerProcess::default();
+        process.registrations.insert(
+            "a".to_string(),
+            "{\"type\":\"session:register\",\"sessionId\":\"a\"}".to_string(),
+        );
+        process.registrations.insert(
+            "b".to_string(),
+            "{\"type\":\"session:register\",\"sessionId\":\"b\"}".to_string(),
+        );
+
+        let mut stdin = Vec::new();
+        process.replay_registrations(&mut stdin);
+
+        let mut lines: Vec<&str> = std::str::from_utf8(&stdin).unwrap().lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "{\"type\":\"session:register\",\"sessionId\":\"a\"}",
+                "{\"type\":\"session:register\",\"sessionId\":\"b\"}",
+            ]
+        );
+    }
+}
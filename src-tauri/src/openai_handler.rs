@@ -0,0 +1,650 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc;
+
+use crate::agent_tools::{self, ToolCall};
+use crate::audit;
+use crate::model_handler::{CommandAck, CommandReceipt, HandlerConfig, ModelHandler};
+use crate::permissions::{self, Verdict};
+use crate::protocol::{HandlerCommand, HandlerEvent, HandlerMessage};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_API_KEY_ENV: &str = "OPENAI_API_KEY";
+// Upper bound on model round-trips per turn when the model keeps calling tools.
+const MAX_TOOL_ROUNDS: usize = 25;
+// Parallel tool calls accepted in one completion.
+const MAX_TOOL_CALLS: usize = 64;
+// Unanswered permission requests are denied after this long.
+const PERMISSION_TIMEOUT: Duration = Duration::from_secs(600);
+const PERMISSION_POLL: Duration = Duration::from_millis(250);
+
+/// Streams chat completions from an OpenAI-compatible endpoint (OpenAI,
+/// llama.cpp, Ollama, ...) without going through a node handler process.
+/// The conversation is kept by the worker across runs until `Restart`.
+pub(crate) struct OpenAiModelHandler {
+    config: HandlerConfig,
+    session_id: String,
+    worker: Option<Worker>,
+}
+
+struct Worker {
+    project_dir: String,
+    jobs: mpsc::UnboundedSender<Job>,
+    // Runs queued or in flight.
+    pending: Arc<AtomicUsize>,
+    // Bumped by `Interrupt` and `Restart`; runs queued before are dropped.
+    epoch: Arc<AtomicU64>,
+    cancel: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+enum Job {
+    Run {
+        payload: String,
+        epoch: u64,
+    },
+    /// Forget the conversation so far.
+    Reset,
+}
+
+impl Worker {
+    /// Cancels the run in flight and drops the queued ones.
+    fn interrupt(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+}
+
+impl OpenAiModelHandler {
+    pub(crate) fn new(config: HandlerConfig, session_id: &str) -> Self {
+        Self {
+            config,
+            session_id: session_id.to_string(),
+            worker: None,
+        }
+    }
+}
+
+impl ModelHandler for OpenAiModelHandler {
+    fn start(&mut self, app: AppHandle, project_dir: &str) -> Result<(), String> {
+        if let Some(worker) = self.worker.as_ref() {
+            if worker.project_dir == project_dir && !worker.jobs.is_closed() {
+                return Ok(());
+            }
+        }
+        self.stop()?;
+
+        let (jobs, mut rx) = mpsc::unbounded_channel::<Job>();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let epoch = Arc::new(AtomicU64::new(0));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let turn = TurnContext {
+            app,
+            config: self.config.clone(),
+            session_id: self.session_id.clone(),
+            client: reqwest::Client::new(),
+            cancel: Arc::clone(&cancel),
+        };
+        let worker_pending = Arc::clone(&pending);
+        let worker_epoch = Arc::clone(&epoch);
+        let task = tauri::async_runtime::spawn(async move {
+            let mut history = Vec::new();
+            while let Some(job) = rx.recv().await {
+                match job {
+                    Job::Run { payload, epoch } => {
+                        // Cleared before the epoch check, so an interrupt
+                        // arriving in between still cancels this run.
+                        turn.cancel.store(false, Ordering::SeqCst);
+                        if worker_epoch.load(Ordering::SeqCst) == epoch {
+                            turn.run(&payload, &mut history).await;
+                        }
+                        worker_pending.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    Job::Reset => history.clear(),
+                }
+            }
+        });
+        eprintln!(
+            "[RUST] Started {} handler for session {} against {}",
+            self.config.name,
+            self.session_id,
+            self.config.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL)
+        );
+
+        self.worker = Some(Worker {
+            project_dir: project_dir.to_string(),
+            jobs,
+            pending,
+            epoch,
+            cancel,
+            task,
+        });
+        Ok(())
+    }
+
+    fn send(&mut self, command: &HandlerCommand) -> Result<CommandReceipt, String> {
+        let worker = self
+            .worker
+            .as_ref()
+            .ok_or_else(|| "Handler not started".to_string())?;
+        let mut queued = false;
+        match command {
+            HandlerCommand::Run { payload, .. } => {
+                queued = worker.pending.fetch_add(1, Ordering::SeqCst) > 0;
+                let job = Job::Run {
+                    payload: payload.clone(),
+                    epoch: worker.epoch.load(Ordering::SeqCst),
+                };
+                if worker.jobs.send(job).is_err() {
+                    worker.pending.fetch_sub(1, Ordering::SeqCst);
+                    return Err("Handler worker stopped".into());
+                }
+            }
+            HandlerCommand::Interrupt { .. } => worker.interrupt(),
+            HandlerCommand::Restart { .. } => {
+                worker.interrupt();
+                if worker.jobs.send(Job::Reset).is_err() {
+                    return Err("Handler worker stopped".into());
+                }
+            }
+            HandlerCommand::Hello { .. }
+            | HandlerCommand::Register { .. }
+            | HandlerCommand::Permission { .. } => {}
+        }
+
+        let command_id = uuid::Uuid::new_v4().to_string();
+        let (receipt, tx) = CommandReceipt::new(command_id.clone());
+        let _ = tx.send(Ok(CommandAck { command_id, queued }));
+        Ok(receipt)
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        if let Some(worker) = self.worker.take() {
+            worker.cancel.store(true, Ordering::SeqCst);
+            worker.task.abort();
+        }
+        Ok(())
+    }
+}
+
+struct TurnContext {
+    app: AppHandle,
+    config: HandlerConfig,
+    session_id: String,
+    client: reqwest::Client,
+    cancel: Arc<AtomicBool>,
+}
+
+impl TurnContext {
+    async fn run(&self, payload: &str, history: &mut Vec<serde_json::Value>) {
+        let request = serde_json::from_str::<serde_json::Value>(payload).ok();
+        let prompt = request
+            .as_ref()
+            .and_then(|r| r.get("currentMessage"))
+            .and_then(|m| m.as_str())
+            .unwrap_or(payload)
+            .to_string();
+        let model = self
+            .config
+            .model
+            .clone()
+            .or_else(|| {
+                request
+                    .as_ref()
+                    .and_then(|r| r.get("model"))
+                    .and_then(|m| m.as_str())
+                    .map(str::to_string)
+            })
+            .unwrap_or_default();
+
+        let message_id = format!("{}_{}", self.config.name, uuid::Uuid::new_v4().simple());
+        if prompt.trim().is_empty() {
+            self.emit(HandlerEvent::AssistantComplete {
+                id: message_id,
+                text: String::new(),
+            });
+            return;
+        }
+
+        self.emit(HandlerEvent::ModelUpdate {
+            model: Some(model.clone()),
+        });
+
+        let text = match self.agent_loop(&model, &prompt, &message_id, history).await {
+            Ok(_) if self.cancel.load(Ordering::SeqCst) => "⚠️ Interrupted".to_string(),
+            Ok(text) => text,
+            Err(e) => format!("⚠️ {}", e),
+        };
+        self.emit(HandlerEvent::AssistantComplete {
+            id: message_id,
+            text,
+        });
+    }
+
+    /// Alternates completions and tool execution until the model answers
+    /// without calling tools, appending every turn to `messages`. Returns the
+    /// assistant text of every round.
+    async fn agent_loop(
+        &self,
+        model: &str,
+        prompt: &str,
+        message_id: &str,
+        messages: &mut Vec<serde_json::Value>,
+    ) -> Result<String, String> {
+        messages.push(serde_json::json!({ "role": "user", "content": prompt }));
+        let mut transcript = String::new();
+
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let completion = self.stream_completion(model, messages, message_id).await?;
+            transcript.push_str(&completion.text);
+            if completion.tool_calls.is_empty() || self.cancel.load(Ordering::SeqCst) {
+                if !completion.text.is_empty() {
+                    messages.push(serde_json::json!({
+                        "role": "assistant",
+                        "content": completion.text,
+                    }));
+                }
+                return Ok(transcript);
+            }
+
+            messages.push(serde_json::json!({
+                "role": "assistant",
+                "content": if completion.text.is_empty() { None } else { Some(&completion.text) },
+                "tool_calls": completion.tool_calls.iter().map(|call| serde_json::json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": { "name": call.name, "arguments": call.arguments },
+                })).collect::<Vec<_>>(),
+            }));
+
+            for call in &completion.tool_calls {
+                if self.cancel.load(Ordering::SeqCst) {
+                    // Every call needs an answer before the conversation can
+                    // go on.
+                    messages.push(serde_json::json!({
+                        "role": "tool",
+                        "tool_call_id": call.id,
+                        "content": "Interrupted before running",
+                    }));
+                    continue;
+                }
+                let (tool, args) = agent_tools::display(&self.session_id, call);
+                self.emit(HandlerEvent::ToolStart {
+                    id: call.id.clone(),
+                    tool,
+                    args,
+                });
+                let outcome = match self.authorize(call).await {
+                    Ok(()) => agent_tools::run_tool(&self.session_id, call).await,
+                    Err(e) => agent_tools::ToolOutcome {
+                        output: e,
+                        exit_code: Some(1),
+                    },
+                };
+                self.emit(HandlerEvent::ToolOutput {
+                    id: call.id.clone(),
+                    chunk: outcome.output.clone(),
+                    stream: None,
+                    done: true,
+                    exit_code: outcome.exit_code,
+                });
+                messages.push(serde_json::json!({
+                    "role": "tool",
+                    "tool_call_id": call.id,
+                    "content": outcome.output,
+                }));
+            }
+            if self.cancel.load(Ordering::SeqCst) {
+                return Ok(transcript);
+            }
+        }
+
+        transcript.push_str(&format!(
+            "\n⚠️ Stopped after {} tool rounds",
+            MAX_TOOL_ROUNDS
+        ));
+        Ok(transcript)
+    }
+
+    /// Checks a tool call against the permission policy, asking the user via
+    /// `permission:request` when no rule or grant decides it.
+    async fn authorize(&self, call: &ToolCall) -> Result<(), String> {
+        let (tool, subject) = agent_tools::permission_target(&self.session_id, call);
+        let verdict = permissions::evaluate(&self.session_id, &tool, &subject);
+        if verdict != Verdict::Ask {
+            let allow = verdict == Verdict::Allow;
+            audit::record_permission(
+                &self.session_id,
+                &call.id,
+                &tool,
+                &subject,
+                allow,
+                "once",
+                "policy",
+            );
+            if !allow {
+                return Err(format!("Permission denied by policy: {}", tool));
+            }
+            return Ok(());
+        }
+
+        let (request_id, mut decision) =
+            permissions::open_request(&self.session_id, &tool, &subject);
+        let details = if tool == "bash" {
+            serde_json::json!({ "command": subject })
+        } else {
+            serde_json::json!({ "files": [subject] })
+        };
+        self.emit(HandlerEvent::PermissionRequest {
+            id: request_id.clone(),
+            tools: vec![tool.clone()],
+            scope: None,
+            details,
+        });
+
+        let started = Instant::now();
+        loop {
+            match tokio::time::timeout(PERMISSION_POLL, &mut decision).await {
+                Ok(Ok(true)) => return Ok(()),
+                Ok(_) => return Err(format!("Permission denied: {}", tool)),
+                Err(_) => {
+                    if self.cancel.load(Ordering::SeqCst) || started.elapsed() > PERMISSION_TIMEOUT
+                    {
+                        permissions::cancel_request(&request_id);
+                        return Err(format!("Permission request for {} was not answered", tool));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Posts one streamed chat completion request, forwarding content deltas as
+    /// `assistant:delta` events and collecting any tool calls.
+    async fn stream_completion(
+        &self,
+        model: &str,
+        messages: &[serde_json::Value],
+        message_id: &str,
+    ) -> Result<Completion, String> {
+        let base_url = self
+            .config
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/');
+        let body = serde_json::json!({
+            "model": model,
+            "messages": messages,
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "tools": agent_tools::tool_definitions(),
+        });
+
+        let mut request = self
+            .client
+            .post(format!("{}/chat/completions", base_url))
+            .json(&body);
+        if let Some(key) = self.api_key() {
+            request = request.bearer_auth(key);
+        }
+        let mut response = request
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let detail = response.text().await.unwrap_or_default();
+            return Err(format!("{}: {}", status, detail.trim()));
+        }
+
+        let mut decoder = SseDecoder::default();
+        let mut completion = Completion::default();
+        while let Some(bytes) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("Stream failed: {}", e))?
+        {
+            if self.cancel.load(Ordering::SeqCst) {
+                break;
+            }
+            for data in decoder.push(&bytes) {
+                if data == "[DONE]" {
+                    return Ok(completion.finish());
+                }
+                let Ok(chunk) = serde_json::from_str::<serde_json::Value>(&data) else {
+                    continue;
+                };
+                if let Some(delta) = chunk
+                    .pointer("/choices/0/delta/content")
+                    .and_then(|c| c.as_str())
+                    .filter(|c| !c.is_empty())
+                {
+                    completion.text.push_str(delta);
+                    self.emit(HandlerEvent::AssistantDelta {
+                        id: message_id.to_string(),
+                        chunk: delta.to_string(),
+                    });
+                }
+                if let Some(calls) = chunk
+                    .pointer("/choices/0/delta/tool_calls")
+                    .and_then(|c| c.as_array())
+                {
+                    completion.push_tool_deltas(calls)?;
+                }
+                if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+                    self.emit_usage(usage);
+                }
+            }
+        }
+        Ok(completion.finish())
+    }
+
+    fn api_key(&self) -> Option<String> {
+        let var = self
+            .config
+            .api_key_env
+            .as_deref()
+            .unwrap_or(DEFAULT_API_KEY_ENV);
+        self.config
+            .env
+            .get(var)
+            .cloned()
+            .or_else(|| std::env::var(var).ok())
+            .filter(|key| !key.trim().is_empty())
+    }
+
+    fn emit_usage(&self, usage: &serde_json::Value) {
+        let count = |pointer: &str| usage.pointer(pointer).and_then(|v| v.as_u64()).unwrap_or(0);
+        let input = count("/prompt_tokens");
+        let output = count("/completion_tokens");
+        let cached = count("/prompt_tokens_details/cached_tokens");
+        self.emit(HandlerEvent::TelemetryTokens {
+            tokens_in: input,
+            tokens_out: output,
+            cached_tokens: cached,
+            token_usage: serde_json::json!({
+                "input": input,
+                "cachedInput": cached,
+                "output": output,
+                "reasoning": count("/completion_tokens_details/reasoning_tokens"),
+                "total": input + output,
+            }),
+        });
+    }
+
+    fn emit(&self, event: HandlerEvent) {
+        let message = HandlerMessage {
+            session_id: Some(self.session_id.clone()),
+            ts: Some(chrono::Utc::now().timestamp_millis().max(0) as u64),
+            event,
+        };
+        audit::observe(&self.config.name, &message);
+        let _ = self
+            .app
+            .emit(&format!("{}:stream", self.config.name), message);
+    }
+}
+
+#[derive(Default)]
+struct Completion {
+    text: String,
+    tool_calls: Vec<ToolCall>,
+}
+
+impl Completion {
+    /// Merges streamed `tool_calls` fragments, which arrive keyed by index
+    /// with the arguments split across chunks. An index may only continue a
+    /// call or start the next one.
+    fn push_tool_deltas(&mut self, deltas: &[serde_json::Value]) -> Result<(), String> {
+        for delta in deltas {
+            let index = delta
+                .get("index")
+                .and_then(|i| i.as_u64())
+                .unwrap_or(self.tool_calls.len() as u64);
+            let index = usize::try_from(index)
+                .ok()
+                .filter(|i| *i <= self.tool_calls.len() && *i < MAX_TOOL_CALLS)
+                .ok_or_else(|| format!("Unexpected tool call index {}", index))?;
+            if index == self.tool_calls.len() {
+                self.tool_calls.push(ToolCall::default());
+            }
+            let call = &mut self.tool_calls[index];
+            if let Some(id) = delta.get("id").and_then(|v| v.as_str()) {
+                call.id = id.to_string();
+            }
+            if let Some(name) = delta.pointer("/function/name").and_then(|v| v.as_str()) {
+                call.name.push_str(name);
+            }
+            if let Some(args) = delta
+                .pointer("/function/arguments")
+                .and_then(|v| v.as_str())
+            {
+                call.arguments.push_str(args);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Self {
+        self.tool_calls.retain(|call| !call.name.is_empty());
+        for call in &mut self.tool_calls {
+            if call.id.is_empty() {
+                call.id = format!("call_{}", uuid::Uuid::new_v4().simple());
+            }
+            if call.arguments.trim().is_empty() {
+                call.arguments = "{}".to_string();
+            }
+        }
+        self
+    }
+}
+
+/// Splits a server-sent events byte stream into `data:` payloads.
+#[derive(Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&raw);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data.push(value.trim_start().to_string());
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decodes_events_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: {\"a\":").is_empty());
+        assert!(decoder.push(b"1}\n").is_empty());
+        assert_eq!(decoder.push(b"\ndata: [DO"), vec!["{\"a\":1}"]);
+        assert_eq!(decoder.push(b"NE]\n\n"), vec!["[DONE]"]);
+    }
+
+    #[test]
+    fn decodes_crlf_and_multi_line_data() {
+        let mut decoder = SseDecoder::default();
+        let events = decoder.push(
+            b": keep-alive\r\nevent: message\r\ndata: first\r\ndata:second\r\n\r\ndata: {}\r\n\r\n",
+        );
+        assert_eq!(events, vec!["first\nsecond", "{}"]);
+        // A blank line with no data before it yields nothing.
+        assert!(decoder.push(b"\r\n\r\n").is_empty());
+    }
+
+    #[test]
+    fn merges_tool_call_fragments() {
+        let mut completion = Completion::default();
+        completion
+            .push_tool_deltas(&[json!({
+                "index": 0, "id": "call_a",
+                "function": { "name": "read_", "arguments": "{\"pa" }
+            })])
+            .unwrap();
+        completion
+            .push_tool_deltas(&[
+                json!({ "index": 0, "function": { "name": "file", "arguments": "th\":\"a\"}" } }),
+                json!({ "index": 1, "function": { "name": "list_files" } }),
+            ])
+            .unwrap();
+        // Fragments without a name never become calls.
+        completion
+            .push_tool_deltas(&[json!({ "function": { "arguments": "{}" } })])
+            .unwrap();
+
+        let completion = completion.finish();
+        assert_eq!(completion.tool_calls.len(), 2);
+        let read = &completion.tool_calls[0];
+        assert_eq!(read.id, "call_a");
+        assert_eq!(read.name, "read_file");
+        assert_eq!(read.arguments, "{\"path\":\"a\"}");
+        let list = &completion.tool_calls[1];
+        assert!(list.id.starts_with("call_"));
+        assert_eq!(list.arguments, "{}");
+    }
+
+    #[test]
+    fn rejects_out_of_range_tool_indexes() {
+        let mut completion = Completion::default();
+        assert!(completion
+            .push_tool_deltas(&[json!({ "index": 1, "function": { "name": "a" } })])
+            .is_err());
+        assert!(completion
+            .push_tool_deltas(&[json!({ "index": u64::MAX, "function": { "name": "a" } })])
+            .is_err());
+        assert!(completion.tool_calls.is_empty());
+
+        for index in 0..MAX_TOOL_CALLS {
+            completion
+                .push_tool_deltas(&[json!({ "index": index, "function": { "name": "a" } })])
+                .unwrap();
+        }
+        assert!(completion
+            .push_tool_deltas(&[json!({ "index": MAX_TOOL_CALLS, "function": { "name": "a" } })])
+            .is_err());
+        assert_eq!(completion.tool_calls.len(), MAX_TOOL_CALLS);
+    }
+}
@@ -0,0 +1,881 @@
+//! Permission policy for tool requests.
+//!
+//! Requests are checked against the `permissions.allow/ask/deny` rules in
+//! settings.json (global, plus `projects.<dir>.permissions`) and against the
+//! grants recorded when the user answers with a session or project scope.
+//! Project grants are persisted next to settings.json so they survive restarts.
+//!
+//! The backend's own agent tools and handlers that raise `permission:request`
+//! are asked before acting. The codex handler runs the Codex SDK, which has no
+//! approval hook, so its shell commands and file changes are screened when
+//! they are announced with `tool:start`: a deny verdict interrupts the run.
+//! Such a handler cannot be paused to ask, and a change it reports only after
+//! applying it is recorded as denied but not undone.
+
+use globset::{Glob, GlobMatcher};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::oneshot;
+
+use crate::audit;
+use crate::get_session_project_dir;
+
+/// Tool names a bare rule (no parentheses) refers to as a whole.
+const KNOWN_TOOLS: &[&str] = &[
+    "bash", "read", "write", "edit", "grep", "web", "mcp", "task",
+];
+
+static STORE: Lazy<Mutex<PermissionStore>> = Lazy::new(|| Mutex::new(PermissionStore::load()));
+static RULES: Lazy<Mutex<RulesCache>> = Lazy::new(|| Mutex::new(RulesCache::default()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Allow,
+    Ask,
+    Deny,
+}
+
+/// An "always allow" (or "always deny") answer, scoped to a session or project.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PermissionGrant {
+    pub id: String,
+    pub project_dir: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub tool: String,
+    pub pattern: String,
+    pub allow: bool,
+    pub scope: String,
+    pub created_at: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct GrantsFile {
+    #[serde(default)]
+    grants: Vec<PermissionGrant>,
+}
+
+struct PendingRequest {
+    session_id: String,
+    tool: String,
+    subject: String,
+    // Set when a backend task is waiting on the answer.
+    responder: Option<oneshot::Sender<bool>>,
+}
+
+/// A recorded grant with its pattern compiled.
+struct Grant {
+    record: PermissionGrant,
+    rule: Rule,
+}
+
+impl Grant {
+    fn new(record: PermissionGrant) -> Result<Self, String> {
+        let rule = Rule {
+            tool: record.tool.clone(),
+            matcher: Matcher::parse(&record.pattern)?,
+        };
+        Ok(Grant { record, rule })
+    }
+
+    fn applies_to(&self, project_dir: &str, session_id: &str) -> bool {
+        self.record.project_dir == project_dir
+            && self
+                .record
+                .session_id
+                .as_deref()
+                .map_or(true, |s| s == session_id)
+    }
+}
+
+#[derive(Default)]
+struct PermissionStore {
+    grants: Vec<Grant>,
+    pending: HashMap<String, PendingRequest>,
+}
+
+/// A parsed `Tool(spec)` rule. `spec` is a glob, or a regex when prefixed
+/// with `re:`. Bare specs that are not a tool name apply to shell commands.
+struct Rule {
+    tool: String,
+    matcher: Matcher,
+}
+
+enum Matcher {
+    Any,
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        if spec.is_empty() || spec == "*" || spec == "**" {
+            return Ok(Matcher::Any);
+        }
+        if let Some(pattern) = spec.strip_prefix("re:") {
+            return Regex::new(pattern)
+                .map(Matcher::Regex)
+                .map_err(|e| format!("Invalid regex '{}': {}", pattern, e));
+        }
+        Glob::new(spec)
+            .map(|g| Matcher::Glob(g.compile_matcher()))
+            .map_err(|e| format!("Invalid glob '{}': {}", spec, e))
+    }
+
+    fn matches(&self, subject: &str) -> bool {
+        match self {
+            Matcher::Any => true,
+            Matcher::Glob(glob) => glob.is_match(subject),
+            Matcher::Regex(re) => re.is_match(subject),
+        }
+    }
+}
+
+impl Rule {
+    fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        if let Some(open) = raw.find('(') {
+            if raw.ends_with(')') {
+                return Ok(Rule {
+                    tool: normalize_tool(&raw[..open]),
+                    matcher: Matcher::parse(&raw[open + 1..raw.len() - 1])?,
+                });
+            }
+        }
+        let tool = normalize_tool(raw);
+        if KNOWN_TOOLS.contains(&tool.as_str()) {
+            return Ok(Rule {
+                tool,
+                matcher: Matcher::Any,
+            });
+        }
+        Ok(Rule {
+            tool: "bash".to_string(),
+            matcher: Matcher::parse(raw)?,
+        })
+    }
+
+    fn matches(&self, tool: &str, subject: &str) -> bool {
+        (self.tool == "*" || self.tool == tool) && self.matcher.matches(subject)
+    }
+}
+
+fn normalize_tool(name: &str) -> String {
+    match name.trim().to_lowercase().as_str() {
+        "shell" | "command" => "bash".to_string(),
+        "edit" | "multiedit" | "write_file" => "write".to_string(),
+        "read_file" => "read".to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[derive(Default)]
+struct SettingsRules {
+    allow: Vec<Rule>,
+    ask: Vec<Rule>,
+    deny: Vec<Rule>,
+}
+
+impl SettingsRules {
+    /// The global rules of `settings` plus those of `project_dir`.
+    fn from_settings(settings: &serde_json::Value, project_dir: &str) -> Self {
+        let mut rules = SettingsRules::default();
+        rules.extend(settings.get("permissions"));
+        rules.extend(
+            settings
+                .get("projects")
+                .and_then(|p| p.get(project_dir))
+                .and_then(|p| p.get("permissions")),
+        );
+        rules
+    }
+
+    fn extend(&mut self, section: Option<&serde_json::Value>) {
+        let Some(section) = section else {
+            return;
+        };
+        for (key, list) in [
+            ("allow", &mut self.allow),
+            ("ask", &mut self.ask),
+            ("deny", &mut self.deny),
+        ] {
+            let entries = section.get(key).and_then(|v| v.as_array());
+            for raw in entries.into_iter().flatten().filter_map(|v| v.as_str()) {
+                match Rule::parse(raw) {
+                    Ok(rule) => list.push(rule),
+                    Err(e) => eprintln!("[RUST] Ignoring permission rule: {}", e),
+                }
+            }
+        }
+    }
+
+    fn any(rules: &[Rule], tool: &str, subject: &str) -> bool {
+        rules.iter().any(|rule| rule.matches(tool, subject))
+    }
+}
+
+/// Compiled settings rules per project, valid while settings.json keeps the
+/// modification time it had when it was read.
+#[derive(Default)]
+struct RulesCache {
+    loaded: bool,
+    modified: Option<SystemTime>,
+    settings: serde_json::Value,
+    projects: HashMap<String, Arc<SettingsRules>>,
+}
+
+fn rules_for(project_dir: &str) -> Arc<SettingsRules> {
+    let modified = crate::settings_path()
+        .ok()
+        .and_then(|path| fs::metadata(path).ok())
+        .and_then(|meta| meta.modified().ok());
+    let mut cache = RULES.lock().unwrap();
+    if !cache.loaded || cache.modified != modified {
+        cache.settings = crate::load_settings().unwrap_or_else(|e| {
+            eprintln!("[RUST] Permission rules unavailable: {}", e);
+            serde_json::Value::Null
+        });
+        cache.modified = modified;
+        cache.loaded = true;
+        cache.projects.clear();
+    }
+    if let Some(rules) = cache.projects.get(project_dir) {
+        return Arc::clone(rules);
+    }
+    let rules = Arc::new(SettingsRules::from_settings(&cache.settings, project_dir));
+    cache
+        .projects
+        .insert(project_dir.to_string(), Arc::clone(&rules));
+    rules
+}
+
+fn grants_path() -> Result<PathBuf, String> {
+    let home = std::env::var("HOME").map_err(|e| format!("Failed to get HOME: {}", e))?;
+    Ok(PathBuf::from(home).join(".config/claude/permissions.json"))
+}
+
+fn session_project(session_id: &str) -> String {
+    get_session_project_dir(session_id).unwrap_or_default()
+}
+
+impl PermissionStore {
+    fn load() -> Self {
+        match grants_path() {
+            Ok(path) => Self::load_from(&path),
+            Err(_) => PermissionStore::default(),
+        }
+    }
+
+    fn load_from(path: &Path) -> Self {
+        let records = fs::read_to_string(path)
+            .ok()
+            .and_then(
+                |contents| match serde_json::from_str::<GrantsFile>(&contents) {
+                    Ok(file) => Some(file.grants),
+                    Err(e) => {
+                        eprintln!("[RUST] Failed to parse permission grants: {}", e);
+                        None
+                    }
+                },
+            )
+            .unwrap_or_default();
+        let grants = records
+            .into_iter()
+            .filter_map(|record| {
+                Grant::new(record)
+                    .map_err(|e| eprintln!("[RUST] Ignoring permission grant: {}", e))
+                    .ok()
+            })
+            .collect();
+        PermissionStore {
+            grants,
+            pending: HashMap::new(),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        self.save_to(&grants_path()?)
+    }
+
+    fn save_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        let file = GrantsFile {
+            grants: self
+                .grants
+                .iter()
+                .filter(|g| g.record.scope == "project")
+                .map(|g| g.record.clone())
+                .collect(),
+        };
+        let contents = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize permission grants: {}", e))?;
+        fs::write(path, contents).map_err(|e| format!("Failed to write permission grants: {}", e))
+    }
+
+    /// Deny and ask rules from settings win over recorded grants, which win
+    /// over allow rules. Anything unmatched is asked.
+    fn verdict(
+        &self,
+        rules: &SettingsRules,
+        project_dir: &str,
+        session_id: &str,
+        tool: &str,
+        subject: &str,
+    ) -> Verdict {
+        if SettingsRules::any(&rules.deny, tool, subject) {
+            return Verdict::Deny;
+        }
+        if SettingsRules::any(&rules.ask, tool, subject) {
+            return Verdict::Ask;
+        }
+
+        let mut granted = None;
+        for grant in self
+            .grants
+            .iter()
+            .filter(|g| g.applies_to(project_dir, session_id))
+        {
+            if grant.rule.matches(tool, subject) {
+                if !grant.record.allow {
+                    return Verdict::Deny;
+                }
+                granted = Some(Verdict::Allow);
+            }
+        }
+        if let Some(verdict) = granted {
+            return verdict;
+        }
+
+        if SettingsRules::any(&rules.allow, tool, subject) {
+            Verdict::Allow
+        } else {
+            Verdict::Ask
+        }
+    }
+
+    /// Records the answer to `request`, matching `pattern` or, without one,
+    /// exactly the requested subject. Session grants apply to the requesting
+    /// session only.
+    fn grant(
+        &mut self,
+        project_dir: &str,
+        request: &PendingRequest,
+        pattern: Option<String>,
+        allow: bool,
+        scope: &str,
+    ) -> Result<(), String> {
+        let pattern =
+            pattern.unwrap_or_else(|| format!("re:^{}$", regex::escape(&request.subject)));
+        self.grants.push(Grant::new(PermissionGrant {
+            id: uuid::Uuid::new_v4().to_string(),
+            project_dir: project_dir.to_string(),
+            session_id: (scope == "session").then(|| request.session_id.clone()),
+            tool: request.tool.clone(),
+            pattern,
+            allow,
+            scope: scope.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        })?);
+        Ok(())
+    }
+}
+
+pub(crate) fn evaluate(session_id: &str, tool: &str, subject: &str) -> Verdict {
+    let tool = normalize_tool(tool);
+    let project_dir = session_project(session_id);
+    let rules = rules_for(&project_dir);
+    STORE
+        .lock()
+        .unwrap()
+        .verdict(&rules, &project_dir, session_id, &tool, subject)
+}
+
+/// Screens an action announced with `tool:start` by a handler that cannot be
+/// asked first. Returns the subject that was denied, if any; the caller is
+/// expected to stop the run.
+pub(crate) fn screen_action(
+    session_id: &str,
+    action_id: &str,
+    tool: &str,
+    args: &serde_json::Value,
+) -> Option<String> {
+    let (tool, subjects): (&str, Vec<&str>) = match tool {
+        "bash" => (
+            "bash",
+            args.get("command")
+                .and_then(|c| c.as_str())
+                .into_iter()
+                .collect(),
+        ),
+        "file-change" => (
+            "write",
+            args.get("changes")
+                .and_then(|c| c.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|change| change.get("path").and_then(|p| p.as_str()))
+                .collect(),
+        ),
+        _ => return None,
+    };
+    let denied = subjects
+        .into_iter()
+        .find(|subject| evaluate(session_id, tool, subject) == Verdict::Deny)?
+        .to_string();
+    audit::record_permission(
+        session_id, action_id, tool, &denied, false, "once", "policy",
+    );
+    Some(denied)
+}
+
+/// Registers a request raised by the backend itself. The receiver yields the
+/// user's answer once `resolve` is called with the returned id.
+pub(crate) fn open_request(
+    session_id: &str,
+    tool: &str,
+    subject: &str,
+) -> (String, oneshot::Receiver<bool>) {
+    let request_id = format!("perm_{}", uuid::Uuid::new_v4().simple());
+    let (tx, rx) = oneshot::channel();
+    STORE.lock().unwrap().pending.insert(
+        request_id.clone(),
+        PendingRequest {
+            session_id: session_id.to_string(),
+            tool: normalize_tool(tool),
+            subject: subject.to_string(),
+            responder: Some(tx),
+        },
+    );
+    (request_id, rx)
+}
+
+pub(crate) fn cancel_request(request_id: &str) {
+    STORE.lock().unwrap().pending.remove(request_id);
+}
+
+/// Screens a `permission:request` raised by a handler. Returns the answer when
+/// policy decides it; otherwise tracks the request until the user resolves it.
+pub(crate) fn screen_request(
+    session_id: &str,
+    request_id: &str,
+    tools: &[String],
+    details: &serde_json::Value,
+) -> Option<bool> {
+    let subject = details
+        .get("command")
+        .and_then(|c| c.as_str())
+        .or_else(|| {
+            details
+                .get("files")
+                .and_then(|f| f.get(0))
+                .and_then(|f| f.as_str())
+        })
+        .unwrap_or("")
+        .to_string();
+
+    let project_dir = session_project(session_id);
+    let rules = rules_for(&project_dir);
+    let mut store = STORE.lock().unwrap();
+    let verdicts: Vec<Verdict> = tools
+        .iter()
+        .map(|tool| {
+            store.verdict(
+                &rules,
+                &project_dir,
+                session_id,
+                &normalize_tool(tool),
+                &subject,
+            )
+        })
+        .collect();
+    let decided = if verdicts.contains(&Verdict::Deny) {
+        Some(false)
+    } else if !verdicts.is_empty() && verdicts.iter().all(|v| *v == Verdict::Allow) {
+        Some(true)
+    } else {
+        None
+    };
+    if let Some(allow) = decided {
+        drop(store);
+        let tool = tools.join(",");
+        audit::record_permission(
+            session_id, request_id, &tool, &subject, allow, "once", "policy",
+        );
+        return Some(allow);
+    }
+
+    store.pending.insert(
+        request_id.to_string(),
+        PendingRequest {
+            session_id: session_id.to_string(),
+            tool: tools
+                .first()
+                .map(|t| normalize_tool(t))
+                .unwrap_or_else(|| "*".to_string()),
+            subject,
+            responder: None,
+        },
+    );
+    None
+}
+
+/// Applies the user's answer to a pending request and returns the effective
+/// decision. Session and project scopes record a grant for the tool and
+/// `pattern`, or for exactly the requested subject when no pattern is given;
+/// deny rules from settings cannot be overridden.
+pub(crate) fn resolve(
+    session_id: &str,
+    request_id: &str,
+    allow: bool,
+    scope: &str,
+    pattern: Option<String>,
+) -> Result<bool, String> {
+    let project_dir = session_project(session_id);
+    let rules = rules_for(&project_dir);
+    let mut store = STORE.lock().unwrap();
+    let Some(pending) = store.pending.remove(request_id) else {
+        return Ok(allow);
+    };
+    if pending.session_id != session_id {
+        store.pending.insert(request_id.to_string(), pending);
+        return Err("Permission request belongs to another session".to_string());
+    }
+
+    let allow = allow
+        && store.verdict(
+            &rules,
+            &project_dir,
+            session_id,
+            &pending.tool,
+            &pending.subject,
+        ) != Verdict::Deny;
+    if scope == "session" || scope == "project" {
+        store.grant(&project_dir, &pending, pattern, allow, scope)?;
+        if scope == "project" {
+            store.save()?;
+        }
+    }
+
+    drop(store);
+    audit::record_permission(
+        session_id,
+        request_id,
+        &pending.tool,
+        &pending.subject,
+        allow,
+        scope,
+        "user",
+    );
+    if let Some(responder) = pending.responder {
+        let _ = responder.send(allow);
+    }
+    Ok(allow)
+}
+
+#[tauri::command]
+pub fn list_permission_grants(project_dir: Option<String>) -> Vec<PermissionGrant> {
+    STORE
+        .lock()
+        .unwrap()
+        .grants
+        .iter()
+        .map(|g| &g.record)
+        .filter(|g| {
+            project_dir
+                .as_deref()
+                .map_or(true, |dir| g.project_dir == dir)
+        })
+        .cloned()
+        .collect()
+}
+
+#[tauri::command]
+pub fn revoke_permission_grant(id: String) -> Result<(), String> {
+    let mut store = STORE.lock().unwrap();
+    let index = store
+        .grants
+        .iter()
+        .position(|g| g.record.id == id)
+        .ok_or_else(|| format!("Permission grant not found: {}", id))?;
+    let grant = store.grants.remove(index);
+    if grant.record.scope == "project" {
+        store.save()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PROJECT: &str = "/work/app";
+
+    fn request(session_id: &str, tool: &str, subject: &str) -> PendingRequest {
+        PendingRequest {
+            session_id: session_id.to_string(),
+            tool: tool.to_string(),
+            subject: subject.to_string(),
+            responder: None,
+        }
+    }
+
+    fn verdict(
+        store: &PermissionStore,
+        rules: &SettingsRules,
+        session_id: &str,
+        tool: &str,
+        subject: &str,
+    ) -> Verdict {
+        store.verdict(rules, PROJECT, session_id, tool, subject)
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = Rule::parse("Bash(npm run *)").unwrap();
+        assert_eq!(rule.tool, "bash");
+        assert!(rule.matches("bash", "npm run test"));
+        assert!(!rule.matches("bash", "npx vite"));
+        assert!(!rule.matches("write", "npm run test"));
+
+        // Bare tool names cover the whole tool; other bare specs are commands.
+        let rule = Rule::parse("Edit").unwrap();
+        assert_eq!(rule.tool, "write");
+        assert!(rule.matches("write", "src/main.rs"));
+        let rule = Rule::parse("git status").unwrap();
+        assert!(rule.matches("bash", "git status"));
+        assert!(!rule.matches("bash", "git push"));
+
+        let rule = Rule::parse("Shell(re:^cargo (build|test)$)").unwrap();
+        assert!(rule.matches("bash", "cargo test"));
+        assert!(!rule.matches("bash", "cargo test; rm -rf /"));
+
+        assert!(Rule::parse("Bash(re:(unclosed)").is_err());
+        assert!(Rule::parse("Write(src/[)").is_err());
+    }
+
+    #[test]
+    fn merges_global_and_project_rules() {
+        let settings = json!({
+            "permissions": { "allow": ["Bash(ls *)", "Bash(re:[)"] },
+            "projects": {
+                "/work/app": { "permissions": { "deny": ["Bash(rm *)"] } },
+                "/work/other": { "permissions": { "deny": ["Bash(ls *)"] } }
+            }
+        });
+        let rules = SettingsRules::from_settings(&settings, PROJECT);
+        // The invalid regex is skipped rather than dropping the whole list.
+        assert_eq!(rules.allow.len(), 1);
+        assert_eq!(rules.deny.len(), 1);
+
+        let store = PermissionStore::default();
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "ls -la"),
+            Verdict::Allow
+        );
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "rm -rf target"),
+            Verdict::Deny
+        );
+    }
+
+    #[test]
+    fn deny_beats_ask_beats_grants_beat_allow() {
+        let rules = SettingsRules::from_settings(
+            &json!({ "permissions": {
+                "allow": ["Bash(*)"],
+                "ask": ["Bash(git push*)"],
+                "deny": ["Bash(rm *)"]
+            }}),
+            PROJECT,
+        );
+        let mut store = PermissionStore::default();
+        for subject in ["rm -rf /", "git push origin"] {
+            store
+                .grant(
+                    PROJECT,
+                    &request("s1", "bash", subject),
+                    None,
+                    true,
+                    "project",
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "rm -rf /"),
+            Verdict::Deny
+        );
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "git push origin"),
+            Verdict::Ask
+        );
+
+        store
+            .grant(
+                PROJECT,
+                &request("s1", "bash", "make"),
+                None,
+                false,
+                "project",
+            )
+            .unwrap();
+        assert_eq!(verdict(&store, &rules, "s1", "bash", "make"), Verdict::Deny);
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "cargo build"),
+            Verdict::Allow
+        );
+        assert_eq!(
+            verdict(&store, &rules, "s1", "write", "src/lib.rs"),
+            Verdict::Ask
+        );
+    }
+
+    #[test]
+    fn grants_respect_their_scope() {
+        let rules = SettingsRules::default();
+        let mut store = PermissionStore::default();
+        store
+            .grant(
+                PROJECT,
+                &request("s1", "bash", "npm test"),
+                None,
+                true,
+                "session",
+            )
+            .unwrap();
+        store
+            .grant(
+                PROJECT,
+                &request("s1", "write", "src/lib.rs"),
+                None,
+                true,
+                "project",
+            )
+            .unwrap();
+
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "npm test"),
+            Verdict::Allow
+        );
+        assert_eq!(
+            verdict(&store, &rules, "s2", "bash", "npm test"),
+            Verdict::Ask
+        );
+        assert_eq!(
+            verdict(&store, &rules, "s2", "write", "src/lib.rs"),
+            Verdict::Allow
+        );
+        assert_eq!(
+            store.verdict(&rules, "/work/other", "s2", "write", "src/lib.rs"),
+            Verdict::Ask
+        );
+    }
+
+    #[test]
+    fn default_grant_covers_exactly_the_subject() {
+        let rules = SettingsRules::default();
+        let mut store = PermissionStore::default();
+        store
+            .grant(
+                PROJECT,
+                &request("s1", "bash", "ls -la"),
+                None,
+                true,
+                "session",
+            )
+            .unwrap();
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "ls -la"),
+            Verdict::Allow
+        );
+        for subject in ["ls -la; rm -rf /", "ls", "sudo ls -la"] {
+            assert_eq!(
+                verdict(&store, &rules, "s1", "bash", subject),
+                Verdict::Ask,
+                "{}",
+                subject
+            );
+        }
+
+        // An explicit pattern widens it.
+        let ls = request("s1", "bash", "ls -la");
+        store
+            .grant(PROJECT, &ls, Some("ls *".to_string()), true, "session")
+            .unwrap();
+        assert_eq!(
+            verdict(&store, &rules, "s1", "bash", "ls src"),
+            Verdict::Allow
+        );
+        assert!(store
+            .grant(PROJECT, &ls, Some("re:(".to_string()), true, "session")
+            .is_err());
+    }
+
+    #[test]
+    fn persists_project_grants_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("permissions.json");
+        let mut store = PermissionStore::default();
+        store
+            .grant(
+                PROJECT,
+                &request("s1", "bash", "npm test"),
+                None,
+                true,
+                "session",
+            )
+            .unwrap();
+        let make = request("s1", "bash", "make");
+        store
+            .grant(PROJECT, &make, Some("make *".to_string()), false, "project")
+            .unwrap();
+        store.save_to(&path).unwrap();
+
+        let loaded = PermissionStore::load_from(&path);
+        assert_eq!(loaded.grants.len(), 1);
+        let grant = &loaded.grants[0].record;
+        assert_eq!(grant.pattern, "make *");
+        assert!(!grant.allow);
+        assert_eq!(grant.session_id, None);
+        let rules = SettingsRules::default();
+        assert_eq!(
+            verdict(&loaded, &rules, "s9", "bash", "make all"),
+            Verdict::Deny
+        );
+    }
+
+    #[test]
+    fn skips_unusable_grant_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(PermissionStore::load_from(&dir.path().join("missing.json"))
+            .grants
+            .is_empty());
+
+        let path = dir.path().join("permissions.json");
+        fs::write(&path, "{not json").unwrap();
+        assert!(PermissionStore::load_from(&path).grants.is_empty());
+
+        let grant = |id: &str, pattern: &str| {
+            json!({
+                "id": id, "projectDir": PROJECT, "tool": "bash", "pattern": pattern,
+                "allow": true, "scope": "project", "createdAt": "2026-01-01T00:00:00Z"
+            })
+        };
+        let file = json!({ "grants": [grant("bad", "re:("), grant("good", "ls *")] });
+        fs::write(&path, file.to_string()).unwrap();
+        let loaded = PermissionStore::load_from(&path);
+        assert_eq!(loaded.grants.len(), 1);
+        assert_eq!(loaded.grants[0].record.id, "good");
+    }
+}
@@ -0,0 +1,567 @@
+//! Line-delimited JSON protocol spoken between the backend and model handlers.
+//!
+//! Commands go to the handler's stdin, events come back on its stdout. Every
+//! stdout line is parsed into a [`HandlerMessage`] before it reaches the webview.
+
+use serde::{Deserialize, Serialize};
+
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RegisterOptions {
+    pub working_directory: String,
+    #[serde(default)]
+    pub skip_git_repo_check: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox_mode: Option<String>,
+}
+
+/// Messages written to a handler's stdin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub(crate) enum HandlerCommand {
+    Hello {
+        protocol_version: u32,
+    },
+    Register {
+        session_id: String,
+        thread_id: Option<String>,
+        options: RegisterOptions,
+    },
+    Run {
+        session_id: String,
+        payload: String,
+    },
+    Interrupt {
+        session_id: String,
+    },
+    Restart {
+        session_id: String,
+    },
+    Permission {
+        session_id: String,
+        request_id: String,
+        allow: bool,
+        scope: String,
+    },
+}
+
+impl HandlerCommand {
+    pub(crate) fn hello() -> Self {
+        HandlerCommand::Hello {
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Reads the `input` given to `send_to_model`: a command, or a run payload
+    /// as the composer sent it before handlers took typed commands.
+    pub(crate) fn from_input(session_id: &str, input: &str) -> Self {
+        serde_json::from_str(input).unwrap_or_else(|_| HandlerCommand::Run {
+            session_id: session_id.to_string(),
+            payload: input.to_string(),
+        })
+    }
+
+    pub(crate) fn to_line(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| format!("Failed to encode command: {}", e))
+    }
+}
+
+/// A command as written to stdin, tagged with the id that the handler echoes
+/// back in its `command:ack` or `command:error` reply.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HandlerRequest<'a> {
+    pub command_id: &'a str,
+    #[serde(flatten)]
+    pub command: &'a HandlerCommand,
+}
+
+impl HandlerRequest<'_> {
+    pub(crate) fn to_line(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| format!("Failed to encode command: {}", e))
+    }
+}
+
+/// Events read from a handler's stdout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all_fields = "camelCase")]
+pub(crate) enum HandlerEvent {
+    #[serde(rename = "hello")]
+    Hello {
+        protocol_version: u32,
+        #[serde(default)]
+        handler: Option<String>,
+    },
+    #[serde(rename = "command:ack")]
+    CommandAck {
+        command_id: String,
+        /// True when the handler is still busy and queued the command.
+        #[serde(default)]
+        queued: bool,
+    },
+    #[serde(rename = "command:error")]
+    CommandError { command_id: String, message: String },
+    #[serde(rename = "thread:update")]
+    ThreadUpdate { thread_id: String },
+    #[serde(rename = "model:update")]
+    ModelUpdate {
+        #[serde(default)]
+        model: Option<String>,
+    },
+    #[serde(rename = "assistant:delta")]
+    AssistantDelta { id: String, chunk: String },
+    #[serde(rename = "assistant:complete")]
+    AssistantComplete { id: String, text: String },
+    #[serde(rename = "thinking")]
+    Thinking {
+        id: String,
+        parent_id: String,
+        sequence: u64,
+        text: String,
+        #[serde(default)]
+        full_text: Option<String>,
+        #[serde(default)]
+        done: bool,
+    },
+    #[serde(rename = "tool:start")]
+    ToolStart {
+        id: String,
+        tool: String,
+        #[serde(default)]
+        args: serde_json::Value,
+    },
+    #[serde(rename = "tool:output")]
+    ToolOutput {
+        id: String,
+        chunk: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stream: Option<String>,
+        #[serde(default)]
+        done: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+    },
+    #[serde(rename = "telemetry:tokens")]
+    TelemetryTokens {
+        tokens_in: u64,
+        tokens_out: u64,
+        #[serde(default)]
+        cached_tokens: u64,
+        #[serde(default)]
+        token_usage: serde_json::Value,
+    },
+    #[serde(rename = "permission:request")]
+    PermissionRequest {
+        id: String,
+        tools: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scope: Option<String>,
+        #[serde(default)]
+        details: serde_json::Value,
+    },
+    /// Emitted by the backend when a handler line fails validation.
+    #[serde(rename = "protocol:error")]
+    ProtocolError { message: String, line: String },
+    /// An event of a type this backend does not know, forwarded as is so
+    /// handlers can add events before the backend learns about them.
+    #[serde(untagged)]
+    Unknown(serde_json::Map<String, serde_json::Value>),
+}
+
+impl HandlerEvent {
+    /// Every `type` the variants above are tagged with.
+    const TYPES: &'static [&'static str] = &[
+        "hello",
+        "command:ack",
+        "command:error",
+        "thread:update",
+        "model:update",
+        "assistant:delta",
+        "assistant:complete",
+        "thinking",
+        "tool:start",
+        "tool:output",
+        "telemetry:tokens",
+        "permission:request",
+        "protocol:error",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HandlerMessage {
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<u64>,
+    #[serde(flatten)]
+    pub event: HandlerEvent,
+}
+
+impl HandlerMessage {
+    /// Parses one stdout line, attributing it to `session_id` when the handler
+    /// left the session out. Invalid lines become `protocol:error` events;
+    /// events of an unknown type are passed through.
+    pub(crate) fn parse(line: &str, session_id: &str) -> Self {
+        let mut message = match serde_json::from_str::<HandlerMessage>(line) {
+            Ok(message) => message,
+            Err(e) => return HandlerMessage::protocol_error(session_id, e.to_string(), line),
+        };
+        if let HandlerEvent::Unknown(fields) = &message.event {
+            // Known types only land here when their fields don't fit.
+            let error = match fields.get("type").and_then(|t| t.as_str()) {
+                None => Some("Event has no type".to_string()),
+                Some(event_type) if HandlerEvent::TYPES.contains(&event_type) => {
+                    Some(format!("Invalid {} event", event_type))
+                }
+                Some(_) => None,
+            };
+            if let Some(error) = error {
+                return HandlerMessage::protocol_error(session_id, error, line);
+            }
+        }
+        if message.session_id.is_none() {
+            message.session_id = Some(session_id.to_string());
+        }
+        message
+    }
+
+    pub(crate) fn protocol_error(session_id: &str, message: String, line: &str) -> Self {
+        HandlerMessage {
+            session_id: Some(session_id.to_string()),
+            ts: Some(chrono::Utc::now().timestamp_millis().max(0) as u64),
+            event: HandlerEvent::ProtocolError {
+                message,
+                line: line.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+
+    /// Checks that `value` encodes to `expected` and decodes back to it.
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T, expected: Value) {
+        assert_eq!(serde_json::to_value(value).unwrap(), expected);
+        let decoded: T = serde_json::from_value(expected.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+    }
+
+    fn message(event: HandlerEvent) -> HandlerMessage {
+        HandlerMessage {
+            session_id: Some("s1".to_string()),
+            ts: Some(7),
+            event,
+        }
+    }
+
+    #[test]
+    fn round_trips_commands() {
+        round_trip(
+            &HandlerCommand::hello(),
+            json!({"type": "hello", "protocolVersion": PROTOCOL_VERSION}),
+        );
+        round_trip(
+            &HandlerCommand::Register {
+                session_id: "s1".to_string(),
+                thread_id: Some("t1".to_string()),
+                options: RegisterOptions {
+                    working_directory: "/work".to_string(),
+                    skip_git_repo_check: true,
+                    sandbox_mode: Some("workspace-write".to_string()),
+                },
+            },
+            json!({
+                "type": "register",
+                "sessionId": "s1",
+                "threadId": "t1",
+                "options": {
+                    "workingDirectory": "/work",
+                    "skipGitRepoCheck": true,
+                    "sandboxMode": "workspace-write",
+                },
+            }),
+        );
+        round_trip(
+            &HandlerCommand::Run {
+                session_id: "s1".to_string(),
+                payload: "{}".to_string(),
+            },
+            json!({"type": "run", "sessionId": "s1", "payload": "{}"}),
+        );
+        round_trip(
+            &HandlerCommand::Interrupt {
+                session_id: "s1".to_string(),
+            },
+            json!({"type": "interrupt", "sessionId": "s1"}),
+        );
+        round_trip(
+            &HandlerCommand::Restart {
+                session_id: "s1".to_string(),
+            },
+            json!({"type": "restart", "sessionId": "s1"}),
+        );
+        round_trip(
+            &HandlerCommand::Permission {
+                session_id: "s1".to_string(),
+                request_id: "r1".to_string(),
+                allow: true,
+                scope: "session".to_string(),
+            },
+            json!({
+                "type": "permission",
+                "sessionId": "s1",
+                "requestId": "r1",
+                "allow": true,
+                "scope": "session",
+            }),
+        );
+    }
+
+    #[test]
+    fn requests_carry_their_command_id() {
+        let command = HandlerCommand::Interrupt {
+            session_id: "s1".to_string(),
+        };
+        let line = HandlerRequest {
+            command_id: "c1",
+            command: &command,
+        }
+        .to_line()
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&line).unwrap(),
+            json!({"commandId": "c1", "type": "interrupt", "sessionId": "s1"})
+        );
+    }
+
+    #[test]
+    fn round_trips_events() {
+        let cases = [
+            (
+                HandlerEvent::Hello {
+                    protocol_version: 1,
+                    handler: Some("codex".to_string()),
+                },
+                json!({"type": "hello", "protocolVersion": 1, "handler": "codex"}),
+            ),
+            (
+                HandlerEvent::CommandAck {
+                    command_id: "c1".to_string(),
+                    queued: true,
+                },
+                json!({"type": "command:ack", "commandId": "c1", "queued": true}),
+            ),
+            (
+                HandlerEvent::CommandError {
+                    command_id: "c2".to_string(),
+                    message: "busy".to_string(),
+                },
+                json!({"type": "command:error", "commandId": "c2", "message": "busy"}),
+            ),
+            (
+                HandlerEvent::ThreadUpdate {
+                    thread_id: "t1".to_string(),
+                },
+                json!({"type": "thread:update", "threadId": "t1"}),
+            ),
+            (
+                HandlerEvent::ModelUpdate {
+                    model: Some("gpt-5".to_string()),
+                },
+                json!({"type": "model:update", "model": "gpt-5"}),
+            ),
+            (
+                HandlerEvent::AssistantDelta {
+                    id: "a1".to_string(),
+                    chunk: "Hel".to_string(),
+                },
+                json!({"type": "assistant:delta", "id": "a1", "chunk": "Hel"}),
+            ),
+            (
+                HandlerEvent::AssistantComplete {
+                    id: "a1".to_string(),
+                    text: "Hello".to_string(),
+                },
+                json!({"type": "assistant:complete", "id": "a1", "text": "Hello"}),
+            ),
+            (
+                HandlerEvent::Thinking {
+                    id: "k1".to_string(),
+                    parent_id: "a1".to_string(),
+                    sequence: 2,
+                    text: "hm".to_string(),
+                    full_text: None,
+                    done: false,
+                },
+                json!({
+                    "type": "thinking",
+                    "id": "k1",
+                    "parentId": "a1",
+                    "sequence": 2,
+                    "text": "hm",
+                    "fullText": null,
+                    "done": false,
+                }),
+            ),
+            (
+                HandlerEvent::ToolStart {
+                    id: "x1".to_string(),
+                    tool: "bash".to_string(),
+                    args: json!({"command": "ls"}),
+                },
+                json!({"type": "tool:start", "id": "x1", "tool": "bash", "args": {"command": "ls"}}),
+            ),
+            (
+                HandlerEvent::ToolOutput {
+                    id: "x1".to_string(),
+                    chunk: "a.txt\n".to_string(),
+                    stream: Some("stdout".to_string()),
+                    done: true,
+                    exit_code: Some(0),
+                },
+                json!({
+                    "type": "tool:output",
+                    "id": "x1",
+                    "chunk": "a.txt\n",
+                    "stream": "stdout",
+                    "done": true,
+                    "exitCode": 0,
+                }),
+            ),
+            (
+                HandlerEvent::TelemetryTokens {
+                    tokens_in: 10,
+                    tokens_out: 5,
+                    cached_tokens: 2,
+                    token_usage: json!({"total": 15}),
+                },
+                json!({
+                    "type": "telemetry:tokens",
+                    "tokensIn": 10,
+                    "tokensOut": 5,
+                    "cachedTokens": 2,
+                    "tokenUsage": {"total": 15},
+                }),
+            ),
+            (
+                HandlerEvent::PermissionRequest {
+                    id: "p1".to_string(),
+                    tools: vec!["bash".to_string()],
+                    scope: Some("session".to_string()),
+                    details: json!({"command": "rm -rf build"}),
+                },
+                json!({
+                    "type": "permission:request",
+                    "id": "p1",
+                    "tools": ["bash"],
+                    "scope": "session",
+                    "details": {"command": "rm -rf build"},
+                }),
+            ),
+            (
+                HandlerEvent::ProtocolError {
+                    message: "bad".to_string(),
+                    line: "{".to_string(),
+                },
+                json!({"type": "protocol:error", "message": "bad", "line": "{"}),
+            ),
+        ];
+        assert_eq!(cases.len(), HandlerEvent::TYPES.len());
+        for (event, mut expected) in cases {
+            assert!(HandlerEvent::TYPES.contains(&expected["type"].as_str().unwrap()));
+            expected["sessionId"] = json!("s1");
+            expected["ts"] = json!(7);
+            round_trip(&message(event), expected);
+        }
+    }
+
+    #[test]
+    fn keeps_command_ids_of_acks_and_errors() {
+        let ack = HandlerMessage::parse(r#"{"type":"command:ack","commandId":"c1"}"#, "s1");
+        assert_eq!(ack.session_id.as_deref(), Some("s1"));
+        match ack.event {
+            HandlerEvent::CommandAck { command_id, queued } => {
+                assert_eq!(command_id, "c1");
+                assert!(!queued);
+            }
+            other => panic!("expected an ack, got {:?}", other),
+        }
+
+        let error = HandlerMessage::parse(
+            r#"{"type":"command:error","commandId":"c2","message":"busy","sessionId":"s2"}"#,
+            "s1",
+        );
+        assert_eq!(error.session_id.as_deref(), Some("s2"));
+        match error.event {
+            HandlerEvent::CommandError {
+                command_id,
+                message,
+            } => {
+                assert_eq!(command_id, "c2");
+                assert_eq!(message, "busy");
+            }
+            other => panic!("expected a command error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn passes_unknown_events_through() {
+        let parsed = HandlerMessage::parse(r#"{"type":"plan:update","steps":["a"],"ts":3}"#, "s1");
+        assert!(matches!(parsed.event, HandlerEvent::Unknown(_)));
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            json!({"type": "plan:update", "steps": ["a"], "sessionId": "s1", "ts": 3})
+        );
+    }
+
+    #[test]
+    fn reports_lines_that_do_not_fit() {
+        for line in [
+            "not json",
+            r#"{"type":"tool:start","id":1}"#,
+            r#"{"type":7}"#,
+            r#"{"id":"a1"}"#,
+        ] {
+            let parsed = HandlerMessage::parse(line, "s1");
+            match parsed.event {
+                HandlerEvent::ProtocolError { line: reported, .. } => assert_eq!(reported, line),
+                other => panic!("expected a protocol error for {}, got {:?}", line, other),
+            }
+        }
+    }
+
+    #[test]
+    fn wraps_raw_input_as_a_run_payload() {
+        let payload = r#"{"prompt":"hi","model":"gpt-5"}"#;
+        match HandlerCommand::from_input("s1", payload) {
+            HandlerCommand::Run {
+                session_id,
+                payload: wrapped,
+            } => {
+                assert_eq!(session_id, "s1");
+                assert_eq!(wrapped, payload);
+            }
+            other => panic!("expected a run, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn passes_commands_through() {
+        let input = r#"{"type":"interrupt","sessionId":"s2"}"#;
+        match HandlerCommand::from_input("s1", input) {
+            HandlerCommand::Interrupt { session_id } => assert_eq!(session_id, "s2"),
+            other => panic!("expected an interrupt, got {:?}", other),
+        }
+    }
+}
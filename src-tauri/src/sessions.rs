@@ -0,0 +1,338 @@
+//! On-disk session records, so a session's project, thread and handler
+//! options survive an app restart. One JSON file per session lives in
+//! `~/.claude-code/sessions/`; records idle for `MAX_IDLE_DAYS`, or beyond
+//! the `MAX_SESSIONS` most recent ones, are removed when sessions are loaded.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::command;
+
+const MAX_SESSIONS: usize = 200;
+const MAX_IDLE_DAYS: i64 = 90;
+
+// Serializes read-modify-write cycles on session files.
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistedSession {
+    pub session_id: String,
+    pub project_dir: String,
+    #[serde(default)]
+    pub thread_id: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub sandbox_mode: Option<String>,
+    #[serde(default)]
+    pub terminal_id: Option<String>,
+    pub created_at: String,
+    pub last_activity: String,
+}
+
+fn sessions_dir() -> Result<PathBuf, String> {
+    let home = dirs::home_dir().ok_or("Failed to get home directory")?;
+    let dir = home.join(".claude-code").join("sessions");
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create sessions directory: {}", e))?;
+    Ok(dir)
+}
+
+fn session_path(dir: &Path, session_id: &str) -> Result<PathBuf, String> {
+    // Session ids come from the webview; keep them from escaping the directory.
+    if session_id.is_empty() || session_id.contains(['/', '\\']) || session_id.contains("..") {
+        return Err(format!("Invalid session id: {}", session_id));
+    }
+    Ok(dir.join(format!("{}.json", session_id)))
+}
+
+fn read(dir: &Path, session_id: &str) -> Result<Option<PersistedSession>, String> {
+    let path = session_path(dir, session_id)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json =
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read session file: {}", e))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse session file: {}", e))
+}
+
+fn write(dir: &Path, session: &PersistedSession) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(session)
+        .map_err(|e| format!("Failed to serialize session: {}", e))?;
+    fs::write(session_path(dir, &session.session_id)?, json)
+        .map_err(|e| format!("Failed to write session file: {}", e))
+}
+
+/// Applies `apply` to the stored record, if there is one, and bumps its
+/// last activity.
+fn update(session_id: &str, apply: impl FnOnce(&mut PersistedSession)) {
+    let _guard = STORE_LOCK.lock().unwrap();
+    let result = sessions_dir().and_then(|dir| match read(&dir, session_id)? {
+        Some(mut session) => {
+            apply(&mut session);
+            session.last_activity = Utc::now().to_rfc3339();
+            write(&dir, &session)
+        }
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        eprintln!("[RUST] Failed to update session {}: {}", session_id, e);
+    }
+}
+
+/// Creates or refreshes the record when a session's handler is started. A
+/// missing thread id keeps the one already stored.
+pub(crate) fn record_start(
+    session_id: &str,
+    project_dir: &str,
+    thread_id: Option<&str>,
+    model: Option<&str>,
+    sandbox_mode: Option<&str>,
+) {
+    let _guard = STORE_LOCK.lock().unwrap();
+    let result = sessions_dir().and_then(|dir| {
+        record_start_in(
+            &dir,
+            session_id,
+            project_dir,
+            thread_id,
+            model,
+            sandbox_mode,
+        )
+    });
+    if let Err(e) = result {
+        eprintln!("[RUST] Failed to persist session {}: {}", session_id, e);
+    }
+}
+
+fn record_start_in(
+    dir: &Path,
+    session_id: &str,
+    project_dir: &str,
+    thread_id: Option<&str>,
+    model: Option<&str>,
+    sandbox_mode: Option<&str>,
+) -> Result<(), String> {
+    let now = Utc::now().to_rfc3339();
+    let mut session = read(dir, session_id)?.unwrap_or_else(|| PersistedSession {
+        session_id: session_id.to_string(),
+        created_at: now.clone(),
+        ..Default::default()
+    });
+    session.project_dir = project_dir.to_string();
+    if let Some(thread_id) = thread_id {
+        session.thread_id = Some(thread_id.to_string());
+    }
+    if let Some(model) = model {
+        session.model = Some(model.to_string());
+    }
+    if let Some(sandbox_mode) = sandbox_mode {
+        session.sandbox_mode = Some(sandbox_mode.to_string());
+    }
+    session.last_activity = now;
+    write(dir, &session)
+}
+
+pub(crate) fn record_thread(session_id: &str, thread_id: &str) {
+    update(session_id, |session| {
+        session.thread_id = Some(thread_id.to_string())
+    });
+}
+
+pub(crate) fn record_terminal(session_id: &str, terminal_id: Option<&str>) {
+    update(session_id, |session| {
+        session.terminal_id = terminal_id.map(str::to_string)
+    });
+}
+
+pub(crate) fn touch(session_id: &str) {
+    update(session_id, |_| {});
+}
+
+/// All stored sessions, most recently active first, after removing expired
+/// ones.
+pub(crate) fn load_all() -> Result<Vec<PersistedSession>, String> {
+    let _guard = STORE_LOCK.lock().unwrap();
+    load_from(&sessions_dir()?, Utc::now())
+}
+
+fn load_from(dir: &Path, now: DateTime<Utc>) -> Result<Vec<PersistedSession>, String> {
+    let mut sessions: Vec<PersistedSession> = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read sessions directory: {}", e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|json| serde_json::from_str(&json).ok())
+        .collect();
+    sessions.sort_by_key(|session| Reverse(last_activity(session)));
+
+    let cutoff = now - Duration::days(MAX_IDLE_DAYS);
+    let keep = sessions
+        .iter()
+        .take(MAX_SESSIONS)
+        .take_while(|session| last_activity(session).is_some_and(|at| at >= cutoff))
+        .count();
+    for expired in sessions.drain(keep..) {
+        let removed = session_path(dir, &expired.session_id).and_then(|path| {
+            fs::remove_file(path).map_err(|e| format!("Failed to delete session file: {}", e))
+        });
+        if let Err(e) = removed {
+            eprintln!(
+                "[RUST] Failed to remove expired session {}: {}",
+                expired.session_id, e
+            );
+        }
+    }
+    Ok(sessions)
+}
+
+fn last_activity(session: &PersistedSession) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&session.last_activity)
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+#[command]
+pub fn list_sessions() -> Result<Vec<PersistedSession>, String> {
+    load_all()
+}
+
+/// Restarts the session's handler and re-registers it with the saved thread
+/// id, so the conversation continues where it left off.
+#[command]
+pub fn resume_session(
+    app: tauri::AppHandle,
+    session_id: String,
+) -> Result<PersistedSession, String> {
+    let session = read(&sessions_dir()?, &session_id)?
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    let running = crate::MODEL_HANDLERS
+        .lock()
+        .unwrap()
+        .get_mut(&session_id, "codex")
+        .is_some();
+    if running {
+        eprintln!("[RUST] Session {} is already running", session_id);
+        return Ok(session);
+    }
+    eprintln!(
+        "[RUST] Resuming session {} in {} (thread {:?})",
+        session_id, session.project_dir, session.thread_id
+    );
+    crate::start_codex(
+        app,
+        session_id.clone(),
+        session.project_dir.clone(),
+        session.thread_id.clone(),
+        session.model.clone(),
+        session.sandbox_mode.clone(),
+    )?;
+    read(&sessions_dir()?, &session_id)?.ok_or_else(|| format!("Session not found: {}", session_id))
+}
+
+#[command]
+pub fn delete_session(session_id: String) -> Result<(), String> {
+    crate::stop_codex(session_id.clone())?;
+    crate::forget_session(&session_id);
+    let _guard = STORE_LOCK.lock().unwrap();
+    let path = session_path(&sessions_dir()?, &session_id)?;
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| format!("Failed to delete session file: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(session_id: &str, last_activity: DateTime<Utc>) -> PersistedSession {
+        PersistedSession {
+            session_id: session_id.to_string(),
+            project_dir: "/work".to_string(),
+            created_at: last_activity.to_rfc3339(),
+            last_activity: last_activity.to_rfc3339(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(sessions: &[PersistedSession]) -> Vec<&str> {
+        sessions.iter().map(|s| s.session_id.as_str()).collect()
+    }
+
+    #[test]
+    fn record_start_merges_into_the_stored_record() {
+        let dir = tempfile::tempdir().unwrap();
+        record_start_in(dir.path(), "s1", "/a", Some("t1"), Some("gpt-5"), None).unwrap();
+        let created_at = read(dir.path(), "s1").unwrap().unwrap().created_at;
+
+        record_start_in(dir.path(), "s1", "/b", None, None, Some("read-only")).unwrap();
+
+        let stored = read(dir.path(), "s1").unwrap().unwrap();
+        assert_eq!(stored.project_dir, "/b");
+        assert_eq!(stored.thread_id.as_deref(), Some("t1"));
+        assert_eq!(stored.model.as_deref(), Some("gpt-5"));
+        assert_eq!(stored.sandbox_mode.as_deref(), Some("read-only"));
+        assert_eq!(stored.created_at, created_at);
+
+        record_start_in(dir.path(), "s1", "/b", Some("t2"), None, None).unwrap();
+        let stored = read(dir.path(), "s1").unwrap().unwrap();
+        assert_eq!(stored.thread_id.as_deref(), Some("t2"));
+    }
+
+    #[test]
+    fn refuses_ids_that_leave_the_directory() {
+        let dir = Path::new("/sessions");
+        for id in ["", "..", "../x", "a/b", "a\\b", "a..b"] {
+            assert!(session_path(dir, id).is_err(), "{} was accepted", id);
+        }
+        assert_eq!(
+            session_path(dir, "session-1").unwrap(),
+            dir.join("session-1.json")
+        );
+    }
+
+    #[test]
+    fn loads_most_recent_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        for (id, hours) in [("old", 5), ("new", 1), ("mid", 3)] {
+            write(dir.path(), &session(id, now - Duration::hours(hours))).unwrap();
+        }
+        fs::write(dir.path().join("broken.json"), "{").unwrap();
+        fs::write(dir.path().join("notes.txt"), "{}").unwrap();
+
+        let sessions = load_from(dir.path(), now).unwrap();
+
+        assert_eq!(ids(&sessions), ["new", "mid", "old"]);
+    }
+
+    #[test]
+    fn removes_idle_and_excess_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        for n in 0..MAX_SESSIONS {
+            let id = format!("s{}", n);
+            write(dir.path(), &session(&id, now - Duration::minutes(n as i64))).unwrap();
+        }
+        write(dir.path(), &session("excess", now - Duration::days(1))).unwrap();
+        write(
+            dir.path(),
+            &session("idle", now - Duration::days(MAX_IDLE_DAYS + 1)),
+        )
+        .unwrap();
+
+        let sessions = load_from(dir.path(), now).unwrap();
+
+        assert_eq!(sessions.len(), MAX_SESSIONS);
+        assert!(!ids(&sessions).contains(&"excess"));
+        assert!(!dir.path().join("excess.json").exists());
+        assert!(!dir.path().join("idle.json").exists());
+        assert!(dir.path().join("s0.json").exists());
+    }
+}
@@ -11,6 +11,8 @@ type CheckpointMeta = {
   file_count: number
   git_branch?: string | null
   git_commit?: string | null
+  tags?: string[]
+  parent_id?: string | null
 }
 
 export function CheckpointsPanel() {
@@ -39,12 +41,32 @@ export function CheckpointsPanel() {
 
   const restore = async (id: string) => {
     try {
-      await invoke('restore_checkpoint', { sessionId: sessionId, checkpointId: id })
+      const conflicts = await invoke<string[]>('check_checkpoint_conflicts', { sessionId: sessionId, checkpointId: id })
+      let onConflict: string | undefined
+      if (conflicts.length) {
+        const proceed = window.confirm(
+          `These files were edited after the checkpoint:\n${conflicts.join('\n')}\n\nRestore anyway? Your versions will be kept as .orig files.`
+        )
+        if (!proceed) return
+        onConflict = 'backup'
+      }
+      await invoke('restore_checkpoint', { sessionId: sessionId, checkpointId: id, onConflict })
     } catch (e) {
       console.error('Failed to restore checkpoint', e)
     }
   }
 
+  const saveNamed = async () => {
+    const name = window.prompt('Checkpoint name')
+    if (!name || !name.trim()) return
+    try {
+      const created = await invoke<CheckpointMeta>('create_checkpoint', { sessionId: sessionId, name })
+      setItems(prev => [created, ...prev])
+    } catch (e: any) {
+      setError(String(e))
+    }
+  }
+
   const remove = async (id: string) => {
     try {
       await invoke('delete_checkpoint', { sessionId: sessionId, checkpointId: id })
@@ -59,6 +81,7 @@ export function CheckpointsPanel() {
       <div style={{ display: 'flex', justifyContent: 'space-between', alignItems: 'center', marginBottom: 8 }}>
         <div style={{ fontWeight: 600 }}>Checkpoints</div>
         <div style={{ display: 'flex', gap: 8 }}>
+          <button className="btn btn--ghost" onClick={saveNamed} disabled={loading}>Save checkpoint</button>
           <button className="btn btn--ghost" onClick={refresh} disabled={loading}>Refresh</button>
         </div>
       </div>
@@ -75,10 +98,11 @@ export function CheckpointsPanel() {
             {items.map(cp => (
               <div key={cp.id} style={{ display: 'flex', alignItems: 'center', gap: 12, border: '1px solid var(--border)', borderRadius: 8, padding: 8 }}>
                 <div style={{ flex: 1 }}>
-                  <div style={{ fontWeight: 600 }}>{cp.trigger || cp.name || cp.id}</div>
+                  <div style={{ fontWeight: 600 }}>{cp.name || cp.trigger || cp.id}</div>
                   <div style={{ fontSize: 12, color: 'var(--text-muted)' }}>
                     {new Date(cp.timestamp).toLocaleString()} • {cp.file_count} file{cp.file_count === 1 ? '' : 's'}
                     {cp.git_branch ? ` • ${cp.git_branch}@${(cp.git_commit || '').slice(0,7)}` : ''}
+                    {cp.tags && cp.tags.length ? ` • ${cp.tags.join(', ')}` : ''}
                   </div>
                 </div>
                 <button className="btn btn--accent" onClick={() => restore(cp.id)}>Restore</button>
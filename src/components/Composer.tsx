@@ -1006,6 +1006,13 @@ export function Composer() {
       });
     } catch (err) {
       console.error("Failed to invoke Codex:", err);
+      pushEvent({
+        id: String(Date.now()),
+        type: "message",
+        role: "assistant",
+        text: `⚠️ ${typeof err === "string" ? err : String(err)}`,
+        ts: Date.now(),
+      } as any);
       setStreaming(false);
       streamEnabledRef.current = false;
     }
@@ -44,6 +44,18 @@ export interface ClaudeSettings {
     url?: string
     headers?: Record<string, string>
   }>
+
+  // Model handlers spawned per session (keyed by model name)
+  modelHandlers?: Record<string, {
+    runtime?: 'node' | 'python' | 'native' | 'openai'
+    script?: string
+    args?: string[]
+    env?: Record<string, string>
+    // openai runtime only
+    baseUrl?: string
+    model?: string
+    apiKeyEnv?: string
+  }>
   
   // UI/UX settings
   theme?: 'light' | 'dark' | 'system' | 'retro'